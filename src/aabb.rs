@@ -1,19 +1,17 @@
-use std::mem::swap;
 use crate::{Point3, Ray};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug)]
 pub struct AABB {
     minimum: Point3,
     maximum: Point3,
 }
 
-unsafe impl Sync for AABB {}
-
 impl AABB {
     pub fn new(a: &Point3, b: &Point3) -> AABB {
         AABB {
-            minimum: a.clone(),
-            maximum: b.clone()
+            minimum: *a,
+            maximum: *b
         }
     }
 
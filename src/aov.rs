@@ -0,0 +1,218 @@
+use std::fmt;
+use std::path::Path;
+use image::ImageError;
+use crate::Color;
+use crate::framebuffer::FrameBuffer;
+
+// Arbitrary output variables: images rendered alongside the beauty pass for
+// compositing and denoising. Everything except the ids is taken at the first
+// surface a camera ray hits and filtered like the beauty image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    // Reflectance of the first surface
+    Albedo,
+    // World space shading normal
+    Normal,
+    // Distance from the camera
+    Depth,
+    // World space hit point
+    Position,
+    // Ids of the nearest sample, not filtered since averaged ids mean nothing
+    ObjectId,
+    MaterialId,
+    // Light reaching the camera after one bounce off the first surface is
+    // direct, after more bounces indirect. Diffuse and specular tell the first
+    // surface's scattering apart; volumes only appear in the beauty image.
+    DiffuseDirect,
+    DiffuseIndirect,
+    SpecularDirect,
+    SpecularIndirect,
+    // Light emitted by the first surface, or the sky when nothing is hit
+    Emission
+}
+
+pub const AOV_COUNT: usize = 11;
+
+impl Aov {
+    pub const ALL: [Aov; AOV_COUNT] = [
+        Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Position, Aov::ObjectId, Aov::MaterialId,
+        Aov::DiffuseDirect, Aov::DiffuseIndirect, Aov::SpecularDirect, Aov::SpecularIndirect, Aov::Emission
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DiffuseDirect => "diffuse_direct",
+            Aov::DiffuseIndirect => "diffuse_indirect",
+            Aov::SpecularDirect => "specular_direct",
+            Aov::SpecularIndirect => "specular_indirect",
+            Aov::Emission => "emission"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    // Channel names in EXR files; single channel outputs keep their value in x
+    fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"]
+        }
+    }
+
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+// AOV values of one camera sample. `primary` is set for the sample's camera
+// ray, whose first bounce is split into direct and indirect light.
+#[derive(Debug, Clone)]
+pub struct AovSample {
+    values: [Color; AOV_COUNT],
+    pub(crate) primary: bool
+}
+
+impl AovSample {
+    pub fn new_empty() -> AovSample {
+        AovSample { values: [Color::new_empty(); AOV_COUNT], primary: false }
+    }
+
+    pub fn new_primary() -> AovSample {
+        AovSample { primary: true, ..AovSample::new_empty() }
+    }
+
+    pub fn get(&self, aov: Aov) -> Color {
+        self.values[aov as usize]
+    }
+
+    pub fn set(&mut self, aov: Aov, value: Color) {
+        self.values[aov as usize] = value;
+    }
+
+    pub fn set_scalar(&mut self, aov: Aov, value: f64) {
+        self.values[aov as usize] = Color::new(value, 0.0, 0.0);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AovOutput {
+    // One 8 bit PNG per AOV next to the beauty image, for viewing
+    Images,
+    // All AOVs as float layers of one EXR with the beauty image
+    MultiLayerExr
+}
+
+#[derive(Debug)]
+pub enum AovError {
+    Image(ImageError),
+    Exr(exr::error::Error)
+}
+
+impl fmt::Display for AovError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AovError::Image(e) => write!(f, "Error writing AOV image: {}", e),
+            AovError::Exr(e) => write!(f, "Error writing EXR file: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for AovError {}
+
+impl From<ImageError> for AovError {
+    fn from(e: ImageError) -> Self {
+        AovError::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for AovError {
+    fn from(e: exr::error::Error) -> Self {
+        AovError::Exr(e)
+    }
+}
+
+// Writes the AOVs of `buffer` next to `path`: output.png gives
+// output_albedo.png, ... or a single output.exr
+pub fn write_aovs<P: AsRef<Path>>(buffer: &FrameBuffer, output: AovOutput, path: P) -> Result<(), AovError> {
+    let path = path.as_ref();
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+
+    match output {
+        AovOutput::Images => {
+            for &aov in buffer.aovs() {
+                buffer.aov_image(aov).save(path.with_file_name(format!("{}_{}.png", stem, aov.name())))?;
+            }
+        }
+        AovOutput::MultiLayerExr => write_exr(buffer, &path.with_extension("exr"))?
+    }
+
+    Ok(())
+}
+
+// Beauty image as the unnamed layer and one named layer per AOV, all 32 bit float
+fn write_exr(buffer: &FrameBuffer, path: &Path) -> Result<(), exr::error::Error> {
+    use exr::prelude::*;
+
+    let (x0, y0, x1, y1) = buffer.bounds();
+    let size = ((x1 - x0) as usize, (y1 - y0) as usize);
+
+    let plane = |value: &dyn Fn(u32, u32) -> f64| -> FlatSamples {
+        FlatSamples::F32((y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))).map(|(x, y)| value(x, y) as f32).collect())
+    };
+
+    let rgb = |value: &dyn Fn(u32, u32) -> Color, names: &[&str]| -> Vec<AnyChannel<FlatSamples>> {
+        names.iter().enumerate().map(|(c, name)| {
+            AnyChannel::new(*name, plane(&|x, y| value(x, y).e[c]))
+        }).collect()
+    };
+
+    let mut layers = vec![Layer::new(size, LayerAttributes::named(""), Encoding::FAST_LOSSLESS,
+                                     AnyChannels::sort(rgb(&|x, y| buffer.color(x, y), &["R", "G", "B"]).into()))];
+
+    for &aov in buffer.aovs() {
+        let channels = rgb(&|x, y| buffer.aov(x, y, aov), aov.channels());
+        layers.push(Layer::new(size, LayerAttributes::named(aov.name()), Encoding::FAST_LOSSLESS,
+                               AnyChannels::sort(channels.into())));
+    }
+
+    let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), layers);
+    image.write().to_file(path)
+}
+
+// Display colour of an AOV value for 8 bit previews. `depth_range` scales depth.
+pub(crate) fn preview_color(aov: Aov, value: &Color, depth_range: f64) -> Color {
+    match aov {
+        Aov::Normal => *value * 0.5 + 0.5,
+        Aov::Depth => {
+            let d = 1.0 - value.x() / depth_range.max(1e-6);
+            Color::new(d, d, d)
+        }
+        Aov::Position => Color::new(
+            value.x().rem_euclid(1.0),
+            value.y().rem_euclid(1.0),
+            value.z().rem_euclid(1.0)),
+        Aov::ObjectId | Aov::MaterialId => id_color(value.x() as u32),
+        // Colours get the same gamma as the beauty image
+        _ => Color::new(value.x().max(0.0).sqrt(), value.y().max(0.0).sqrt(), value.z().max(0.0).sqrt())
+    }
+}
+
+// Arbitrary but distinct colour for an id, black for none
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::new_empty();
+    }
+
+    let h = id.wrapping_mul(0x9e3779b9);
+    Color::new((h & 0xff) as f64 / 255.0, ((h >> 8) & 0xff) as f64 / 255.0, ((h >> 16) & 0xff) as f64 / 255.0)
+}
@@ -3,16 +3,23 @@ use std::sync::Arc;
 use rand::Rng;
 use crate::aabb::AABB;
 use crate::{HitList, Hittable};
-use crate::hittable::HitRecord;
+use crate::hittable::{object_id, HitRecord};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 
 type ChildNode = Option<Arc<dyn Hittable>>;
+// Object with its position in the list the BVH was built from
+type IndexedObject = (usize, Arc<dyn Hittable>);
 
 #[derive(Clone)]
 pub struct BvhNode {
     left: ChildNode,
     right: ChildNode,
-    bounding_box: AABB
+    bounding_box: AABB,
+    // Children are scene objects rather than further nodes
+    leaves: bool,
+    // Positions of leaf children in the source list, for their object ids
+    indices: (usize, usize)
 }
 
 impl BvhNode {
@@ -20,29 +27,32 @@ impl BvhNode {
         BvhNode {
             left: None,
             right: None,
-            bounding_box: AABB::new_empty()
+            bounding_box: AABB::new_empty(),
+            leaves: false,
+            indices: (0, 0)
         }
     }
 
     pub fn new_from_hitlist(list: &HitList, time0: f64, time1: f64) -> BvhNode {
-        BvhNode::new(&list.objects, 0, list.objects.len(), time0, time1)
+        let objects: Vec<IndexedObject> = list.objects.iter().cloned().enumerate().collect();
+        BvhNode::new(&objects, 0, objects.len(), time0, time1)
     }
 
-    pub fn new(src_objects: &Vec<Arc<dyn Hittable>>, start: usize, end: usize, time0: f64, time1: f64) -> BvhNode {
-        let mut objects = src_objects.clone();
+    fn new(src_objects: &[IndexedObject], start: usize, end: usize, time0: f64, time1: f64) -> BvhNode {
+        let mut objects = src_objects.to_vec();
 
         let axis = rand::thread_rng().gen_range(0usize..3usize);
-        let comparator = |a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| -> std::cmp::Ordering {
+        let comparator = |(_, a): &IndexedObject, (_, b): &IndexedObject| -> std::cmp::Ordering {
             let box_compare = |a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize| -> Ordering {
                 let mut box_a = AABB::new_empty();
                 let mut box_b = AABB::new_empty();
 
-                if !a.bounding_box(0.0, 0.0, &mut box_a) ||
-                    !b.bounding_box(0.0, 0.0, &mut box_b) {
+                if !a.bounding_box(time0, time1, &mut box_a) ||
+                    !b.bounding_box(time0, time1, &mut box_b) {
                     eprintln!("No bounding box in BvhNode constructor");
                 }
 
-                return if box_a.min().e[axis] < box_b.min().e[axis] {
+                if box_a.min().e[axis] < box_b.min().e[axis] {
                     Ordering::Less
                 } else {
                     Ordering::Greater
@@ -61,12 +71,16 @@ impl BvhNode {
         objects[start..end].sort_by(comparator);
 
         if object_span == 1 {
-            new.left = Some(objects[start].clone());
+            new.left = Some(objects[start].1.clone());
             new.right = new.left.clone();
+            new.leaves = true;
+            new.indices = (objects[start].0, objects[start].0);
         }
         else if object_span == 2 {
-            new.left = Some(objects[start].clone());
-            new.right = Some(objects[start + 1].clone());
+            new.left = Some(objects[start].1.clone());
+            new.right = Some(objects[start + 1].1.clone());
+            new.leaves = true;
+            new.indices = (objects[start].0, objects[start + 1].0);
         }
         else {
             let mid = start + (object_span / 2);
@@ -99,20 +113,38 @@ impl BvhNode {
 
         new
     }
+
+    // Hits a child, giving leaf objects their id from their source position.
+    // A miss leaves `rec` as it was.
+    fn hit_child(&self, (child, index): (&Arc<dyn Hittable>, usize), ray: &Ray, t_min: f64, t_max: f64,
+                 rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        if !self.leaves {
+            return child.hit(ray, t_min, t_max, rec, sampler);
+        }
+
+        let previous = rec.object_id;
+        rec.object_id = 0;
+        if child.hit(ray, t_min, t_max, rec, sampler) {
+            rec.object_id = object_id(index, rec.object_id);
+            true
+        } else {
+            rec.object_id = previous;
+            false
+        }
+    }
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
         if !self.bounding_box.hit(ray, t_min, t_max) {
             return false;
         }
 
-        let left_hit = self.left.as_ref().unwrap().hit(ray, t_min, t_max, rec);
-        let right_hit = if left_hit {
-            self.right.as_ref().unwrap().hit(ray, t_min, rec.t, rec)
-        } else {
-            self.right.as_ref().unwrap().hit(ray, t_min, t_max, rec)
-        };
+        let left = self.left.as_ref().unwrap();
+        let right = self.right.as_ref().unwrap();
+
+        let left_hit = self.hit_child((left, self.indices.0), ray, t_min, t_max, rec, sampler);
+        let right_hit = self.hit_child((right, self.indices.1), ray, t_min, if left_hit { rec.t } else { t_max }, rec, sampler);
 
         left_hit || right_hit
     }
@@ -121,4 +153,25 @@ impl Hittable for BvhNode {
         *output_box = self.bounding_box;
         true
     }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        if !self.bounding_box.hit(ray, t_min, t_max) {
+            return 1.0;
+        }
+
+        let left = self.left.as_ref().unwrap();
+        let right = self.right.as_ref().unwrap();
+
+        // Single children are stored on both sides
+        if Arc::ptr_eq(left, right) {
+            return left.transmittance(ray, t_min, t_max, sampler);
+        }
+
+        let transmittance = left.transmittance(ray, t_min, t_max, sampler);
+        if transmittance <= 0.0 {
+            return 0.0;
+        }
+
+        transmittance * right.transmittance(ray, t_min, t_max, sampler)
+    }
 }
\ No newline at end of file
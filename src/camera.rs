@@ -1,7 +1,122 @@
-use crate::{Point3, Ray, Vec3};
+use std::f64::consts::PI;
+use crate::{HitRecord, Hittable, Point3, Ray, Vec3};
+use crate::camera_path::CameraKey;
+use crate::sampler::Sampler;
+
+// Where a physical camera focuses
+#[derive(Debug, Copy, Clone)]
+pub enum Focus {
+    // Distance along the viewing direction, in scene units
+    Distance(f64),
+    // Focus on the plane through this point
+    Point(Point3)
+}
+
+// Thin lens described the way a photographer would. Lengths on the camera side
+// are in millimetres; scene units are taken to be metres.
+#[derive(Debug, Copy, Clone)]
+pub struct Lens {
+    pub focal_length: f64,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub f_number: f64,
+    pub focus: Focus,
+    // Number of aperture blades, fewer than three gives a round aperture
+    pub blades: u32,
+    // Rotation of the blade polygon in degrees
+    pub blade_rotation: f64,
+    // Strength of the cat-eye clipping of off-axis bokeh, zero disables it
+    pub cat_eye: f64
+}
+
+impl Lens {
+    // Full frame sensor and a round aperture
+    pub fn new_with(focal_length: f64, f_number: f64, focus: Focus) -> Lens {
+        Lens {
+            focal_length,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_number,
+            focus,
+            blades: 0,
+            blade_rotation: 0.0,
+            cat_eye: 0.0
+        }
+    }
+}
+
+// 50mm at f/2.8
+impl Default for Lens {
+    fn default() -> Self {
+        Lens::new_with(50.0, 2.8, Focus::Distance(10.0))
+    }
+}
+
+// Maps screen positions (s, t) in [0, 1], with t pointing up, to primary rays.
+// Positions a projection does not cover, like the corners outside a fisheye's
+// image circle, give no ray. Lens and time samples come from `sampler`.
+pub trait Camera: Send + Sync {
+    fn ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+
+    // Angle subtended by one pixel of an image `image_height` pixels tall, used
+    // to size ray cones for texture filtering. Zero disables filtering.
+    fn pixel_spread(&self, _image_height: u32) -> f64 {
+        0.0
+    }
+
+    // Position, target and vertical field of view, for cameras that can be
+    // described that way, so the viewer can start navigating from them
+    fn view(&self) -> Option<CameraKey> {
+        None
+    }
+}
+
+// Shutter in front of any camera, spreading its rays uniformly over the time
+// it is open. Cameras on their own take every ray at time zero.
+pub struct Shutter<C: Camera> {
+    camera: C,
+    time0: f64,
+    time1: f64
+}
+
+impl<C: Camera> Shutter<C> {
+    pub fn new_with(camera: C, time0: f64, time1: f64) -> Shutter<C> {
+        Shutter { camera, time0, time1 }
+    }
+
+    // Shutter that only opens for the instant `time`, for frames of an animation
+    pub fn new_instant(camera: C, time: f64) -> Shutter<C> {
+        Shutter::new_with(camera, time, time)
+    }
+}
+
+impl<C: Camera> Camera for Shutter<C> {
+    fn ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let ray = self.camera.ray(s, t, sampler)?;
+        let u = sampler.get_1d();
+        Some(ray.with_time(self.time0 + (self.time1 - self.time0).max(0.0) * u))
+    }
+
+    fn pixel_spread(&self, image_height: u32) -> f64 {
+        self.camera.pixel_spread(image_height)
+    }
+
+    fn view(&self) -> Option<CameraKey> {
+        self.camera.view().map(|key| CameraKey { time: self.time0, ..key })
+    }
+}
+
+// Right, up and backwards vectors of a camera looking from `look_from` to `look_at`
+pub(crate) fn look_basis(look_from: &Point3, look_at: &Point3, vup: &Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (*look_from - *look_at).normalized();
+    let u = vup.cross(&w).normalized();
+    let v = w.cross(&u);
+
+    (u, v, w)
+}
 
 #[derive(Debug)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
@@ -9,36 +124,158 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    lens_radius: f64
+    lens_radius: f64,
+    viewport_width: f64,
+    viewport_height: f64,
+    focus_dist: f64,
+    blades: u32,
+    blade_rotation: f64,
+    cat_eye: f64
 }
 
-impl Camera {
-    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, fov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64) -> Camera {
+impl PerspectiveCamera {
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, fov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64) -> PerspectiveCamera {
         let theta = fov.to_radians();
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (look_from - look_at).normalized();
-        let u = vup.cross(&w).normalized();
-        let v = w.cross(&u);
+        let (u, v, w) = look_basis(&look_from, &look_at, &vup);
 
-        let mut cam = Camera {
+        let mut cam = PerspectiveCamera {
             origin: look_from,
-            horizontal: u * viewport_width * focus_dist,
-            vertical: v * viewport_height * focus_dist,
+            horizontal: Vec3::new_empty(),
+            vertical: Vec3::new_empty(),
             lower_left_corner: Point3::new_empty(),
             u, v, w,
-            lens_radius: aperture / 2.0
+            lens_radius: aperture / 2.0,
+            viewport_width,
+            viewport_height,
+            focus_dist,
+            blades: 0,
+            blade_rotation: 0.0,
+            cat_eye: 0.0,
+        };
+
+        cam.update_focus_plane();
+        cam
+    }
+
+    // Camera whose field of view and depth of field follow from a real lens.
+    // The sensor is cropped to `aspect_ratio`, keeping as much of it as fits.
+    pub fn new_physical(look_from: Point3, look_at: Point3, vup: Vec3, lens: Lens, aspect_ratio: f64) -> PerspectiveCamera {
+        let sensor_height = if aspect_ratio >= lens.sensor_width / lens.sensor_height {
+            lens.sensor_width / aspect_ratio
+        } else {
+            lens.sensor_height
+        };
+        let fov = 2.0 * (sensor_height / (2.0 * lens.focal_length)).atan().to_degrees();
+
+        // Entrance pupil diameter is the focal length over the f-number
+        let aperture = lens.focal_length / lens.f_number / 1000.0;
+
+        let view_dir = (look_at - look_from).normalized();
+        let focus_dist = match lens.focus {
+            Focus::Distance(d) => d,
+            Focus::Point(p) => (p - look_from).dot(&view_dir)
         };
 
-        cam.lower_left_corner = cam.origin - cam.horizontal / 2.0 - cam.vertical / 2.0 - w * focus_dist;
+        let mut cam = PerspectiveCamera::new(look_from, look_at, vup, fov, aspect_ratio, aperture, focus_dist.max(1e-3));
+        cam.blades = lens.blades;
+        cam.blade_rotation = lens.blade_rotation.to_radians();
+        cam.cat_eye = lens.cat_eye;
         cam
     }
 
-    pub fn ray(&self, s: f64, t: f64) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
-        let offset =  self.u * rd.x() + self.v * rd.y();
-        Ray::new(self.origin + offset, self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset)
+    fn update_focus_plane(&mut self) {
+        self.horizontal = self.u * self.viewport_width * self.focus_dist;
+        self.vertical = self.v * self.viewport_height * self.focus_dist;
+        self.lower_left_corner = self.origin - self.horizontal / 2.0 - self.vertical / 2.0 - self.w * self.focus_dist;
+    }
+
+    pub fn set_focus_distance(&mut self, focus_dist: f64) {
+        self.focus_dist = focus_dist.max(1e-3);
+        self.update_focus_plane();
+    }
+
+    // Focuses on whatever `world` shows at screen position (s, t), like tapping
+    // the screen of a phone camera. Returns false and keeps the current focus
+    // when nothing is there.
+    pub fn autofocus(&mut self, world: &dyn Hittable, s: f64, t: f64, sampler: &mut dyn Sampler) -> bool {
+        let target = self.lower_left_corner + self.horizontal * s + self.vertical * t;
+        let ray = Ray::new(self.origin, target - self.origin);
+
+        let mut rec = HitRecord::default();
+        if !world.hit(&ray, 0.001, f64::INFINITY, &mut rec, sampler) {
+            return false;
+        }
+
+        self.set_focus_distance((rec.p - self.origin).dot(&-self.w));
+        true
+    }
+
+    // Point on the unit aperture, shaped by the blades
+    fn sample_aperture(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        if self.blades < 3 {
+            return Vec3::in_unit_disk_from((u, v));
+        }
+
+        // Pick one of the triangles fanning out from the centre with the first
+        // number, reusing what is left of it, then a uniform point inside it
+        let wedge = 2.0 * PI / self.blades as f64;
+        let scaled = u * self.blades as f64;
+        let blade = scaled.floor().min(self.blades as f64 - 1.0);
+        let a0 = self.blade_rotation + blade * wedge;
+        let a1 = a0 + wedge;
+
+        let (mut x, mut y) = (scaled - blade, v);
+        if x + y > 1.0 {
+            x = 1.0 - x;
+            y = 1.0 - y;
+        }
+
+        Vec3::new(x * a0.cos() + y * a1.cos(), x * a0.sin() + y * a1.sin(), 0.0)
+    }
+
+}
+
+impl Camera for PerspectiveCamera {
+    fn ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let mut rd = self.sample_aperture(sampler);
+
+        // Cat-eye: off-axis the lens barrel clips the aperture with a second disk,
+        // offset further the further the pixel is from the image centre, which
+        // squashes bokeh into lemon shapes
+        if self.cat_eye > 0.0 {
+            let shift = Vec3::new(s - 0.5, t - 0.5, 0.0) * (2.0 * self.cat_eye);
+            for _ in 0..16 {
+                if (rd - shift).length_squared() <= 1.0 {
+                    break;
+                }
+                rd = self.sample_aperture(sampler);
+            }
+        }
+
+        let rd = rd * self.lens_radius;
+        let offset = self.u * rd.x() + self.v * rd.y();
+
+        Some(Ray::new(
+            self.origin + offset,
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset
+        ))
+    }
+
+    fn pixel_spread(&self, image_height: u32) -> f64 {
+        2.0 * (self.viewport_height / 2.0).atan() / image_height.max(1) as f64
+    }
+
+    fn view(&self) -> Option<CameraKey> {
+        Some(CameraKey::new_with(
+            0.0,
+            self.origin,
+            self.origin - self.w * self.focus_dist,
+            2.0 * (self.viewport_height / 2.0).atan().to_degrees(),
+            self.focus_dist))
     }
-}
\ No newline at end of file
+}
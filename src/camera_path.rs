@@ -0,0 +1,368 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use crate::{Point3, Vec3};
+use crate::camera::{PerspectiveCamera, Shutter};
+
+#[derive(Debug)]
+pub enum PathError {
+    Io(std::io::Error),
+    Format(String)
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Io(e) => write!(f, "I/O error reading camera path: {}", e),
+            PathError::Format(msg) => write!(f, "Invalid camera path: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl From<std::io::Error> for PathError {
+    fn from(e: std::io::Error) -> Self {
+        PathError::Io(e)
+    }
+}
+
+// Camera state at one point in time. `fov` is vertical, in degrees.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraKey {
+    pub time: f64,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub fov: f64,
+    pub focus_dist: f64
+}
+
+impl CameraKey {
+    pub fn new_with(time: f64, look_from: Point3, look_at: Point3, fov: f64, focus_dist: f64) -> CameraKey {
+        CameraKey { time, look_from, look_at, fov, focus_dist }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // Smooth curve through every key, using the neighbouring keys as tangents
+    CatmullRom
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "catmull-rom" => Some(Interpolation::CatmullRom),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CameraMotion {
+    // Keys sorted by time; the camera holds still before the first and after the last
+    Keyframes {
+        keys: Vec<CameraKey>,
+        interpolation: Interpolation
+    },
+    // One full orbit around `center` between `time0` and `time1`, starting on
+    // the -z side and looking at `center`
+    Turntable {
+        center: Point3,
+        radius: f64,
+        height: f64,
+        fov: f64,
+        time0: f64,
+        time1: f64
+    }
+}
+
+// Animated perspective camera. Times are scene times, so a path and animated
+// objects using the same range move together.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    pub motion: CameraMotion,
+    pub vup: Vec3,
+    pub aspect_ratio: f64,
+    pub aperture: f64
+}
+
+impl CameraPath {
+    pub fn new_keyframes(keys: Vec<CameraKey>, interpolation: Interpolation, aspect_ratio: f64) -> CameraPath {
+        let mut keys = keys;
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        CameraPath {
+            motion: CameraMotion::Keyframes { keys, interpolation },
+            vup: Vec3::new(0, 1, 0),
+            aspect_ratio,
+            aperture: 0.0
+        }
+    }
+
+    // Keys from a text file with one key per line:
+    //
+    //   time  from_x from_y from_z  at_x at_y at_z  fov  [focus_dist]
+    //
+    // The focus distance defaults to the distance to the point looked at. A line
+    // holding just `linear` or `catmull-rom` picks the interpolation, Catmull-Rom
+    // by default, and everything after a # is a comment.
+    pub fn load_keyframes<P: AsRef<Path>>(path: P, aspect_ratio: f64) -> Result<CameraPath, PathError> {
+        let text = std::fs::read_to_string(path)?;
+        CameraPath::parse_keyframes(&text, aspect_ratio)
+    }
+
+    fn parse_keyframes(text: &str, aspect_ratio: f64) -> Result<CameraPath, PathError> {
+        let mut keys = Vec::new();
+        let mut interpolation = Interpolation::CatmullRom;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(mode) = Interpolation::from_name(line) {
+                interpolation = mode;
+                continue;
+            }
+
+            let values: Vec<f64> = line.split_whitespace()
+                .map(|v| v.parse().map_err(|_| PathError::Format(format!("line {}: {} is not a number", number + 1, v))))
+                .collect::<Result<_, _>>()?;
+            if values.len() != 8 && values.len() != 9 {
+                return Err(PathError::Format(format!("line {}: expected 8 or 9 values but got {}", number + 1, values.len())));
+            }
+
+            let look_from = Point3::new(values[1], values[2], values[3]);
+            let look_at = Point3::new(values[4], values[5], values[6]);
+            let focus_dist = values.get(8).copied().unwrap_or_else(|| (look_from - look_at).length());
+            keys.push(CameraKey::new_with(values[0], look_from, look_at, values[7], focus_dist));
+        }
+
+        if keys.is_empty() {
+            return Err(PathError::Format(String::from("no keys")));
+        }
+
+        Ok(CameraPath::new_keyframes(keys, interpolation, aspect_ratio))
+    }
+
+    pub fn new_turntable(center: Point3, radius: f64, height: f64, fov: f64, aspect_ratio: f64) -> CameraPath {
+        CameraPath {
+            motion: CameraMotion::Turntable { center, radius, height, fov, time0: 0.0, time1: 1.0 },
+            vup: Vec3::new(0, 1, 0),
+            aspect_ratio,
+            aperture: 0.0
+        }
+    }
+
+    // Camera holding still at `key` over the scene times 0 to 1, so a sequence
+    // only shows what moves in the scene
+    pub fn new_still(key: CameraKey, aspect_ratio: f64) -> CameraPath {
+        let keys = vec![CameraKey { time: 0.0, ..key }, CameraKey { time: 1.0, ..key }];
+        CameraPath::new_keyframes(keys, Interpolation::Linear, aspect_ratio)
+    }
+
+    // Turntable around the point `key` looks at, keeping its distance and height
+    pub fn new_orbit(key: &CameraKey, aspect_ratio: f64) -> CameraPath {
+        let offset = key.look_from - key.look_at;
+        let radius = (offset.x() * offset.x() + offset.z() * offset.z()).sqrt();
+
+        CameraPath::new_turntable(key.look_at, radius, offset.y(), key.fov, aspect_ratio)
+    }
+
+    // Time range covered by the path
+    pub fn time_range(&self) -> (f64, f64) {
+        match &self.motion {
+            CameraMotion::Keyframes { keys, .. } => match (keys.first(), keys.last()) {
+                (Some(first), Some(last)) => (first.time, last.time),
+                _ => (0.0, 0.0)
+            },
+            CameraMotion::Turntable { time0, time1, .. } => (*time0, *time1)
+        }
+    }
+
+    // Time of frame `frame` out of `frame_count`. Keyframed paths include both
+    // ends; turntables leave out the end, which would repeat the first frame
+    // when the sequence loops.
+    pub fn frame_time(&self, frame: u32, frame_count: u32) -> f64 {
+        let (time0, time1) = self.time_range();
+        let steps = match self.motion {
+            CameraMotion::Keyframes { .. } => frame_count.saturating_sub(1),
+            CameraMotion::Turntable { .. } => frame_count
+        };
+
+        if steps == 0 { time0 } else { time0 + (time1 - time0) * frame as f64 / steps as f64 }
+    }
+
+    pub fn key_at(&self, time: f64) -> CameraKey {
+        match &self.motion {
+            CameraMotion::Keyframes { keys, interpolation } => interpolate_keys(keys, *interpolation, time),
+            CameraMotion::Turntable { center, radius, height, fov, time0, time1 } => {
+                let span = (time1 - time0).max(1e-12);
+                let angle = 2.0 * PI * (time - time0) / span;
+                let look_from = *center + Vec3::new(radius * angle.sin(), *height, -radius * angle.cos());
+
+                CameraKey::new_with(time, look_from, *center, *fov, (look_from - *center).length())
+            }
+        }
+    }
+
+    // Camera for the frame at `time`, with the shutter closed at that instant
+    pub fn camera_at(&self, time: f64) -> Shutter<PerspectiveCamera> {
+        let key = self.key_at(time);
+        let camera = PerspectiveCamera::new(
+            key.look_from,
+            key.look_at,
+            self.vup,
+            key.fov,
+            self.aspect_ratio,
+            self.aperture,
+            key.focus_dist.max(1e-3)
+        );
+
+        Shutter::new_instant(camera, time)
+    }
+}
+
+fn interpolate_keys(keys: &[CameraKey], interpolation: Interpolation, time: f64) -> CameraKey {
+    if keys.is_empty() {
+        return CameraKey::new_with(time, Point3::new_empty(), Point3::new(0, 0, -1), 90.0, 1.0);
+    }
+
+    let last = keys.len() - 1;
+    if time <= keys[0].time {
+        return CameraKey { time, ..keys[0] };
+    }
+    if time >= keys[last].time {
+        return CameraKey { time, ..keys[last] };
+    }
+
+    // Segment from keys[i] to keys[i + 1] containing `time`
+    let i = keys.windows(2).position(|w| time < w[1].time).unwrap_or(last - 1);
+    let (k1, k2) = (&keys[i], &keys[i + 1]);
+    let span = k2.time - k1.time;
+    let t = if span > 0.0 { (time - k1.time) / span } else { 0.0 };
+
+    match interpolation {
+        Interpolation::Linear => CameraKey {
+            time,
+            look_from: k1.look_from * (1.0 - t) + k2.look_from * t,
+            look_at: k1.look_at * (1.0 - t) + k2.look_at * t,
+            fov: k1.fov * (1.0 - t) + k2.fov * t,
+            focus_dist: k1.focus_dist * (1.0 - t) + k2.focus_dist * t
+        },
+        Interpolation::CatmullRom => {
+            // Ends reuse their own key as the missing neighbour
+            let k0 = &keys[i.saturating_sub(1)];
+            let k3 = &keys[(i + 2).min(last)];
+
+            CameraKey {
+                time,
+                look_from: catmull_rom(&k0.look_from, &k1.look_from, &k2.look_from, &k3.look_from, t),
+                look_at: catmull_rom(&k0.look_at, &k1.look_at, &k2.look_at, &k3.look_at, t),
+                fov: catmull_rom_scalar(k0.fov, k1.fov, k2.fov, k3.fov, t),
+                focus_dist: catmull_rom_scalar(k0.focus_dist, k1.focus_dist, k2.focus_dist, k3.focus_dist, t)
+            }
+        }
+    }
+}
+
+fn catmull_rom(p0: &Vec3, p1: &Vec3, p2: &Vec3, p3: &Vec3, t: f64) -> Vec3 {
+    Vec3::new(
+        catmull_rom_scalar(p0.x(), p1.x(), p2.x(), p3.x(), t),
+        catmull_rom_scalar(p0.y(), p1.y(), p2.y(), p3.y(), t),
+        catmull_rom_scalar(p0.z(), p1.z(), p2.z(), p3.z(), t)
+    )
+}
+
+fn catmull_rom_scalar(p0: f64, p1: f64, p2: f64, p3: f64, t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<CameraKey> {
+        vec![
+            CameraKey::new_with(0.0, Point3::new(0, 1, -5), Point3::new(0, 0, 0), 40.0, 5.0),
+            CameraKey::new_with(0.5, Point3::new(4, 2, -3), Point3::new(0, 1, 0), 30.0, 4.0),
+            CameraKey::new_with(1.0, Point3::new(5, 1, 2), Point3::new(1, 0, 0), 50.0, 6.0),
+            CameraKey::new_with(2.0, Point3::new(0, 3, 5), Point3::new(0, 0, 1), 40.0, 5.0)
+        ]
+    }
+
+    fn assert_same(a: &CameraKey, b: &CameraKey) {
+        assert!((a.look_from - b.look_from).length() < 1e-9, "{:?} != {:?}", a.look_from, b.look_from);
+        assert!((a.look_at - b.look_at).length() < 1e-9, "{:?} != {:?}", a.look_at, b.look_at);
+        assert!((a.fov - b.fov).abs() < 1e-9);
+        assert!((a.focus_dist - b.focus_dist).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interpolation_passes_through_keys() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let path = CameraPath::new_keyframes(keys(), interpolation, 1.0);
+            for key in keys() {
+                assert_same(&path.key_at(key.time), &key);
+            }
+        }
+    }
+
+    #[test]
+    fn catmull_rom_clamps_at_the_ends() {
+        let path = CameraPath::new_keyframes(keys(), Interpolation::CatmullRom, 1.0);
+        let keys = keys();
+
+        assert_same(&path.key_at(-1.0), &keys[0]);
+        assert_same(&path.key_at(3.0), &keys[3]);
+
+        // Just inside either end the curve stays next to the end keys
+        assert!((path.key_at(1e-6).look_from - keys[0].look_from).length() < 1e-4);
+        assert!((path.key_at(2.0 - 1e-6).look_from - keys[3].look_from).length() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_returns_to_its_start() {
+        let key = CameraKey::new_with(0.0, Point3::new(3, 2, -4), Point3::new(0, 1, 0), 40.0, 5.0);
+        let path = CameraPath::new_orbit(&key, 1.0);
+        let (time0, time1) = path.time_range();
+
+        let start = path.key_at(time0);
+        assert_same(&start, &path.key_at(time1));
+        assert!(((start.look_from - start.look_at).length() - (key.look_from - key.look_at).length()).abs() < 1e-9);
+
+        // Halfway round it is on the opposite side of the centre
+        let half = path.key_at(0.5 * (time0 + time1));
+        let a = start.look_from - start.look_at;
+        let b = half.look_from - half.look_at;
+        assert!((a.x() + b.x()).abs() < 1e-9 && (a.z() + b.z()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_keyframe_files() {
+        let text = "# a short dolly\nlinear\n0  0 1 -5  0 0 0  40\n1  0 1 -3  0 0 0  35 2.5 # closer\n";
+        let path = CameraPath::parse_keyframes(text, 1.0).unwrap();
+
+        match &path.motion {
+            CameraMotion::Keyframes { keys, interpolation } => {
+                assert_eq!(*interpolation, Interpolation::Linear);
+                assert_eq!(keys.len(), 2);
+                assert!((keys[0].focus_dist - 26f64.sqrt()).abs() < 1e-9);
+                assert!((keys[1].focus_dist - 2.5).abs() < 1e-9);
+            },
+            _ => panic!("expected keyframes")
+        }
+
+        assert!(CameraPath::parse_keyframes("0 1 2", 1.0).is_err());
+        assert!(CameraPath::parse_keyframes("# nothing\n", 1.0).is_err());
+    }
+}
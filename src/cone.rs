@@ -0,0 +1,129 @@
+use std::borrow::Borrow;
+use std::f64::consts::PI;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+
+// Capped cone standing on `base` along `axis`. The radius changes linearly from
+// `base_radius` to `top_radius`, so a zero top radius gives a pointed cone and
+// anything else a frustum.
+pub struct Cone {
+    base: Point3,
+    frame: Onb,
+    base_radius: f64,
+    top_radius: f64,
+    height: f64,
+    material: Materials
+}
+
+impl Cone {
+    pub fn new_with(base: Point3, axis: Vec3, radius: f64, height: f64, material: Materials) -> Cone {
+        Cone::new_frustum(base, axis, radius, 0.0, height, material)
+    }
+
+    pub fn new_frustum(base: Point3, axis: Vec3, base_radius: f64, top_radius: f64, height: f64, material: Materials) -> Cone {
+        Cone {
+            base, base_radius, top_radius, height, material,
+            frame: Onb::build_from_w(&axis)
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let o = self.frame.to_local(&(*ray.origin() - self.base));
+        let d = self.frame.to_local(ray.dir());
+
+        // Radius at height z is r0 + k * z
+        let r0 = self.base_radius;
+        let k = (self.top_radius - self.base_radius) / self.height;
+        let ro = r0 + k * o.z();
+
+        let mut closest = t_max;
+        let mut local_normal = Vec3::new_empty();
+        let mut found = false;
+
+        let a = d.x() * d.x() + d.y() * d.y() - k * k * d.z() * d.z();
+        let half_b = o.x() * d.x() + o.y() * d.y() - k * d.z() * ro;
+        let c = o.x() * o.x() + o.y() * o.y() - ro * ro;
+
+        // Missing roots are NaN, which fails every test below
+        let roots = if a.abs() < 1e-12 {
+            [if half_b.abs() < 1e-12 { f64::NAN } else { -c / (2.0 * half_b) }, f64::NAN]
+        } else {
+            let disc = half_b * half_b - a * c;
+            if disc < 0.0 {
+                [f64::NAN; 2]
+            } else {
+                let sqrt_d = disc.sqrt();
+                [(-half_b - sqrt_d) / a, (-half_b + sqrt_d) / a]
+            }
+        };
+
+        for root in roots {
+            let p = o + d * root;
+            if root >= t_min && root < closest && (0.0..=self.height).contains(&p.z()) {
+                closest = root;
+                local_normal = Vec3::new(p.x(), p.y(), -k * (r0 + k * p.z())).normalized();
+                found = true;
+            }
+        }
+
+        // End caps, skipped when they collapse to a point
+        if d.z().abs() > 1e-12 {
+            for (z, nz, radius) in [(0.0, -1.0, self.base_radius), (self.height, 1.0, self.top_radius)] {
+                if radius <= 0.0 {
+                    continue;
+                }
+
+                let root = (z - o.z()) / d.z();
+                let p = o + d * root;
+                if root >= t_min && root < closest && p.x() * p.x() + p.y() * p.y() <= radius * radius {
+                    closest = root;
+                    local_normal = Vec3::new(0.0, 0.0, nz);
+                    found = true;
+                }
+            }
+        }
+
+        if !found || local_normal.near_zero() {
+            return false;
+        }
+
+        let local_p = o + d * closest;
+        rec.t = closest;
+        rec.p = ray.at(closest);
+
+        if local_normal.z().abs() < 1.0 {
+            rec.u = (local_p.y().atan2(local_p.x()) + PI) / (2.0 * PI);
+            rec.v = local_p.z() / self.height;
+            rec.dpdu = self.frame.local(-local_p.y(), local_p.x(), 0.0) * (2.0 * PI);
+
+            // Moving up the side also moves outwards by the slope of the cone
+            let rho = (r0 + k * local_p.z()).max(1e-12);
+            rec.dpdv = self.frame.local(local_p.x() * k / rho, local_p.y() * k / rho, 1.0) * self.height;
+        } else {
+            let radius = if local_normal.z() < 0.0 { self.base_radius } else { self.top_radius };
+            rec.u = 0.5 * (local_p.x() / radius + 1.0);
+            rec.v = 0.5 * (local_p.y() / radius + 1.0);
+            rec.dpdu = self.frame.u * (2.0 * radius);
+            rec.dpdv = self.frame.v * (2.0 * radius);
+        }
+
+        let outward_normal = self.frame.local(local_normal.x(), local_normal.y(), local_normal.z());
+        rec.set_face_normal(ray, &outward_normal);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let radius = self.base_radius.max(self.top_radius);
+        let center = self.base + self.frame.w * (self.height / 2.0);
+        let extent = self.frame.world_extent(&Vec3::new(radius, radius, self.height / 2.0));
+
+        *output_box = AABB::new((center - extent).borrow(), (center + extent).borrow());
+        true
+    }
+}
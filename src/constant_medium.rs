@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use crate::{HitRecord, Hittable, Materials, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::medium::{inside_range, PhaseFunction};
+use crate::texture::Texture;
+use crate::sampler::Sampler;
+
+// Volume of constant density inside a closed boundary, such as smoke or fog
+// filling a box. Rays scatter at a random distance inside the boundary.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_material: Materials
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Texture) -> ConstantMedium {
+        ConstantMedium::new_with_phase(boundary, density, albedo, PhaseFunction::Isotropic)
+    }
+
+    pub fn new_with_phase(boundary: Arc<dyn Hittable>, density: f64, albedo: Texture, phase: PhaseFunction) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_material: Materials::Medium { albedo, phase }
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        let (t1, t2) = match inside_range(self.boundary.as_ref(), ray, t_min, t_max, sampler) {
+            Some(range) => range,
+            None => return false
+        };
+
+        let ray_length = ray.dir().length();
+        let distance_inside_boundary = (t2 - t1) * ray_length;
+        let hit_distance = self.neg_inv_density * (1.0 - sampler.get_1d()).ln();
+
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = t1 + hit_distance / ray_length;
+        rec.p = ray.at(rec.t);
+
+        // Scattering inside a volume has no surface, so the normal is arbitrary
+        rec.normal = Vec3::new(1, 0, 0);
+        rec.front_face = true;
+        rec.dpdu = Vec3::new_empty();
+        rec.dpdv = Vec3::new_empty();
+        rec.material = self.phase_material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        match inside_range(self.boundary.as_ref(), ray, t_min, t_max, sampler) {
+            Some((t1, t2)) => ((t2 - t1) * ray.dir().length() / self.neg_inv_density).exp(),
+            None => 1.0
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::transform::{Mat4, Transformed};
+use crate::sampler::Sampler;
+
+// Axis-aligned box. Oriented boxes are built by wrapping one in a `Transformed`.
+pub struct Cuboid {
+    minimum: Point3,
+    maximum: Point3,
+    material: Materials
+}
+
+impl Cuboid {
+    pub fn new_with(a: Point3, b: Point3, material: Materials) -> Cuboid {
+        Cuboid {
+            minimum: Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            maximum: Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+            material
+        }
+    }
+
+    // Box of the given size centred on `center`, rotated about its own centre.
+    // None when `rotation` cannot be inverted.
+    pub fn new_oriented(center: Point3, size: Vec3, rotation: Mat4, material: Materials) -> Option<Transformed> {
+        let half = size / 2.0;
+        Transformed::new(
+            Arc::new(Cuboid::new_with(-half, half, material)),
+            Mat4::translate(center) * rotation
+        )
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let mut t_enter = f64::NEG_INFINITY;
+        let mut t_exit = f64::INFINITY;
+        let mut enter_axis = 0;
+        let mut exit_axis = 0;
+
+        for a in 0..3 {
+            let inv = ray.inv_dir().e[a];
+            let mut t0 = (self.minimum.e[a] - ray.origin().e[a]) * inv;
+            let mut t1 = (self.maximum.e[a] - ray.origin().e[a]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            if t0 > t_enter {
+                t_enter = t0;
+                enter_axis = a;
+            }
+            if t1 < t_exit {
+                t_exit = t1;
+                exit_axis = a;
+            }
+        }
+
+        if t_enter > t_exit {
+            return false;
+        }
+
+        // Rays starting inside the box hit the face they leave through
+        let (t, axis, entering) = if t_enter >= t_min && t_enter <= t_max {
+            (t_enter, enter_axis, true)
+        } else if t_exit >= t_min && t_exit <= t_max {
+            (t_exit, exit_axis, false)
+        } else {
+            return false;
+        };
+
+        let sign = if (ray.dir().e[axis] < 0.0) == entering { 1.0 } else { -1.0 };
+        let mut outward_normal = Vec3::new_empty();
+        outward_normal.e[axis] = sign;
+
+        rec.t = t;
+        rec.p = ray.at(t);
+
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let size = self.maximum - self.minimum;
+        rec.u = (rec.p.e[a] - self.minimum.e[a]) / size.e[a];
+        rec.v = (rec.p.e[b] - self.minimum.e[b]) / size.e[b];
+        rec.dpdu = Vec3::new_empty();
+        rec.dpdu.e[a] = size.e[a];
+        rec.dpdv = Vec3::new_empty();
+        rec.dpdv.e[b] = size.e[b];
+        rec.set_face_normal(ray, &outward_normal);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(self.minimum.borrow(), self.maximum.borrow());
+        true
+    }
+}
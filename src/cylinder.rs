@@ -0,0 +1,104 @@
+use std::borrow::Borrow;
+use std::f64::consts::PI;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+
+// Capped cylinder standing on `base` and extending `height` along `axis`
+pub struct Cylinder {
+    base: Point3,
+    frame: Onb,
+    radius: f64,
+    height: f64,
+    material: Materials
+}
+
+impl Cylinder {
+    pub fn new_with(base: Point3, axis: Vec3, radius: f64, height: f64, material: Materials) -> Cylinder {
+        Cylinder {
+            base, radius, height, material,
+            frame: Onb::build_from_w(&axis)
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let o = self.frame.to_local(&(*ray.origin() - self.base));
+        let d = self.frame.to_local(ray.dir());
+
+        let mut closest = t_max;
+        let mut local_normal = Vec3::new_empty();
+        let mut found = false;
+
+        // Side of the cylinder
+        let a = d.x() * d.x() + d.y() * d.y();
+        if a > 1e-12 {
+            let half_b = o.x() * d.x() + o.y() * d.y();
+            let c = o.x() * o.x() + o.y() * o.y() - self.radius * self.radius;
+            let disc = half_b * half_b - a * c;
+
+            if disc >= 0.0 {
+                let sqrt_d = disc.sqrt();
+                for root in [(-half_b - sqrt_d) / a, (-half_b + sqrt_d) / a] {
+                    let z = o.z() + root * d.z();
+                    if root >= t_min && root < closest && (0.0..=self.height).contains(&z) {
+                        let p = o + d * root;
+                        closest = root;
+                        local_normal = Vec3::new(p.x(), p.y(), 0.0) / self.radius;
+                        found = true;
+                    }
+                }
+            }
+        }
+
+        // End caps
+        if d.z().abs() > 1e-12 {
+            for (z, nz) in [(0.0, -1.0), (self.height, 1.0)] {
+                let root = (z - o.z()) / d.z();
+                let p = o + d * root;
+                if root >= t_min && root < closest
+                    && p.x() * p.x() + p.y() * p.y() <= self.radius * self.radius {
+                    closest = root;
+                    local_normal = Vec3::new(0.0, 0.0, nz);
+                    found = true;
+                }
+            }
+        }
+
+        if !found {
+            return false;
+        }
+
+        let local_p = o + d * closest;
+        rec.t = closest;
+        rec.p = ray.at(closest);
+
+        if local_normal.z() == 0.0 {
+            rec.u = (local_p.y().atan2(local_p.x()) + PI) / (2.0 * PI);
+            rec.v = local_p.z() / self.height;
+            rec.dpdu = self.frame.local(-local_p.y(), local_p.x(), 0.0) * (2.0 * PI);
+            rec.dpdv = self.frame.w * self.height;
+        } else {
+            rec.u = 0.5 * (local_p.x() / self.radius + 1.0);
+            rec.v = 0.5 * (local_p.y() / self.radius + 1.0);
+            rec.dpdu = self.frame.u * (2.0 * self.radius);
+            rec.dpdv = self.frame.v * (2.0 * self.radius);
+        }
+
+        let outward_normal = self.frame.local(local_normal.x(), local_normal.y(), local_normal.z());
+        rec.set_face_normal(ray, &outward_normal);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let center = self.base + self.frame.w * (self.height / 2.0);
+        let extent = self.frame.world_extent(&Vec3::new(self.radius, self.radius, self.height / 2.0));
+
+        *output_box = AABB::new((center - extent).borrow(), (center + extent).borrow());
+        true
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use image::RgbaImage;
+use rayon::prelude::*;
+use crate::Color;
+use crate::aov::Aov;
+use crate::framebuffer::{color_to_rgba, FrameBuffer};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Denoiser {
+    // Open Image Denoise, only with the `oidn` feature and its native library
+    Oidn,
+    // Built-in joint bilateral filter, always available
+    Bilateral
+}
+
+impl Denoiser {
+    // Open Image Denoise when it is compiled in, otherwise the built-in filter
+    pub fn best_available() -> Denoiser {
+        if cfg!(feature = "oidn") { Denoiser::Oidn } else { Denoiser::Bilateral }
+    }
+
+    pub fn from_name(name: &str) -> Option<Denoiser> {
+        match name {
+            "oidn" => Some(Denoiser::Oidn),
+            "bilateral" => Some(Denoiser::Bilateral),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DenoiseError {
+    // Failure reported by Open Image Denoise
+    #[cfg(feature = "oidn")]
+    Oidn(String),
+    // Asked for a denoiser this build does not include
+    Unavailable(Denoiser)
+}
+
+impl fmt::Display for DenoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "oidn")]
+            DenoiseError::Oidn(msg) => write!(f, "Error denoising image: {}", msg),
+            DenoiseError::Unavailable(denoiser) => write!(f, "Denoiser {:?} is not available in this build", denoiser)
+        }
+    }
+}
+
+impl std::error::Error for DenoiseError {}
+
+// Linear colours of `buffer` and, when the buffer has both the albedo and
+// normal AOVs, those as guides. Each is a row-major plane.
+struct DenoiseInput {
+    width: usize,
+    height: usize,
+    color: Vec<Color>,
+    guides: Option<(Vec<Color>, Vec<Color>)>
+}
+
+impl DenoiseInput {
+    fn new(buffer: &FrameBuffer) -> DenoiseInput {
+        let (x0, y0, x1, y1) = buffer.bounds();
+        let plane = |value: &dyn Fn(u32, u32) -> Color| -> Vec<Color> {
+            (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))).map(|(x, y)| value(x, y)).collect()
+        };
+
+        let guides = if buffer.aovs().contains(&Aov::Albedo) && buffer.aovs().contains(&Aov::Normal) {
+            Some((plane(&|x, y| buffer.aov(x, y, Aov::Albedo)), plane(&|x, y| buffer.aov(x, y, Aov::Normal))))
+        } else {
+            None
+        };
+
+        DenoiseInput {
+            width: (x1 - x0) as usize,
+            height: (y1 - y0) as usize,
+            color: plane(&|x, y| buffer.color(x, y)),
+            guides
+        }
+    }
+}
+
+// Denoises the linear colours of `buffer`. The albedo and normal AOVs guide
+// the denoiser when the buffer has both, which keeps texture detail and edges
+// the noise would otherwise hide.
+pub fn denoise(buffer: &FrameBuffer, denoiser: Denoiser) -> Result<Vec<Color>, DenoiseError> {
+    let input = DenoiseInput::new(buffer);
+
+    match denoiser {
+        Denoiser::Oidn => denoise_oidn(&input),
+        Denoiser::Bilateral => Ok(denoise_bilateral(&input))
+    }
+}
+
+// Denoised version of the buffer's image, encoded like the original
+pub fn denoise_image(buffer: &FrameBuffer, denoiser: Denoiser) -> Result<RgbaImage, DenoiseError> {
+    let colors = denoise(buffer, denoiser)?;
+    let (x0, y0, x1, y1) = buffer.bounds();
+
+    let mut image = RgbaImage::new(x1 - x0, y1 - y0);
+    for (pixel, color) in image.pixels_mut().zip(colors.iter()) {
+        *pixel = color_to_rgba(color);
+    }
+
+    Ok(image)
+}
+
+#[cfg(feature = "oidn")]
+fn denoise_oidn(input: &DenoiseInput) -> Result<Vec<Color>, DenoiseError> {
+    let flatten = |plane: &[Color]| -> Vec<f32> {
+        plane.iter().flat_map(|c| c.e.map(|v| v as f32)).collect()
+    };
+
+    let color = flatten(&input.color);
+    let (albedo, normal) = match &input.guides {
+        Some((albedo, normal)) => (flatten(albedo), flatten(normal)),
+        None => (Vec::new(), Vec::new())
+    };
+
+    let mut output = vec![0.0f32; color.len()];
+
+    let device = oidn::Device::new();
+    let mut filter = oidn::RayTracing::new(&device);
+    filter
+        .hdr(true)
+        .image_dimensions(input.width, input.height);
+    if input.guides.is_some() {
+        filter.albedo_normal(&albedo[..], &normal[..]);
+    }
+
+    filter
+        .filter(&color[..], &mut output[..])
+        .map_err(|e| DenoiseError::Oidn(format!("{:?}", e)))?;
+
+    if let Err(e) = device.get_error() {
+        return Err(DenoiseError::Oidn(e.1));
+    }
+
+    Ok(output.chunks(3).map(|c| Color::new(c[0], c[1], c[2])).collect())
+}
+
+#[cfg(not(feature = "oidn"))]
+fn denoise_oidn(_input: &DenoiseInput) -> Result<Vec<Color>, DenoiseError> {
+    Err(DenoiseError::Unavailable(Denoiser::Oidn))
+}
+
+const BILATERAL_RADIUS: i64 = 6;
+const SIGMA_SPATIAL: f64 = 3.0;
+// Colour differences are compared after gamma encoding and a 3x3 blur, so
+// the noise itself does not read as an edge
+const SIGMA_COLOR: f64 = 0.15;
+const SIGMA_ALBEDO: f64 = 0.1;
+const SIGMA_NORMAL: f64 = 0.25;
+
+// Joint bilateral filter: each pixel becomes a weighted average of its
+// neighbours, with weights falling off with distance and with differences in
+// colour, albedo and normal. With guides the lighting is filtered on its own,
+// by dividing out the albedo first and multiplying it back after, so
+// textures stay sharp.
+fn denoise_bilateral(input: &DenoiseInput) -> Vec<Color> {
+    let (width, height) = (input.width as i64, input.height as i64);
+    let albedo_floor = |a: &Color| Color::new(a.x().max(0.01), a.y().max(0.01), a.z().max(0.01));
+
+    let lighting: Vec<Color> = match &input.guides {
+        Some((albedo, _)) => input.color.iter().zip(albedo.iter()).map(|(c, a)| *c / albedo_floor(a)).collect(),
+        None => input.color.clone()
+    };
+
+    let encoded: Vec<Color> = input.color.iter()
+        .map(|c| Color::new(c.x().max(0.0).sqrt(), c.y().max(0.0).sqrt(), c.z().max(0.0).sqrt()))
+        .collect();
+    let range_guide: Vec<Color> = (0..width * height).map(|i| {
+        let (x, y) = (i % width, i / width);
+        let mut sum = Color::new_empty();
+        let mut count = 0.0;
+        for qy in (y - 1).max(0)..=(y + 1).min(height - 1) {
+            for qx in (x - 1).max(0)..=(x + 1).min(width - 1) {
+                sum += encoded[(qy * width + qx) as usize];
+                count += 1.0;
+            }
+        }
+        sum / count
+    }).collect();
+
+    let gaussian = |d2: f64, sigma: f64| (-d2 / (2.0 * sigma * sigma)).exp();
+
+    (0..width * height).into_par_iter().map(|i| {
+        let (x, y) = (i % width, i / width);
+        let p = i as usize;
+
+        let mut sum = Color::new_empty();
+        let mut weight_sum = 0.0;
+
+        for qy in (y - BILATERAL_RADIUS).max(0)..=(y + BILATERAL_RADIUS).min(height - 1) {
+            for qx in (x - BILATERAL_RADIUS).max(0)..=(x + BILATERAL_RADIUS).min(width - 1) {
+                let q = (qy * width + qx) as usize;
+                let (dx, dy) = ((qx - x) as f64, (qy - y) as f64);
+
+                let mut w = gaussian(dx * dx + dy * dy, SIGMA_SPATIAL)
+                    * gaussian((range_guide[p] - range_guide[q]).length_squared(), SIGMA_COLOR);
+                if let Some((albedo, normal)) = &input.guides {
+                    w *= gaussian((albedo[p] - albedo[q]).length_squared(), SIGMA_ALBEDO)
+                        * gaussian((normal[p] - normal[q]).length_squared(), SIGMA_NORMAL);
+                }
+
+                sum += lighting[q] * w;
+                weight_sum += w;
+            }
+        }
+
+        // The centre pixel always has weight one, so the sum is never zero
+        let filtered = sum / weight_sum;
+        match &input.guides {
+            Some((albedo, _)) => filtered * albedo_floor(&albedo[p]),
+            None => filtered
+        }
+    }).collect()
+}
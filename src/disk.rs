@@ -0,0 +1,93 @@
+use std::borrow::Borrow;
+use std::f64::consts::PI;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::sampler::Sampler;
+use crate::onb::Onb;
+
+pub struct Disk {
+    center: Point3,
+    radius: f64,
+    frame: Onb,
+    material: Materials
+}
+
+impl Disk {
+    pub fn new_with(center: Point3, normal: Vec3, radius: f64, material: Materials) -> Disk {
+        Disk {
+            center, radius, material,
+            frame: Onb::build_from_w(&normal)
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let denom = self.frame.w.dot(ray.dir());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = (self.center - *ray.origin()).dot(&self.frame.w) / denom;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        let p = ray.at(t);
+        let local = self.frame.to_local(&(p - self.center));
+        let dist_squared = local.x() * local.x() + local.y() * local.y();
+        if dist_squared > self.radius * self.radius {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = p;
+        rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+        rec.v = dist_squared.sqrt() / self.radius;
+        rec.dpdu = self.frame.local(-local.y(), local.x(), 0.0) * (2.0 * PI);
+        rec.dpdv = if dist_squared > 0.0 {
+            self.frame.local(local.x(), local.y(), 0.0) * (self.radius / dist_squared.sqrt())
+        } else {
+            Vec3::new_empty()
+        };
+        rec.set_face_normal(ray, &self.frame.w);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        // Extent of the disk along each world axis, padded so it never has zero thickness
+        let n = self.frame.w;
+        let extent = Vec3::new(
+            self.radius * (1.0 - n.x() * n.x()).max(0.0).sqrt() + 1e-4,
+            self.radius * (1.0 - n.y() * n.y()).max(0.0).sqrt() + 1e-4,
+            self.radius * (1.0 - n.z() * n.z()).max(0.0).sqrt() + 1e-4
+        );
+
+        *output_box = AABB::new((self.center - extent).borrow(), (self.center + extent).borrow());
+
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3, sampler: &mut dyn Sampler) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(*origin, *dir), 0.001, f64::INFINITY, &mut rec, sampler) {
+            return 0.0;
+        }
+
+        let area = PI * self.radius * self.radius;
+        let distance_squared = rec.t * rec.t * dir.length_squared();
+        let cosine = (dir.dot(&self.frame.w) / dir.length()).abs();
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let r = self.radius * u.sqrt();
+        let phi = 2.0 * PI * v;
+        let p = self.center + self.frame.local(r * phi.cos(), r * phi.sin(), 0.0);
+        p - *origin
+    }
+}
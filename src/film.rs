@@ -0,0 +1,115 @@
+// Resolution of a render and the part of it that is actually traced. Cameras
+// take their aspect ratio from here so the image is never stretched.
+#[derive(Debug, Copy, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    // Width over height of a single pixel, for anamorphic output
+    pub pixel_aspect: f64,
+    // Traced region as fractions of the film, [x_min, x_max, y_min, y_max]
+    // with y pointing down. The output image only holds this region.
+    pub crop: [f64; 4]
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width: width.max(1),
+            height: height.max(1),
+            pixel_aspect: 1.0,
+            crop: [0.0, 1.0, 0.0, 1.0]
+        }
+    }
+
+    pub fn with_pixel_aspect(mut self, pixel_aspect: f64) -> Film {
+        self.pixel_aspect = pixel_aspect;
+        self
+    }
+
+    pub fn with_crop(mut self, x_min: f64, x_max: f64, y_min: f64, y_max: f64) -> Film {
+        self.crop = [x_min.clamp(0.0, 1.0), x_max.clamp(0.0, 1.0), y_min.clamp(0.0, 1.0), y_max.clamp(0.0, 1.0)];
+        self
+    }
+
+    // The same film at a fraction of the resolution, for quick previews
+    #[cfg(feature = "viewer")]
+    pub fn downscaled(&self, factor: u32) -> Film {
+        Film {
+            width: (self.width / factor.max(1)).max(1),
+            height: (self.height / factor.max(1)).max(1),
+            ..*self
+        }
+    }
+
+    // Aspect ratio of the whole film as displayed, for the camera
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 * self.pixel_aspect / self.height as f64
+    }
+
+    // Pixel bounds of the crop window as (x0, y0, x1, y1), end exclusive and
+    // never empty
+    pub fn pixel_bounds(&self) -> (u32, u32, u32, u32) {
+        // A crop starting at the far edge still keeps its last pixel
+        let x0 = ((self.crop[0] * self.width as f64).floor() as u32).min(self.width - 1);
+        let y0 = ((self.crop[2] * self.height as f64).floor() as u32).min(self.height - 1);
+        let x1 = ((self.crop[1] * self.width as f64).ceil() as u32).clamp(x0 + 1, self.width);
+        let y1 = ((self.crop[3] * self.height as f64).ceil() as u32).clamp(y0 + 1, self.height);
+
+        (x0, y0, x1, y1)
+    }
+
+    // Size of the output image, which is the size of the crop window
+    pub fn output_size(&self) -> (u32, u32) {
+        let (x0, y0, x1, y1) = self.pixel_bounds();
+        (x1 - x0, y1 - y0)
+    }
+
+    // Screen position for the camera of a point in output pixel (px, py), with
+    // (dx, dy) in [0, 1) the offset inside the pixel. t points up.
+    pub fn screen_position(&self, px: u32, py: u32, dx: f64, dy: f64) -> (f64, f64) {
+        let (x0, y0, _, _) = self.pixel_bounds();
+        let s = ((x0 + px) as f64 + dx) / self.width as f64;
+        let t = 1.0 - ((y0 + py) as f64 + dy) / self.height as f64;
+
+        (s, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_film_covers_every_pixel() {
+        let film = Film::new(640, 480);
+        assert_eq!(film.pixel_bounds(), (0, 0, 640, 480));
+        assert_eq!(film.output_size(), (640, 480));
+    }
+
+    #[test]
+    fn crop_rounds_outwards() {
+        let film = Film::new(100, 100).with_crop(0.251, 0.5, 0.1, 0.349);
+        assert_eq!(film.pixel_bounds(), (25, 10, 50, 35));
+    }
+
+    #[test]
+    fn crop_at_far_edge_stays_inside_film() {
+        let film = Film::new(100, 50).with_crop(1.0, 1.0, 1.0, 1.0);
+        assert_eq!(film.pixel_bounds(), (99, 49, 100, 50));
+        assert_eq!(film.output_size(), (1, 1));
+    }
+
+    #[test]
+    fn empty_crop_keeps_one_pixel() {
+        let film = Film::new(100, 100).with_crop(0.5, 0.2, 0.5, 0.5);
+        assert_eq!(film.pixel_bounds(), (50, 50, 51, 51));
+    }
+
+    #[test]
+    fn screen_position_is_relative_to_whole_film() {
+        let film = Film::new(100, 100).with_crop(0.5, 1.0, 0.0, 1.0);
+        let (s, t) = film.screen_position(0, 0, 0.5, 0.5);
+        assert!((s - 0.505).abs() < 1e-12);
+        assert!((t - 0.995).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,146 @@
+use std::f64::consts::PI;
+
+// Pixel reconstruction filter. Every sample is splatted onto all pixels within
+// `radius` of it, weighted by the filter at the offset from the pixel centre,
+// and each pixel is the weighted average of what landed on it. Offsets are in
+// pixels. All filters are separable.
+#[derive(Debug, Copy, Clone)]
+pub enum Filter {
+    // Plain average; a radius of 0.5 keeps every sample inside its own pixel
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    // Gaussian shifted down so it reaches zero at the radius
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell-Netravali cubic, b = c = 1/3 is the recommended pair. Its
+    // negative lobes sharpen edges.
+    Mitchell { radius: f64, b: f64, c: f64 },
+    BlackmanHarris { radius: f64 }
+}
+
+impl Filter {
+    pub fn new_mitchell(radius: f64) -> Filter {
+        Filter::Mitchell { radius, b: 1.0 / 3.0, c: 1.0 / 3.0 }
+    }
+
+    // Filter for a command line name, at its usual radius
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box { radius: 0.5 }),
+            "tent" => Some(Filter::Tent { radius: 1.0 }),
+            "gaussian" => Some(Filter::default()),
+            "mitchell" => Some(Filter::new_mitchell(2.0)),
+            "blackman-harris" => Some(Filter::BlackmanHarris { radius: 2.0 }),
+            _ => None
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::BlackmanHarris { radius } => radius
+        }
+    }
+
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => radius - x,
+            Filter::Gaussian { sigma, .. } => {
+                let gaussian = |d: f64| (-d * d / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { b, c, .. } => mitchell_1d(2.0 * x / radius, b, c),
+            Filter::BlackmanHarris { .. } => {
+                // Window over [-radius, radius], peaking at the centre
+                let t = 2.0 * PI * (0.5 + 0.5 * x / radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Gaussian { radius: 1.5, sigma: 0.5 }
+    }
+}
+
+// Mitchell-Netravali cubic over |x| in [0, 2]
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    let x2 = x * x;
+    let x3 = x2 * x;
+
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integral of the 1D filter over its support
+    fn integral(filter: &Filter) -> f64 {
+        let r = filter.radius();
+        let n = 10000;
+        let dx = 2.0 * r / n as f64;
+        (0..n).map(|i| filter.evaluate_1d(-r + (i as f64 + 0.5) * dx) * dx).sum()
+    }
+
+    #[test]
+    fn filters_vanish_outside_radius() {
+        let filters = [
+            Filter::Box { radius: 0.5 },
+            Filter::Tent { radius: 1.0 },
+            Filter::default(),
+            Filter::new_mitchell(2.0),
+            Filter::BlackmanHarris { radius: 2.0 }
+        ];
+
+        for filter in filters {
+            let r = filter.radius();
+            assert_eq!(filter.evaluate(r * 1.01, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, -r * 1.01), 0.0);
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+        }
+    }
+
+    #[test]
+    fn gaussian_reaches_zero_at_radius() {
+        assert!(Filter::default().evaluate_1d(1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mitchell_matches_reference_values() {
+        // (6 - 2b) / 6 at the centre and a unit integral for b = c = 1/3
+        let filter = Filter::new_mitchell(2.0);
+        assert!((filter.evaluate_1d(0.0) - 8.0 / 9.0).abs() < 1e-12);
+        assert!((integral(&filter) - 1.0).abs() < 1e-6);
+        assert!(filter.evaluate_1d(1.5) < 0.0);
+    }
+
+    #[test]
+    fn blackman_harris_peaks_at_centre() {
+        let filter = Filter::BlackmanHarris { radius: 2.0 };
+        assert!((filter.evaluate_1d(0.0) - 1.0).abs() < 1e-4);
+        assert!(filter.evaluate_1d(2.0).abs() < 1e-4);
+    }
+}
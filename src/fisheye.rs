@@ -0,0 +1,51 @@
+use crate::{Point3, Ray, Vec3};
+use crate::camera::{look_basis, Camera};
+use crate::sampler::Sampler;
+
+// Equidistant fisheye: the angle from the view direction grows linearly with
+// the distance from the image centre, reaching `fov / 2` at the top and bottom
+// edges. Corners outside the image circle of wide images see nothing.
+#[derive(Debug)]
+pub struct FisheyeCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    fov: f64,
+    aspect_ratio: f64
+}
+
+impl FisheyeCamera {
+    // `fov` is in degrees and may exceed 180
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, fov: f64, aspect_ratio: f64) -> FisheyeCamera {
+        let (u, v, w) = look_basis(&look_from, &look_at, &vup);
+
+        FisheyeCamera {
+            origin: look_from,
+            u, v, w,
+            fov: fov.to_radians(),
+            aspect_ratio
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * self.fov / 2.0;
+        let phi = y.atan2(x);
+        let dir = self.u * (theta.sin() * phi.cos()) + self.v * (theta.sin() * phi.sin()) - self.w * theta.cos();
+
+        Some(Ray::new(self.origin, dir))
+    }
+
+    fn pixel_spread(&self, image_height: u32) -> f64 {
+        self.fov / image_height.max(1) as f64
+    }
+}
@@ -0,0 +1,385 @@
+use image::{Rgba, RgbaImage};
+use crate::Color;
+use crate::aov::{preview_color, Aov, AovSample};
+use crate::filter::Filter;
+
+// Running mean and sum of squared deviations of the luminance of the samples
+// traced for one pixel, for adaptive sampling
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PixelStats {
+    pub count: u32,
+    pub mean: f64,
+    pub m2: f64
+}
+
+impl PixelStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Statistics of both sets of samples together
+    pub fn combined(&self, other: &PixelStats) -> PixelStats {
+        let count = self.count + other.count;
+        if count == 0 {
+            return PixelStats::default();
+        }
+
+        let delta = other.mean - self.mean;
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+
+        PixelStats {
+            count,
+            mean: self.mean + delta * n_b / count as f64,
+            m2: self.m2 + other.m2 + delta * delta * n_a * n_b / count as f64
+        }
+    }
+}
+
+// Weighted sums of filtered samples over a rectangle of the output image.
+// Tiles render into small buffers of their own that are merged into the full
+// image buffer, so threads only contend when a tile finishes.
+#[derive(Debug, Clone)]
+pub struct FrameBuffer {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    sum: Vec<Color>,
+    weight: Vec<f64>,
+    // Unweighted sums and counts of the samples splatted onto each pixel. Filters
+    // with negative lobes can leave a pixel with no positive weight, which then
+    // falls back to the plain mean.
+    plain_sum: Vec<Color>,
+    splats: Vec<u32>,
+    // Samples traced for each pixel, as opposed to splatted onto it
+    stats: Vec<PixelStats>,
+    // Enabled AOVs and their weighted sums, interleaved per pixel. Ids are
+    // not summed but taken from the sample nearest the pixel centre, whose
+    // squared distance is kept in `nearest`.
+    aovs: Vec<Aov>,
+    aov_sum: Vec<Color>,
+    plain_aov_sum: Vec<Color>,
+    nearest: Vec<f64>
+}
+
+// Smallest total filter weight a pixel is resolved with
+const MIN_WEIGHT: f64 = 1e-3;
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> FrameBuffer {
+        FrameBuffer::new_region(0, 0, width, height)
+    }
+
+    // Buffer covering pixels x0..x1, y0..y1
+    pub fn new_region(x0: u32, y0: u32, x1: u32, y1: u32) -> FrameBuffer {
+        let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+        let len = (width * height) as usize;
+
+        FrameBuffer {
+            x0, y0, width, height,
+            sum: vec![Color::new_empty(); len],
+            weight: vec![0.0; len],
+            plain_sum: vec![Color::new_empty(); len],
+            splats: vec![0; len],
+            stats: vec![PixelStats::default(); len],
+            aovs: Vec::new(),
+            aov_sum: Vec::new(),
+            plain_aov_sum: Vec::new(),
+            nearest: Vec::new()
+        }
+    }
+
+    pub fn with_aovs(mut self, aovs: &[Aov]) -> FrameBuffer {
+        let len = (self.width * self.height) as usize;
+        self.aovs = aovs.to_vec();
+        self.aov_sum = vec![Color::new_empty(); len * aovs.len()];
+        self.plain_aov_sum = vec![Color::new_empty(); len * aovs.len()];
+        self.nearest = vec![f64::INFINITY; len];
+        self
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    // Region this buffer covers as (x0, y0, x1, y1)
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        (self.x0, self.y0, self.x0 + self.width, self.y0 + self.height)
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.x0 || y < self.y0 || x >= self.x0 + self.width || y >= self.y0 + self.height {
+            return None;
+        }
+
+        Some(((y - self.y0) * self.width + (x - self.x0)) as usize)
+    }
+
+    // Adds a sample at continuous image position (fx, fy), pixel (x, y)
+    // covering [x, x + 1) x [y, y + 1), to every pixel the filter reaches.
+    // Pixels outside this buffer are skipped.
+    pub fn splat(&mut self, fx: f64, fy: f64, color: Color, aov: Option<&AovSample>, filter: &Filter) {
+        let radius = filter.radius();
+        let x_min = (fx - 0.5 - radius).ceil().max(self.x0 as f64) as u32;
+        let y_min = (fy - 0.5 - radius).ceil().max(self.y0 as f64) as u32;
+        let x_max = (fx - 0.5 + radius).floor();
+        let y_max = (fy - 0.5 + radius).floor();
+        if x_max < x_min as f64 || y_max < y_min as f64 {
+            return;
+        }
+
+        for y in y_min..=(y_max as u32) {
+            for x in x_min..=(x_max as u32) {
+                let w = filter.evaluate(x as f64 + 0.5 - fx, y as f64 + 0.5 - fy);
+                if w == 0.0 {
+                    continue;
+                }
+
+                if let Some(i) = self.index(x, y) {
+                    self.sum[i] += color * w;
+                    self.weight[i] += w;
+                    self.plain_sum[i] += color;
+                    self.splats[i] += 1;
+
+                    if let Some(aov) = aov {
+                        let (dx, dy) = (x as f64 + 0.5 - fx, y as f64 + 0.5 - fy);
+                        self.splat_aovs(i, aov, w, dx * dx + dy * dy);
+                    }
+                }
+            }
+        }
+    }
+
+    fn splat_aovs(&mut self, i: usize, sample: &AovSample, w: f64, distance_squared: f64) {
+        let nearest = distance_squared < self.nearest[i];
+        if nearest {
+            self.nearest[i] = distance_squared;
+        }
+
+        let base = i * self.aovs.len();
+        for (k, aov) in self.aovs.iter().enumerate() {
+            if !aov.is_id() {
+                self.aov_sum[base + k] += sample.get(*aov) * w;
+                self.plain_aov_sum[base + k] += sample.get(*aov);
+            } else if nearest {
+                self.aov_sum[base + k] = sample.get(*aov);
+            }
+        }
+    }
+
+    pub fn add_stats(&mut self, x: u32, y: u32, stats: &PixelStats) {
+        if let Some(i) = self.index(x, y) {
+            self.stats[i] = self.stats[i].combined(stats);
+        }
+    }
+
+    // Samples traced so far for pixel (x, y), none outside this buffer
+    pub fn stats(&self, x: u32, y: u32) -> PixelStats {
+        self.index(x, y).map_or(PixelStats::default(), |i| self.stats[i])
+    }
+
+    // Adds the sums of `other` over the region both buffers cover
+    pub fn merge(&mut self, other: &FrameBuffer) {
+        let (x0, y0, x1, y1) = other.bounds();
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if let (Some(i), Some(j)) = (self.index(x, y), other.index(x, y)) {
+                    self.sum[i] += other.sum[j];
+                    self.weight[i] += other.weight[j];
+                    self.plain_sum[i] += other.plain_sum[j];
+                    self.splats[i] += other.splats[j];
+                    self.stats[i] = self.stats[i].combined(&other.stats[j]);
+
+                    if !self.aovs.is_empty() && self.aovs == other.aovs {
+                        let nearest = other.nearest[j] < self.nearest[i];
+                        if nearest {
+                            self.nearest[i] = other.nearest[j];
+                        }
+
+                        let n = self.aovs.len();
+                        for (k, aov) in self.aovs.iter().enumerate() {
+                            if !aov.is_id() {
+                                self.aov_sum[i * n + k] += other.aov_sum[j * n + k];
+                                self.plain_aov_sum[i * n + k] += other.plain_aov_sum[j * n + k];
+                            } else if nearest {
+                                self.aov_sum[i * n + k] = other.aov_sum[j * n + k];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Weighted mean where the filter weights add up to something positive,
+    // otherwise the plain mean of the samples. Black where nothing has landed.
+    fn resolve(&self, i: usize, sum: Color, plain_sum: Color) -> Color {
+        if self.weight[i] > MIN_WEIGHT {
+            sum / self.weight[i]
+        } else if self.splats[i] > 0 {
+            plain_sum / self.splats[i] as f64
+        } else {
+            Color::new_empty()
+        }
+    }
+
+    // Reconstructed colour of pixel (x, y), black where nothing has landed yet
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        match self.index(x, y) {
+            Some(i) => self.resolve(i, self.sum[i], self.plain_sum[i]),
+            None => Color::new_empty()
+        }
+    }
+
+    // Filtered value of `aov` at pixel (x, y), zero when it is not enabled
+    pub fn aov(&self, x: u32, y: u32, aov: Aov) -> Color {
+        let (i, k) = match (self.index(x, y), self.aovs.iter().position(|a| *a == aov)) {
+            (Some(i), Some(k)) => (i, k),
+            _ => return Color::new_empty()
+        };
+
+        let j = i * self.aovs.len() + k;
+        if aov.is_id() {
+            self.aov_sum[j]
+        } else {
+            self.resolve(i, self.aov_sum[j], self.plain_aov_sum[j])
+        }
+    }
+
+    // 8 bit preview of an AOV
+    pub fn aov_image(&self, aov: Aov) -> RgbaImage {
+        let pixels = || (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x, y)));
+        let depth_range = if aov == Aov::Depth {
+            pixels().map(|(x, y)| self.aov(self.x0 + x, self.y0 + y, aov).x()).fold(0.0, f64::max)
+        } else {
+            1.0
+        };
+
+        let mut image = RgbaImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let c = preview_color(aov, &self.aov(self.x0 + x, self.y0 + y, aov), depth_range);
+            *pixel = Rgba([
+                (c.x().clamp(0.0, 1.0) * 255.0) as u8,
+                (c.y().clamp(0.0, 1.0) * 255.0) as u8,
+                (c.z().clamp(0.0, 1.0) * 255.0) as u8,
+                255]);
+        }
+
+        image
+    }
+
+    pub fn rgba(&self, x: u32, y: u32) -> Rgba<u8> {
+        color_to_rgba(&self.color(x, y))
+    }
+
+    pub fn to_image(&self) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            *pixel = self.rgba(self.x0 + x, self.y0 + y);
+        }
+
+        image
+    }
+
+    // Samples per pixel as colours from blue for the fewest through green to
+    // red for the most
+    pub fn sample_heatmap(&self) -> RgbaImage {
+        let min = self.stats.iter().map(|stats| stats.count).min().unwrap_or(0) as f64;
+        let max = self.stats.iter().map(|stats| stats.count).max().unwrap_or(0) as f64;
+        let range = (max - min).max(1.0);
+
+        let mut image = RgbaImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let t = (self.stats[(y * self.width + x) as usize].count as f64 - min) / range;
+            let color = if t < 0.5 {
+                Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+            } else {
+                Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+            };
+
+            *pixel = Rgba([(color.x() * 255.0) as u8, (color.y() * 255.0) as u8, (color.z() * 255.0) as u8, 255]);
+        }
+
+        image
+    }
+}
+
+// Gamma 2 encoded 8 bit pixel of a linear colour
+pub fn color_to_rgba(color: &Color) -> Rgba<u8> {
+    let ir = (color.x().max(0.0).sqrt().clamp(0.0, 0.999) * 256.0) as u8;
+    let ig = (color.y().max(0.0).sqrt().clamp(0.0, 0.999) * 256.0) as u8;
+    let ib = (color.z().max(0.0).sqrt().clamp(0.0, 0.999) * 256.0) as u8;
+
+    Rgba([ir, ig, ib, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_averages_samples() {
+        let mut buffer = FrameBuffer::new(1, 1);
+        let filter = Filter::Box { radius: 0.5 };
+        buffer.splat(0.25, 0.25, Color::new(1.0, 0.0, 0.0), None, &filter);
+        buffer.splat(0.75, 0.75, Color::new(0.0, 1.0, 0.0), None, &filter);
+
+        let c = buffer.color(0, 0);
+        assert!((c.x() - 0.5).abs() < 1e-12 && (c.y() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn negative_weight_falls_back_to_plain_mean() {
+        // 1.5 pixels off centre Mitchell's lobe is negative
+        let mut buffer = FrameBuffer::new(1, 1);
+        let filter = Filter::new_mitchell(2.0);
+        buffer.splat(2.0, 0.5, Color::new(0.5, 0.5, 0.5), None, &filter);
+
+        let c = buffer.color(0, 0);
+        assert!((c.x() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn merge_matches_single_buffer() {
+        let filter = Filter::default();
+        let mut whole = FrameBuffer::new(4, 4);
+        let mut left = FrameBuffer::new_region(0, 0, 2, 4);
+        let mut right = FrameBuffer::new_region(2, 0, 4, 4);
+
+        for (fx, fy, v) in [(0.3, 0.7, 0.2), (1.9, 2.2, 0.8), (2.1, 3.5, 0.4)] {
+            let color = Color::new(v, v, v);
+            whole.splat(fx, fy, color, None, &filter);
+            left.splat(fx, fy, color, None, &filter);
+            right.splat(fx, fy, color, None, &filter);
+        }
+
+        let mut merged = FrameBuffer::new(4, 4);
+        merged.merge(&left);
+        merged.merge(&right);
+        for y in 0..4 {
+            for x in 0..4 {
+                assert!((merged.color(x, y) - whole.color(x, y)).length() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn combined_stats_match_adding_one_by_one() {
+        let values = [0.1, 0.7, 0.3, 0.9, 0.2];
+        let mut all = PixelStats::default();
+        let (mut first, mut second) = (PixelStats::default(), PixelStats::default());
+        for (i, value) in values.iter().enumerate() {
+            all.add(*value);
+            if i < 2 { first.add(*value) } else { second.add(*value) }
+        }
+
+        let combined = first.combined(&second);
+        assert_eq!(combined.count, all.count);
+        assert!((combined.mean - all.mean).abs() < 1e-12);
+        assert!((combined.m2 - all.m2).abs() < 1e-12);
+    }
+}
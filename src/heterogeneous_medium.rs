@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use crate::{Color, HitRecord, Hittable, Materials, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::cuboid::Cuboid;
+use crate::medium::{inside_range, PhaseFunction};
+use crate::texture::Texture;
+use crate::voxel_grid::{MajorantGrid, VoxelGrid};
+use crate::sampler::Sampler;
+
+// Voxels per majorant cell along each axis
+const MAJORANT_BLOCK: usize = 8;
+
+// Volume whose density is read from a voxel grid, clipped to a closed boundary.
+// Scattering distances are sampled with delta tracking and transmittance is
+// estimated with ratio tracking, both stepping through a coarse majorant grid.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hittable>,
+    grid: Arc<VoxelGrid>,
+    majorants: MajorantGrid,
+    density_scale: f64,
+    phase_material: Materials
+}
+
+impl HeterogeneousMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, grid: Arc<VoxelGrid>, density_scale: f64, albedo: Color,
+               phase: PhaseFunction) -> HeterogeneousMedium {
+        HeterogeneousMedium {
+            boundary,
+            majorants: MajorantGrid::new(&grid, MAJORANT_BLOCK),
+            grid,
+            density_scale,
+            phase_material: Materials::Medium {
+                albedo: Texture::SolidColor { color_value: albedo },
+                phase
+            }
+        }
+    }
+
+    // Uses the bounds of the grid itself as the boundary
+    pub fn new_in_bounds(grid: Arc<VoxelGrid>, density_scale: f64, albedo: Color, phase: PhaseFunction) -> HeterogeneousMedium {
+        let boundary = Arc::new(Cuboid::new_with(
+            grid.bounds().min(),
+            grid.bounds().max(),
+            Materials::Medium { albedo: Texture::SolidColor { color_value: albedo }, phase }
+        ));
+
+        HeterogeneousMedium::new(boundary, grid, density_scale, albedo, phase)
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        let (t1, t2) = match inside_range(self.boundary.as_ref(), ray, t_min, t_max, sampler) {
+            Some(range) => range,
+            None => return false
+        };
+
+        let ray_length = ray.dir().length();
+        let mut scatter_t = None;
+
+        // Delta tracking: sample tentative collisions against the local majorant and
+        // accept each one as real with probability density / majorant
+        self.majorants.traverse(ray, t1, t2, |seg_t0, seg_t1, majorant| {
+            let sigma_max = majorant * self.density_scale;
+            if sigma_max <= 0.0 {
+                return true;
+            }
+
+            let mut t = seg_t0;
+            loop {
+                t -= (1.0 - sampler.get_1d()).ln() / (sigma_max * ray_length);
+                if t >= seg_t1 {
+                    return true;
+                }
+
+                let density = self.grid.density(&ray.at(t)) * self.density_scale;
+                if sampler.get_1d() * sigma_max < density {
+                    scatter_t = Some(t);
+                    return false;
+                }
+            }
+        });
+
+        let t = match scatter_t {
+            Some(t) => t,
+            None => return false
+        };
+
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.normal = Vec3::new(1, 0, 0);
+        rec.front_face = true;
+        rec.dpdu = Vec3::new_empty();
+        rec.dpdv = Vec3::new_empty();
+        rec.material = self.phase_material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        let (t1, t2) = match inside_range(self.boundary.as_ref(), ray, t_min, t_max, sampler) {
+            Some(range) => range,
+            None => return 1.0
+        };
+
+        let ray_length = ray.dir().length();
+        let mut transmittance = 1.0;
+
+        // Ratio tracking: weight by the null collision probability at every
+        // tentative collision, with russian roulette once little light is left
+        self.majorants.traverse(ray, t1, t2, |seg_t0, seg_t1, majorant| {
+            let sigma_max = majorant * self.density_scale;
+            if sigma_max <= 0.0 {
+                return true;
+            }
+
+            let mut t = seg_t0;
+            loop {
+                t -= (1.0 - sampler.get_1d()).ln() / (sigma_max * ray_length);
+                if t >= seg_t1 {
+                    return true;
+                }
+
+                let density = self.grid.density(&ray.at(t)) * self.density_scale;
+                transmittance *= 1.0 - (density / sigma_max).min(1.0);
+
+                if transmittance < 0.1 {
+                    if sampler.get_1d() < 0.5 {
+                        transmittance = 0.0;
+                        return false;
+                    }
+                    transmittance *= 2.0;
+                }
+            }
+        });
+
+        transmittance
+    }
+}
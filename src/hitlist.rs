@@ -1,24 +1,25 @@
 use std::sync::Arc;
-use crate::hittable::{HitRecord, Hittable};
-use crate::{Ray};
+use crate::hittable::{object_id, HitRecord, Hittable};
+use crate::{Point3, Ray, Vec3};
 use crate::aabb::AABB;
+use crate::sampler::Sampler;
 
 pub struct HitList {
     pub objects: Vec<Arc<dyn Hittable>>
 }
 
-unsafe impl Sync for HitList {}
-
 impl Hittable for HitList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
         let mut temp_rec = HitRecord::default();
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
 
-        for object in &self.objects {
-            if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
+        for (i, object) in self.objects.iter().enumerate() {
+            temp_rec.object_id = 0;
+            if object.hit(r, t_min, closest_so_far, &mut temp_rec, sampler) {
+                temp_rec.object_id = object_id(i, temp_rec.object_id);
                 hit_anything = true;
-                closest_so_far = temp_rec.t.clone();
+                closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
             }
         }
@@ -39,17 +40,43 @@ impl Hittable for HitList {
                 return false;
             }
 
-            *output_box = if first_box { temp_box.clone() } else {AABB::surrounding_box(output_box, &temp_box)};
+            *output_box = if first_box { temp_box } else {AABB::surrounding_box(output_box, &temp_box)};
             first_box = false;
         }
 
         true
     }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        let mut transmittance = 1.0;
+        for object in &self.objects {
+            transmittance *= object.transmittance(ray, t_min, t_max, sampler);
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+
+        transmittance
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3, sampler: &mut dyn Sampler) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects.iter().map(|object| weight * object.pdf_value(origin, dir, sampler)).sum()
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let index = ((sampler.get_1d() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin, sampler)
+    }
 }
 
 impl HitList {
-    pub fn clear(&mut self) {
-        self.objects.clear();
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
     }
 
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
@@ -1,6 +1,6 @@
-use std::sync::Arc;
 use crate::{Color, Materials, Point3, Ray, Vec3};
 use crate::aabb::AABB;
+use crate::sampler::Sampler;
 use crate::texture::Texture::SolidColor;
 
 #[derive(Clone)]
@@ -10,8 +10,15 @@ pub struct HitRecord {
     pub t: f64,
     pub u: f64,
     pub v: f64,
+    // Change in position per unit change of u and v, zero when not known
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // Width of the pixel footprint in texture space, zero when not known
+    pub uv_footprint: f64,
     pub front_face: bool,
-    pub material: Materials
+    pub material: Materials,
+    // Object for the object id AOV, zero when no container has set it
+    pub object_id: u32
 }
 
 impl HitRecord {
@@ -19,6 +26,23 @@ impl HitRecord {
         self.front_face = ray.dir().dot(outward_normal) < 0.0;
         self.normal = if self.front_face { *outward_normal } else { *outward_normal * -1.0 }
     }
+
+    // Estimates how much of the texture the ray's cone covers at the hit, so
+    // image textures can pick a matching mip level
+    pub fn set_uv_footprint(&mut self, ray: &Ray) {
+        let tangent_length = self.dpdu.length().max(self.dpdv.length());
+        if ray.spread() <= 0.0 || tangent_length <= 0.0 {
+            self.uv_footprint = 0.0;
+            return;
+        }
+
+        // Grazing hits stretch the footprint along the surface
+        let dir = ray.dir().normalized();
+        let cosine = dir.dot(&self.normal).abs().max(0.05);
+        let width = ray.spread() * self.t * ray.dir().length() / cosine;
+
+        self.uv_footprint = width / tangent_length;
+    }
 }
 
 impl Default for HitRecord {
@@ -29,13 +53,62 @@ impl Default for HitRecord {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            dpdu: Vec3::new_empty(),
+            dpdv: Vec3::new_empty(),
+            uv_footprint: 0.0,
             front_face: false,
-            material: Materials::Lambertian {albedo: SolidColor{color_value: Color::new_empty()}}
+            material: Materials::Lambertian {albedo: SolidColor{color_value: Color::new_empty()}},
+            object_id: 0
         }
     }
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
+// Id of a scene object for the object id AOV: non-zero and exact in a float
+// channel. Objects are numbered by their position in the list or BVH holding
+// them, combined with `inner`, the id they got further down the hierarchy,
+// so ids only change when the scene does.
+pub fn object_id(index: usize, inner: u32) -> u32 {
+    let h = (((index as u64 + 1) << 24) | inner as u64).wrapping_mul(0xbf58476d1ce4e5b9);
+    (((h >> 32) & 0xffffff) as u32).max(1)
+}
+
+pub trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool;
-}
\ No newline at end of file
+
+    // Fraction of light that makes it along `ray` between `t_min` and `t_max`.
+    // Surfaces block it completely, participating media attenuate it.
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        let mut rec = HitRecord::default();
+        if self.hit(ray, t_min, t_max, &mut rec, sampler) { 0.0 } else { 1.0 }
+    }
+
+    // Solid angle density of sampling `dir` from `origin` with `random`.
+    // Only shapes that can be used as area lights need to implement these.
+    fn pdf_value(&self, _origin: &Point3, _dir: &Vec3, _sampler: &mut dyn Sampler) -> f64 {
+        0.0
+    }
+
+    fn random(&self, _origin: &Point3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1, 0, 0)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_ids_are_stable_and_distinct() {
+        let ids: Vec<u32> = (0..1000).map(|i| object_id(i, 0)).collect();
+        assert_eq!(ids, (0..1000).map(|i| object_id(i, 0)).collect::<Vec<u32>>());
+
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+
+        // Non-zero and exact in an f32 channel
+        assert!(ids.iter().all(|&id| id > 0 && id < 1 << 24));
+        assert_ne!(object_id(0, object_id(1, 0)), object_id(1, object_id(0, 0)));
+    }
+}
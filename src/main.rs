@@ -11,122 +11,278 @@ mod aabb;
 mod bvh;
 mod texture;
 mod triangle;
+mod ply;
+mod transform;
+mod onb;
+mod plane;
+mod quad;
+mod disk;
+mod cuboid;
+mod cylinder;
+mod cone;
+mod torus;
+mod moving_sphere;
+mod medium;
+mod constant_medium;
+mod voxel_grid;
+mod heterogeneous_medium;
+mod perlin;
+mod mipmap;
+mod texture_cache;
+mod orthographic;
+mod fisheye;
+mod panorama;
+mod camera_path;
+mod film;
+mod filter;
+mod framebuffer;
+mod sampler;
+mod aov;
+mod denoise;
+#[cfg(feature = "viewer")]
+mod viewer;
 
-use std::sync::{Arc, Mutex};
-use glium::*;
-use std::time::{Duration, Instant};
-use glium::glutin::dpi::PhysicalSize;
-use glium::texture::RawImage2d;
-use image::{RgbaImage};
 use crate::hitlist::HitList;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
 use crate::sphere::Sphere;
 use crate::vec3::{Color, Point3, Vec3};
-use crate::camera::Camera;
-use crate::glutin::window::Fullscreen;
 use crate::material::{Materials};
-use crate::raytrace::RTParams;
+use crate::raytrace::{AdaptiveSampling, RTParams};
+use crate::aov::{Aov, AovOutput};
+use crate::camera_path::CameraPath;
+use crate::denoise::Denoiser;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::sampler::SamplerKind;
+use crate::mipmap::{FilterMode, WrapMode};
+use crate::texture_cache::TextureCache;
+use crate::aabb::AABB;
+use crate::voxel_grid::{VolumeError, VoxelGrid};
 
-#[derive(Copy, Clone)]
-struct TexVertex {
-    position: [f32; 2],
-    tex_coords: [f32; 2],
-}
-
-implement_vertex!(TexVertex, position, tex_coords);
-
-static ASPECT_RATIO: f64 = 16.0 / 9.0;
 static IMAGE_WIDTH: u32 = 2560;
-static IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
+static IMAGE_HEIGHT: u32 = 1440;
 static SAMPLES_PER_PIXEL: u32 = 50;
 static MAX_DEPTH: i32 = 5;
+static SEQUENCE_FRAMES: u32 = 48;
+// Memory all image textures together may take up, in bytes
+static TEXTURE_BUDGET: usize = 256 * 1024 * 1024;
+
+// Value of `--name=value` or `--name value` on the command line
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if *arg == flag {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&flag).and_then(|rest| rest.strip_prefix('=')).map(str::to_string)
+        }
+    })
+}
+
+// Grid for --volume. Raw grids have no header, so their size comes from
+// `dims`; they are scaled to four units along their longest side.
+fn load_volume(path: &str, dims: Option<&str>) -> Result<VoxelGrid, VolumeError> {
+    let dims = match dims {
+        Some(dims) => dims,
+        None => return VoxelGrid::load_sparse(path)
+    };
+
+    let dims: Vec<usize> = dims.split('x').filter_map(|d| d.parse().ok()).collect();
+    let dims: [usize; 3] = dims.try_into()
+        .map_err(|_| VolumeError::Format(String::from("--dims must look like 64x64x64")))?;
+
+    let longest = *dims.iter().max().unwrap_or(&1) as f64;
+    let half = |d: usize| 2.0 * d as f64 / longest;
+    let bounds = AABB::new(
+        &Point3::new(-half(dims[0]), 0.0, -half(dims[2])),
+        &Point3::new(half(dims[0]), 2.0 * half(dims[1]), half(dims[2])));
+
+    VoxelGrid::load_raw(path, dims, bounds)
+}
 
 fn main() {
-    // Create our image object and wrap it within Arc<Mutex>
-    let image = RgbaImage::new(IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32);
-    let shared_image = Arc::new(Mutex::new(image));
-
-    // Initialize window and opengl context
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let mut wb = glutin::window::WindowBuilder::new()
-        .with_inner_size(PhysicalSize { width: IMAGE_WIDTH, height: IMAGE_HEIGHT })
-        .with_fullscreen(Some(Fullscreen::Borderless(None)));
-    let cb = glutin::ContextBuilder::new();
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
-
-    // Create square shape to pass to GPU
-    let shape = vec![
-        TexVertex { position: [-1.0, 1.0], tex_coords: [0.0, 1.0] },
-        TexVertex { position: [1.0, 1.0], tex_coords: [1.0, 1.0] },
-        TexVertex { position: [-1.0, -1.0], tex_coords: [0.0, 0.0] },
-        TexVertex { position: [1.0, -1.0], tex_coords: [1.0, 0.0] }
-    ];
-
-    // Create vertex buffer for shape
-    let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
-
-    // Create index buffer
-    let indices: [u16; 6] = [0, 1, 2, 1, 2, 3];
-    let index_buffer = glium::IndexBuffer::new(&display,
-                                               glium::index::PrimitiveType::TrianglesList,
-                                               &indices).unwrap();
-
-    // Compile the shader program
-    let vertex_shader_src = include_str!("shaders/vert.vs");
-    let fragment_shader_src = include_str!("shaders/frag.fs");
-    let program = glium::Program::from_source(&display,
-                                              vertex_shader_src,
-                                              fragment_shader_src,
-                                              None).unwrap();
-
-    // Create another handle to the image and run the RT on another thread
-    let image_copy = shared_image.clone();
-    std::thread::spawn(move || {
-        raytrace::run_rt(RTParams::new(
-                ASPECT_RATIO,
-                IMAGE_WIDTH,
-                SAMPLES_PER_PIXEL,
-                MAX_DEPTH),
-                         &scene::random_scene(), image_copy);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // --denoise also saves a denoised copy of every pass as output_denoised.png,
+    // with the best denoiser this build has; --denoise=oidn or
+    // --denoise=bilateral picks one
+    let denoiser = args.iter().find_map(|arg| match arg.as_str() {
+        "--denoise" => Some(Denoiser::best_available()),
+        _ => arg.strip_prefix("--denoise=").map(|name| {
+            Denoiser::from_name(name).unwrap_or_else(|| {
+                println!("Unknown denoiser {}, using the default", name);
+                Denoiser::best_available()
+            })
+        })
     });
 
-    event_loop.run(move |ev, _, control_flow| {
-        match ev {
-            glutin::event::Event::WindowEvent { event, .. } => match event {
-                glutin::event::WindowEvent::CloseRequested => {
-                    *control_flow = glutin::event_loop::ControlFlow::Exit;
-                    return;
+    // The camera takes its aspect ratio from the film, so any resolution renders undistorted
+    let mut film = Film::new(IMAGE_WIDTH, IMAGE_HEIGHT);
+
+    // --pixel-aspect <ratio> renders for pixels that are ratio times as wide
+    // as they are tall, e.g. 2 for footage shot with a 2x anamorphic lens
+    if let Some(value) = arg_value(&args, "pixel-aspect") {
+        match value.parse::<f64>() {
+            Ok(ratio) if ratio > 0.0 => film = film.with_pixel_aspect(ratio),
+            _ => println!("Invalid pixel aspect {}, using square pixels", value)
+        }
+    }
+
+    // --crop x0,x1,y0,y1 renders only that window of the film, given as
+    // fractions of its width and height from the top left
+    if let Some(value) = arg_value(&args, "crop") {
+        let window: Vec<f64> = value.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+        match window[..] {
+            [x_min, x_max, y_min, y_max] => film = film.with_crop(x_min, x_max, y_min, y_max),
+            _ => println!("Invalid crop window {}, rendering the whole film", value)
+        }
+    }
+
+    let mut params = RTParams::new(film, SAMPLES_PER_PIXEL, MAX_DEPTH).with_denoise(denoiser);
+
+    // --filter box|tent|gaussian|mitchell|blackman-harris picks the pixel
+    // reconstruction filter
+    if let Some(name) = arg_value(&args, "filter") {
+        match Filter::from_name(&name) {
+            Some(filter) => params = params.with_filter(filter),
+            None => println!("Unknown filter {}, using the default", name)
+        }
+    }
+
+    // --sampler independent|stratified|halton|sobol|blue-noise picks where
+    // the random numbers of every sample come from
+    if let Some(name) = arg_value(&args, "sampler") {
+        match SamplerKind::from_name(&name) {
+            Some(sampler) => params = params.with_sampler(sampler),
+            None => println!("Unknown sampler {}, using the default", name)
+        }
+    }
+
+    // --adaptive <threshold> stops sampling pixels once their noise is below
+    // the threshold, e.g. 0.01; --heatmap also saves the samples each pixel
+    // took as output_samples.png
+    if let Some(value) = arg_value(&args, "adaptive") {
+        match value.parse::<f64>() {
+            Ok(threshold) if threshold > 0.0 => {
+                let mut adaptive = AdaptiveSampling::new_with(SAMPLES_PER_PIXEL, threshold);
+                adaptive.heatmap = args.iter().any(|arg| arg == "--heatmap");
+                params = params.with_adaptive(adaptive);
+            }
+            _ => println!("Invalid adaptive threshold {}, sampling every pixel fully", value)
+        }
+    }
+
+    // --aov albedo,normal,... or --aov all saves those AOVs next to
+    // output.png, as one PNG each or with --exr as layers of output.exr
+    if let Some(list) = arg_value(&args, "aov") {
+        let aovs: Vec<Aov> = if list == "all" {
+            Aov::ALL.to_vec()
+        } else {
+            list.split(',').filter_map(|name| {
+                let aov = Aov::from_name(name.trim());
+                if aov.is_none() {
+                    println!("Unknown AOV {}, skipping it", name);
                 }
-                glutin::event::WindowEvent::KeyboardInput { input, ..} => {
-                    if input.scancode == 1 {
-                        *control_flow = glutin::event_loop::ControlFlow::Exit;
-                        return;
-                    }
+                aov
+            }).collect()
+        };
+        let output = if args.iter().any(|arg| arg == "--exr") { AovOutput::MultiLayerExr } else { AovOutput::Images };
+        params = params.with_aovs(&aovs, output);
+    }
+
+    // --scene random|triangles|shapes|motion|fog|volume|noise|bump|textures|
+    // lens|orthographic|fisheye|equirectangular|stereo picks one of the built-in
+    // scenes, --ply <file> renders that mesh instead and --volume <file> a
+    // density grid: an SVOX sparse grid, or with --dims WxHxD a raw dense one
+    let built_in = match arg_value(&args, "scene") {
+        Some(name) => scene::from_name(&name).unwrap_or_else(|| {
+            println!("Unknown scene {}, using the default", name);
+            scene::random_scene
+        }),
+        None => scene::random_scene
+    };
+    let ply = arg_value(&args, "ply");
+    let volume = arg_value(&args, "volume").and_then(|path| {
+        match load_volume(&path, arg_value(&args, "dims").as_deref()) {
+            Ok(grid) => Some(grid),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        }
+    });
+    // --texture-filter nearest|bilinear|trilinear and --texture-wrap
+    // repeat|clamp|mirror change how the scenes' image textures are sampled
+    let mut textures = TextureCache::new().with_budget(TEXTURE_BUDGET);
+    if let Some(name) = arg_value(&args, "texture-filter") {
+        match FilterMode::from_name(&name) {
+            Some(filter) => textures = textures.with_filter(filter),
+            None => println!("Unknown texture filter {}, using the default", name)
+        }
+    }
+    if let Some(name) = arg_value(&args, "texture-wrap") {
+        match WrapMode::from_name(&name) {
+            Some(wrap) => textures = textures.with_wrap(wrap),
+            None => println!("Unknown texture wrap mode {}, using the default", name)
+        }
+    }
+
+    let build_scene = move |film: &Film| match (ply, volume) {
+        (Some(path), _) => scene::ply_scene(film, &path),
+        (None, Some(grid)) => scene::volume_scene(film, grid),
+        (None, None) => built_in(film, &textures)
+    };
+
+    // --sequence <dir> renders an animation into dir instead, --frames N
+    // frames of it (48 by default). The camera stays where the scene put it
+    // while the frames step through the scene's motion, with --turntable
+    // circles once around the point it looks at, or with --camera-path <file>
+    // follows the keys in that file (see CameraPath::load_keyframes).
+    if let Some(dir) = arg_value(&args, "sequence") {
+        let frames = match arg_value(&args, "frames").map(|value| value.parse::<u32>()) {
+            Some(Ok(frames)) if frames > 0 => frames,
+            Some(_) => {
+                println!("Invalid frame count, rendering {} frames", SEQUENCE_FRAMES);
+                SEQUENCE_FRAMES
+            }
+            None => SEQUENCE_FRAMES
+        };
+
+        let mut scene = build_scene(&film);
+        let keyframes = arg_value(&args, "camera-path").and_then(|file| {
+            match CameraPath::load_keyframes(&file, film.aspect_ratio()) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    println!("{}", e);
+                    None
                 }
-                _ => return,
-            },
-            glutin::event::Event::NewEvents(cause) => match cause {
-                glutin::event::StartCause::ResumeTimeReached { .. } => (),
-                glutin::event::StartCause::Init => (),
-                _ => return,
+            }
+        });
+        let path = keyframes.or_else(|| scene.camera.view().map(|key| {
+            if args.iter().any(|arg| arg == "--turntable") {
+                CameraPath::new_orbit(&key, film.aspect_ratio())
+            } else {
+                CameraPath::new_still(key, film.aspect_ratio())
+            }
+        }));
+
+        match path {
+            Some(path) => if let Err(e) = raytrace::render_sequence(&params, &mut scene, &path, frames, dir) {
+                println!("Error saving image: {}", e);
             },
-            _ => return,
+            None => println!("The scene's camera cannot follow a camera path")
         }
+        return;
+    }
 
-        let image = shared_image.lock().unwrap().clone();
-        let dimensions = image.dimensions();
-        let gpu_image = RawImage2d::from_raw_rgba_reversed(&image.into_raw(), dimensions);
-        let texture = glium::texture::SrgbTexture2d::new(&display, gpu_image).unwrap();
+    // The viewer renders progressively from wherever its camera is moved to
+    #[cfg(feature = "viewer")]
+    viewer::run_viewer(params, build_scene);
 
-        let mut target = display.draw();
-        target.clear_color(0.0, 0.0, 1.0, 1.0);
-        target.draw(&vertex_buffer, &index_buffer, &program,
-                    &uniform! { tex: &texture },
-                    &Default::default()).unwrap();
-        target.finish().unwrap();
-
-        let next_frame_time = Instant::now() + Duration::from_millis(16);
-        *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
-    });
+    // Without it the image is rendered once on this thread and saved
+    #[cfg(not(feature = "viewer"))]
+    if let Err(e) = raytrace::run_rt(&params, &build_scene(&film)) {
+        println!("Error saving image: {}", e);
+    }
 }
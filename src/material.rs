@@ -1,11 +1,13 @@
+use std::collections::hash_map::DefaultHasher;
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use crate::{Point3, Vec3};
 use crate::{Color, HitRecord, Ray};
-use rand;
-use rand::Rng;
-use crate::texture::Texture;
+use crate::medium::PhaseFunction;
+use crate::sampler::Sampler;
+use crate::texture::{TexCoord, Texture};
 
-#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Materials {
     Lambertian{
@@ -20,26 +22,61 @@ pub enum Materials {
     },
     DiffuseLight {
         tex: Texture
+    },
+    Medium {
+        albedo: Texture,
+        phase: PhaseFunction
+    },
+    // Replaces the shading normal from a tangent space normal map, then shades
+    // with `base`. `strength` scales the tilt away from the surface normal.
+    NormalMap {
+        base: Arc<Materials>,
+        map: Texture,
+        strength: f64
+    },
+    // Tilts the shading normal by the slope of a scalar height field, then
+    // shades with `base`. The height is the average of the texture's channels.
+    BumpMap {
+        base: Arc<Materials>,
+        height: Texture,
+        scale: f64
+    },
+    // Lets rays pass through wherever `opacity` says the surface is absent,
+    // for foliage and decals. Shading uses `base`.
+    Cutout {
+        base: Arc<Materials>,
+        opacity: Texture,
+        mode: AlphaMode
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub enum AlphaMode {
+    // Hits with opacity below the threshold are rejected
+    Threshold(f64),
+    // Hits are kept with probability equal to the opacity, which averages out
+    // to soft, semi-transparent edges
+    Stochastic
+}
+
 impl Materials {
-    pub(crate) fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        return match self {
+    pub(crate) fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool {
+        match self {
             Materials::Lambertian { albedo } => {
-                let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+                let mut scatter_direction = rec.normal + Vec3::unit_vector_from(sampler.get_2d());
                 if scatter_direction.near_zero() {
                     scatter_direction = rec.normal;
                 }
 
-                *scattered = Ray::new(rec.p, scatter_direction);
-                *attenuation = albedo.value(rec.u, rec.v, &rec.p);
+                *scattered = Ray::new_with_time(rec.p, scatter_direction, r_in.time());
+                *attenuation = albedo.sample(&TexCoord::from_record(rec));
 
                 true
             }
             Materials::Metal { albedo, fuzz } => {
                 let reflected = Vec3::reflect(&r_in.dir().normalized(), &rec.normal);
-                *scattered = Ray::new(rec.p, reflected + Vec3::random_in_unit_sphere() * (*fuzz));
+                let fuzz_direction = Vec3::in_unit_sphere_from(sampler.get_2d(), sampler.get_1d());
+                *scattered = Ray::new_with_time(rec.p, reflected + fuzz_direction * (*fuzz), r_in.time());
                 *attenuation = *albedo;
 
                 scattered.dir().dot(&rec.normal) > 0.0
@@ -55,36 +92,314 @@ impl Materials {
                 let cos_theta = (-unit_direction).dot(&rec.normal).min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
                 let cannot_refract = refraction_ratio * sin_theta > 1.0;
-                let direction: Vec3;
-
-                if cannot_refract
-                    || dieelectric_reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen::<f64>() {
-                    direction = Vec3::reflect(&unit_direction, &rec.normal);
+                let direction = if cannot_refract
+                    || dieelectric_reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+                    Vec3::reflect(&unit_direction, &rec.normal)
                 } else {
-                    direction = Vec3::refract(&unit_direction, &rec.normal, refraction_ratio);
-                }
+                    Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
+                };
 
-                *scattered = Ray::new(rec.p, direction);
+                *scattered = Ray::new_with_time(rec.p, direction, r_in.time());
 
                 true
             },
-            Materials::DiffuseLight {tex } => {
+            Materials::DiffuseLight { .. } => {
                 false
+            },
+            Materials::Medium { albedo, phase } => {
+                *scattered = Ray::new_with_time(rec.p, phase.sample(r_in.dir(), sampler), r_in.time());
+                *attenuation = albedo.sample(&TexCoord::from_record(rec));
+
+                true
+            }
+            Materials::NormalMap { base, .. } | Materials::BumpMap { base, .. } | Materials::Cutout { base, .. } => {
+                base.scatter(r_in, rec, attenuation, scattered, sampler)
+            }
+        }
+    }
+
+    // Whether a surface with this material is present at `coords`. Intersection
+    // routines call this before accepting a hit.
+    pub fn is_opaque_at(&self, coords: &TexCoord, sampler: &mut dyn Sampler) -> bool {
+        match self {
+            Materials::Cutout { opacity, mode, .. } => {
+                let alpha = opacity.opacity(coords);
+                match mode {
+                    AlphaMode::Threshold(threshold) => alpha >= *threshold,
+                    AlphaMode::Stochastic => sampler.get_1d() < alpha
+                }
+            },
+            Materials::NormalMap { base, .. } | Materials::BumpMap { base, .. } => base.is_opaque_at(coords, sampler),
+            _ => true
+        }
+    }
+
+    // Applies normal and bump maps to the shading normal of `rec` and replaces
+    // wrapper materials with the material underneath, so shading only sees
+    // plain materials
+    pub fn apply_shading_normal(rec: &mut HitRecord) {
+        loop {
+            let material = rec.material.clone();
+            let base = match &material {
+                Materials::NormalMap { base, map, strength } => {
+                    if let Some(normal) = normal_mapped(rec, map, *strength) {
+                        rec.normal = normal;
+                    }
+                    base
+                },
+                Materials::BumpMap { base, height, scale } => {
+                    if let Some(normal) = bump_mapped(rec, height, *scale) {
+                        rec.normal = normal;
+                    }
+                    base
+                },
+                Materials::Cutout { base, .. } => base,
+                _ => return
+            };
+
+            rec.material = (**base).clone();
+        }
+    }
+
+    // Density of `scatter` producing the direction of `scattered`. Zero for
+    // materials that scatter specularly and so cannot use light sampling.
+    pub fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        match self {
+            Materials::Lambertian { .. } => {
+                let cosine = rec.normal.dot(&scattered.dir().normalized());
+                if cosine < 0.0 { 0.0 } else { cosine / PI }
+            },
+            _ => 0.0
+        }
+    }
+
+    // Phase function of materials that scatter inside a volume
+    pub fn phase(&self) -> Option<PhaseFunction> {
+        match self {
+            Materials::Medium { phase, .. } => Some(*phase),
+            _ => None
+        }
+    }
+
+    // Id for the material AOV, non-zero and small enough for a float channel.
+    // Materials with the same parameters share an id.
+    pub fn id(&self) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(self).hash(&mut hasher);
+
+        match self {
+            Materials::Lambertian { albedo } | Materials::Medium { albedo, .. } => albedo.hash_identity(&mut hasher),
+            Materials::DiffuseLight { tex } => tex.hash_identity(&mut hasher),
+            Materials::Metal { albedo, fuzz } => {
+                albedo.e.iter().for_each(|c| c.to_bits().hash(&mut hasher));
+                fuzz.to_bits().hash(&mut hasher);
+            },
+            Materials::DiElectric { ir } => ir.to_bits().hash(&mut hasher),
+            Materials::NormalMap { base, .. } | Materials::BumpMap { base, .. } | Materials::Cutout { base, .. } => {
+                base.id().hash(&mut hasher)
             }
         }
+
+        ((hasher.finish() & 0xffffff) as u32).max(1)
     }
+
     pub fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
-        return match self {
+        match self {
             Materials::DiffuseLight { tex } => {
                 tex.value(u, v, p)
             },
+            Materials::NormalMap { base, .. } | Materials::BumpMap { base, .. } | Materials::Cutout { base, .. } => {
+                base.emitted(u, v, p)
+            },
             _ => Color::new_empty()
         }
     }
 }
 
+// Tangent and bitangent around the shading normal, following the texture's u
+// and v directions
+fn tangent_frame(rec: &HitRecord) -> Option<(Vec3, Vec3)> {
+    let n = rec.normal;
+    let tangent = rec.dpdu - n * n.dot(&rec.dpdu);
+    if tangent.near_zero() {
+        return None;
+    }
+
+    let tangent = tangent.normalized();
+    let mut bitangent = n.cross(&tangent);
+    if bitangent.dot(&rec.dpdv) < 0.0 {
+        bitangent = -bitangent;
+    }
+
+    Some((tangent, bitangent))
+}
+
+fn normal_mapped(rec: &HitRecord, map: &Texture, strength: f64) -> Option<Vec3> {
+    let (tangent, bitangent) = tangent_frame(rec)?;
+
+    // Colours in [0, 1] encode components in [-1, 1], with z along the normal
+    let c = map.sample(&TexCoord::from_record(rec));
+    let local = Vec3::new(
+        (2.0 * c.x() - 1.0) * strength,
+        (2.0 * c.y() - 1.0) * strength,
+        (2.0 * c.z() - 1.0).max(0.0)
+    );
+
+    let normal = tangent * local.x() + bitangent * local.y() + rec.normal * local.z();
+    if normal.near_zero() { None } else { Some(normal.normalized()) }
+}
+
+fn bump_mapped(rec: &HitRecord, height: &Texture, scale: f64) -> Option<Vec3> {
+    if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+        return None;
+    }
+
+    let coords = TexCoord::from_record(rec);
+    let height_at = |u: f64, v: f64, p: &Point3| {
+        height.scalar(&TexCoord { u, v, p: *p, ..coords })
+    };
+
+    // Forward differences about half a footprint wide, so the slope is filtered
+    // like the texture lookups
+    let du = (0.5 * rec.uv_footprint).max(5e-4);
+    let dv = du;
+    let h = height_at(rec.u, rec.v, &rec.p);
+    let dhdu = (height_at(rec.u + du, rec.v, &(rec.p + rec.dpdu * du)) - h) / du;
+    let dhdv = (height_at(rec.u, rec.v + dv, &(rec.p + rec.dpdv * dv)) - h) / dv;
+
+    let dpdu = rec.dpdu + rec.normal * (dhdu * scale);
+    let dpdv = rec.dpdv + rec.normal * (dhdv * scale);
+    let normal = dpdu.cross(&dpdv);
+    if normal.near_zero() {
+        return None;
+    }
+
+    // The parametrisation may be left handed, keep the side of the original normal
+    let normal = normal.normalized();
+    Some(if normal.dot(&rec.normal) < 0.0 { -normal } else { normal })
+}
+
 pub fn dieelectric_reflectance(cosine: f64, ref_idx: f64) -> f64 {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
-    return r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0);
-}
\ No newline at end of file
+    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use crate::Hittable;
+    use crate::mipmap::{FilterMode, MipMap, WrapMode};
+    use crate::sampler::IndependentSampler;
+    use crate::sphere::Sphere;
+    use crate::triangle::Triangle;
+
+    // Cutout over an image whose left half is transparent and right half opaque,
+    // read from the image's alpha channel
+    fn half_cutout(mode: AlphaMode) -> Materials {
+        let image = RgbaImage::from_fn(2, 1, |x, _| Rgba([255, 255, 255, if x == 0 { 0 } else { 255 }]));
+        let opacity = Texture::Image {
+            image: Arc::new(MipMap::from_dynamic(&DynamicImage::ImageRgba8(image), true)),
+            wrap: WrapMode::Clamp,
+            filter: FilterMode::Nearest,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0]
+        };
+
+        Materials::Cutout {
+            base: Arc::new(Materials::Lambertian { albedo: Texture::constant(0.5) }),
+            opacity,
+            mode
+        }
+    }
+
+    fn cast(object: &dyn Hittable, origin: Point3, dir: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(origin, dir);
+        if object.hit(&ray, 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler) { Some(rec) } else { None }
+    }
+
+    #[test]
+    fn cutout_rejects_transparent_triangle_hits() {
+        let triangle = Triangle::new_with(
+            Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0),
+            half_cutout(AlphaMode::Threshold(0.5)));
+
+        // Without texture coordinates u runs from the first to the second vertex
+        assert!(cast(&triangle, Point3::new(0.1, 0.1, 1.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(cast(&triangle, Point3::new(0.8, 0.1, 1.0), Vec3::new(0.0, 0.0, -1.0)).is_some());
+    }
+
+    #[test]
+    fn cutout_sphere_falls_through_to_the_far_side() {
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: half_cutout(AlphaMode::Threshold(0.5))
+        };
+
+        // Seen from +z the near side is at u = 0.25, which is cut away, and the
+        // far side at u = 0.75, which is kept
+        let rec = cast(&sphere, Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0))
+            .expect("the far side should be hit");
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+
+        // From -z the opaque side is the near one
+        let rec = cast(&sphere, Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0))
+            .expect("the near side should be hit");
+        assert!((rec.t - 4.0).abs() < 1e-9);
+
+        // Both the near and the far side of this ray are in the transparent half
+        assert!(cast(&sphere, Point3::new(-5.0, 0.0, 0.2), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn stochastic_cutout_keeps_hits_by_opacity() {
+        let material = half_cutout(AlphaMode::Stochastic);
+        let mut sampler = IndependentSampler;
+        let at = |u: f64| TexCoord::new(u, 0.5, Point3::new_empty());
+
+        let opaque = (0..1000).filter(|_| material.is_opaque_at(&at(0.75), &mut sampler)).count();
+        let transparent = (0..1000).filter(|_| material.is_opaque_at(&at(0.25), &mut sampler)).count();
+        assert_eq!(opaque, 1000);
+        assert_eq!(transparent, 0);
+    }
+
+    #[test]
+    fn normal_map_tilts_towards_the_texel() {
+        // Left texel tilts towards +u, right texel is flat
+        let texels = vec![[0.75, 0.5, 1.0, 1.0], [0.5, 0.5, 1.0, 1.0]];
+        let map = Texture::Image {
+            image: Arc::new(MipMap::new_linear(2, 1, texels, false)),
+            wrap: WrapMode::Clamp,
+            filter: FilterMode::Nearest,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0]
+        };
+        let base = Arc::new(Materials::Lambertian { albedo: Texture::constant(0.5) });
+        let material = Materials::NormalMap { base, map, strength: 1.0 };
+
+        let shade = |u: f64| {
+            let mut rec = HitRecord {
+                u,
+                v: 0.5,
+                normal: Vec3::new(0.0, 0.0, 1.0),
+                dpdu: Vec3::new(2.0, 0.0, 0.0),
+                dpdv: Vec3::new(0.0, 2.0, 0.0),
+                material: material.clone(),
+                ..Default::default()
+            };
+            Materials::apply_shading_normal(&mut rec);
+            rec
+        };
+
+        let rec = shade(0.25);
+        let expected = Vec3::new(0.5, 0.0, 1.0).normalized();
+        assert!((rec.normal - expected).length() < 1e-6, "{:?}", rec.normal);
+        assert!(matches!(rec.material, Materials::Lambertian { .. }));
+
+        let rec = shade(0.75);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-6, "{:?}", rec.normal);
+    }
+}
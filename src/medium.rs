@@ -0,0 +1,132 @@
+use std::f64::consts::PI;
+use crate::{Color, HitRecord, Hittable, Point3, Ray, Vec3};
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+
+#[derive(Debug, Copy, Clone)]
+pub enum PhaseFunction {
+    Isotropic,
+    // Positive g scatters forwards, negative g backwards
+    HenyeyGreenstein {
+        g: f64
+    }
+}
+
+impl PhaseFunction {
+    // Samples a new direction for a ray travelling along `dir`
+    pub fn sample(&self, dir: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (xi, xi_phi) = sampler.get_2d();
+
+        match self {
+            PhaseFunction::Isotropic => Vec3::unit_vector_from((xi, xi_phi)),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let g = *g;
+
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * xi
+                } else {
+                    let sqr = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+                    ((1.0 + g * g - sqr * sqr) / (2.0 * g)).clamp(-1.0, 1.0)
+                };
+
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * xi_phi;
+
+                Onb::build_from_w(dir).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+            }
+        }
+    }
+
+    // Density over the sphere of scattering by an angle with the given cosine
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein { g } => {
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+            }
+        }
+    }
+}
+
+// Homogeneous fog filling a sphere of `radius` around `center`. The camera is
+// usually inside it; rays that leave it without scattering see the sky.
+#[derive(Debug, Clone)]
+pub struct Fog {
+    pub center: Point3,
+    pub radius: f64,
+    pub density: f64,
+    pub albedo: Color,
+    pub phase: PhaseFunction
+}
+
+impl Fog {
+    // Returns the scattered ray if the fog scatters `ray` before `t_max`
+    pub fn sample_scatter(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let oc = *ray.origin() - self.center;
+        let a = ray.dir().length_squared();
+        let half_b = oc.dot(ray.dir());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let disc = half_b * half_b - a * c;
+        if disc < 0.0 {
+            return None;
+        }
+
+        let sqrt_d = disc.sqrt();
+        let t0 = ((-half_b - sqrt_d) / a).max(0.001);
+        let t1 = ((-half_b + sqrt_d) / a).min(t_max);
+        if t0 >= t1 {
+            return None;
+        }
+
+        let ray_length = a.sqrt();
+        let distance = -(1.0 - sampler.get_1d()).ln() / self.density;
+        let t = t0 + distance / ray_length;
+        if t >= t1 {
+            return None;
+        }
+
+        Some(Ray::new_with_time(ray.at(t), self.phase.sample(ray.dir(), sampler), ray.time()))
+    }
+
+    pub fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        let oc = *ray.origin() - self.center;
+        let a = ray.dir().length_squared();
+        let half_b = oc.dot(ray.dir());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let disc = half_b * half_b - a * c;
+        if disc < 0.0 {
+            return 1.0;
+        }
+
+        let sqrt_d = disc.sqrt();
+        let t0 = ((-half_b - sqrt_d) / a).max(0.0);
+        let t1 = ((-half_b + sqrt_d) / a).min(t_max);
+        if t0 >= t1 {
+            return 1.0;
+        }
+
+        (-self.density * (t1 - t0) * a.sqrt()).exp()
+    }
+}
+
+// Parametric range along the ray that lies inside the closed `boundary` of a
+// volume, clipped to `t_min..t_max` and to the front of the ray
+pub fn inside_range(boundary: &dyn Hittable, ray: &Ray, t_min: f64, t_max: f64,
+                    sampler: &mut dyn Sampler) -> Option<(f64, f64)> {
+    let mut rec1 = HitRecord::default();
+    let mut rec2 = HitRecord::default();
+
+    // Find where the ray enters and leaves the boundary
+    if !boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY, &mut rec1, sampler) {
+        return None;
+    }
+
+    if !boundary.hit(ray, rec1.t + 0.0001, f64::INFINITY, &mut rec2, sampler) {
+        return None;
+    }
+
+    let t1 = rec1.t.max(t_min).max(0.0);
+    let t2 = rec2.t.min(t_max);
+    if t1 >= t2 { None } else { Some((t1, t2)) }
+}
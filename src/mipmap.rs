@@ -0,0 +1,354 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use image::{ColorType, DynamicImage};
+use crate::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "clamp" => Some(WrapMode::Clamp),
+            "mirror" => Some(WrapMode::Mirror),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    // Bilinear lookups in the two mip levels closest to the footprint, blended
+    Trilinear
+}
+
+impl FilterMode {
+    pub fn from_name(name: &str) -> Option<FilterMode> {
+        match name {
+            "nearest" => Some(FilterMode::Nearest),
+            "bilinear" => Some(FilterMode::Bilinear),
+            "trilinear" => Some(FilterMode::Trilinear),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>
+}
+
+impl MipLevel {
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> [f32; 4] {
+        let x = wrap_coord(x, self.width, wrap);
+        let y = wrap_coord(y, self.height, wrap);
+
+        self.texels[y * self.width + x]
+    }
+
+    // Box filters 2x2 blocks into the next level down, repeating the last row
+    // or column of odd sized levels
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0f32; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    let t = self.texels[sy * self.width + sx];
+                    for c in 0..4 {
+                        sum[c] += 0.25 * t[c];
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+
+        MipLevel { width, height, texels }
+    }
+}
+
+fn wrap_coord(i: i64, size: usize, wrap: WrapMode) -> usize {
+    let n = size as i64;
+    let wrapped = match wrap {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::Clamp => i.clamp(0, n - 1),
+        WrapMode::Mirror => {
+            let m = i.rem_euclid(2 * n);
+            if m < n { m } else { 2 * n - 1 - m }
+        }
+    };
+
+    wrapped as usize
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Image stored as linear floating point colour with a full chain of
+// progressively halved levels for filtering minified lookups
+#[derive(Debug)]
+pub struct MipMap {
+    levels: Vec<MipLevel>,
+    // Whether the source had an alpha channel, otherwise alpha is always one
+    has_alpha: bool,
+    // Hash of the full resolution texels, so images with the same content can
+    // be recognised from run to run
+    content_hash: u64
+}
+
+impl MipMap {
+    // Accepts 8-bit, 16-bit and floating point images. Floating point formats are
+    // already linear, so `srgb` only applies to the integer ones.
+    pub fn from_dynamic(image: &DynamicImage, srgb: bool) -> MipMap {
+        let decode = srgb && !matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let rgba = image.to_rgba32f();
+
+        let texels = rgba.pixels()
+            .map(|pixel| {
+                let mut texel = pixel.0;
+                if decode {
+                    for value in texel.iter_mut().take(3) {
+                        *value = srgb_to_linear(*value);
+                    }
+                }
+                texel
+            })
+            .collect();
+
+        MipMap::new_linear(rgba.width() as usize, rgba.height() as usize, texels, image.color().has_alpha())
+    }
+
+    // Builds the chain from row-major linear RGBA texels, top row first
+    pub fn new_linear(width: usize, height: usize, texels: Vec<[f32; 4]>, has_alpha: bool) -> MipMap {
+        if width == 0 || height == 0 || texels.len() != width * height {
+            return MipMap { levels: Vec::new(), has_alpha, content_hash: 0 };
+        }
+
+        let mut hasher = DefaultHasher::new();
+        (width, height, has_alpha).hash(&mut hasher);
+        texels.iter().flatten().for_each(|c| c.to_bits().hash(&mut hasher));
+        let content_hash = hasher.finish();
+
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while levels.last().is_some_and(|l| l.width > 1 || l.height > 1) {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+
+        MipMap { levels, has_alpha, content_hash }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.has_alpha
+    }
+
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.levels.iter().map(|l| l.texels.len() * std::mem::size_of::<[f32; 4]>()).sum()
+    }
+
+    // Drops the most detailed levels until the chain fits in `bytes`, always
+    // keeping at least the final 1x1 level
+    pub fn fit_to_budget(&mut self, bytes: usize) {
+        while self.levels.len() > 1 && self.size_in_bytes() > bytes {
+            self.levels.remove(0);
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels.first().map_or(0, |l| l.width)
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels.first().map_or(0, |l| l.height)
+    }
+
+    // Looks up texture coordinates (u, v), with v pointing up the image.
+    // `footprint` is the width of the lookup in texture space and selects the
+    // mip level for trilinear filtering.
+    pub fn lookup(&self, u: f64, v: f64, wrap: WrapMode, filter: FilterMode, footprint: f64) -> Color {
+        let t = self.filtered(u, v, wrap, filter, footprint);
+        Color::new(t[0], t[1], t[2])
+    }
+
+    pub fn lookup_alpha(&self, u: f64, v: f64, wrap: WrapMode, filter: FilterMode, footprint: f64) -> f64 {
+        self.filtered(u, v, wrap, filter, footprint)[3]
+    }
+
+    fn filtered(&self, u: f64, v: f64, wrap: WrapMode, filter: FilterMode, footprint: f64) -> [f64; 4] {
+        if self.levels.is_empty() {
+            return [0.0; 4];
+        }
+
+        let v = 1.0 - v;
+        match filter {
+            FilterMode::Nearest => {
+                let level = &self.levels[0];
+                let x = (u * level.width as f64).floor() as i64;
+                let y = (v * level.height as f64).floor() as i64;
+                level.texel(x, y, wrap).map(|c| c as f64)
+            },
+            FilterMode::Bilinear => self.bilinear(0, u, v, wrap),
+            FilterMode::Trilinear => {
+                let texels = footprint * self.width().max(self.height()) as f64;
+                let lod = if texels > 1.0 { texels.log2() } else { 0.0 };
+                let max_level = (self.levels.len() - 1) as f64;
+                let lod = lod.min(max_level);
+
+                let lower = lod.floor() as usize;
+                let frac = lod - lower as f64;
+                if frac <= 0.0 || lower + 1 >= self.levels.len() {
+                    return self.bilinear(lower, u, v, wrap);
+                }
+
+                let a = self.bilinear(lower, u, v, wrap);
+                let b = self.bilinear(lower + 1, u, v, wrap);
+                [0, 1, 2, 3].map(|c| a[c] * (1.0 - frac) + b[c] * frac)
+            }
+        }
+    }
+
+    fn bilinear(&self, level: usize, u: f64, v: f64, wrap: WrapMode) -> [f64; 4] {
+        let level = &self.levels[level];
+
+        // Texel centres sit at half integer coordinates
+        let x = u * level.width as f64 - 0.5;
+        let y = v * level.height as f64 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let corners = [
+            (level.texel(x0, y0, wrap), (1.0 - fx) * (1.0 - fy)),
+            (level.texel(x0 + 1, y0, wrap), fx * (1.0 - fy)),
+            (level.texel(x0, y0 + 1, wrap), (1.0 - fx) * fy),
+            (level.texel(x0 + 1, y0 + 1, wrap), fx * fy)
+        ];
+
+        let mut result = [0.0; 4];
+        for (texel, weight) in corners {
+            for c in 0..4 {
+                result[c] += texel[c] as f64 * weight;
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_follows_texels() {
+        let texels = |v: f32| vec![[v, 0.5, 0.25, 1.0]; 16];
+        let a = MipMap::new_linear(4, 4, texels(0.1), false);
+        let b = MipMap::new_linear(4, 4, texels(0.1), false);
+        let c = MipMap::new_linear(4, 4, texels(0.2), false);
+
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), c.content_hash());
+    }
+
+    // Grey texel of value `v` with full alpha
+    fn grey(v: f32) -> [f32; 4] {
+        [v, v, v, 1.0]
+    }
+
+    #[test]
+    fn bilinear_weights_follow_texel_centres() {
+        // Values 0, 1 on the top row and 2, 3 on the bottom one
+        let image = MipMap::new_linear(2, 2, vec![grey(0.0), grey(1.0), grey(2.0), grey(3.0)], false);
+        let at = |u: f64, v: f64| image.lookup(u, v, WrapMode::Clamp, FilterMode::Bilinear, 0.0).x();
+
+        // Texel centres give the texel itself, v points up the image
+        assert!((at(0.25, 0.75) - 0.0).abs() < 1e-6);
+        assert!((at(0.75, 0.25) - 3.0).abs() < 1e-6);
+
+        // Halfway between all four centres is their average
+        assert!((at(0.5, 0.5) - 1.5).abs() < 1e-6);
+
+        // A quarter of the way from the first to the second centre
+        assert!((at(0.375, 0.75) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn trilinear_picks_levels_by_footprint() {
+        // Checkerboard of 0 and 1, which averages to 0.5 from the second level on
+        let texels = (0..16).map(|i| grey(((i % 4 + i / 4) % 2) as f32)).collect();
+        let image = MipMap::new_linear(4, 4, texels, false);
+        let at = |footprint: f64| image.lookup(0.375, 0.375, WrapMode::Repeat, FilterMode::Trilinear, footprint).x();
+
+        // (0.375, 0.375) is the centre of a texel set to 1
+        assert!((at(0.0) - 1.0).abs() < 1e-6);
+        // A footprint two texels wide reads the 2x2 level
+        assert!((at(0.5) - 0.5).abs() < 1e-6);
+        // Halfway between the levels in log2 blends them equally
+        assert!((at(2f64.sqrt() / 4.0) - 0.75).abs() < 1e-6);
+        // Footprints beyond the whole image stop at the last level
+        assert!((at(100.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn wrap_modes_map_outside_texels() {
+        assert_eq!([-1, 0, 3, 4, 5].map(|i| wrap_coord(i, 4, WrapMode::Repeat)), [3, 0, 3, 0, 1]);
+        assert_eq!([-1, 0, 3, 4, 5].map(|i| wrap_coord(i, 4, WrapMode::Clamp)), [0, 0, 3, 3, 3]);
+        assert_eq!([-2, -1, 0, 3, 4, 5, 8].map(|i| wrap_coord(i, 4, WrapMode::Mirror)), [1, 0, 0, 3, 3, 2, 0]);
+
+        let image = MipMap::new_linear(2, 1, vec![grey(0.0), grey(1.0)], false);
+        let at = |u: f64, wrap: WrapMode| image.lookup(u, 0.5, wrap, FilterMode::Nearest, 0.0).x();
+        assert_eq!(at(1.25, WrapMode::Repeat), 0.0);
+        assert_eq!(at(1.25, WrapMode::Clamp), 1.0);
+        assert_eq!(at(1.25, WrapMode::Mirror), 1.0);
+        assert_eq!(at(-0.25, WrapMode::Mirror), 0.0);
+    }
+
+    #[test]
+    fn srgb_images_decode_to_linear() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        // The linear toe below 0.04045
+        assert!((srgb_to_linear(0.04) - 0.04 / 12.92).abs() < 1e-7);
+
+        let pixel = image::Rgba([128u8, 128, 128, 128]);
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, pixel));
+        let decoded = MipMap::from_dynamic(&image, true);
+        let raw = MipMap::from_dynamic(&image, false);
+
+        let value = (128.0 / 255.0) as f32;
+        assert!((decoded.lookup(0.5, 0.5, WrapMode::Clamp, FilterMode::Nearest, 0.0).x() - srgb_to_linear(value) as f64).abs() < 1e-6);
+        assert!((raw.lookup(0.5, 0.5, WrapMode::Clamp, FilterMode::Nearest, 0.0).x() - value as f64).abs() < 1e-6);
+        // Alpha is never decoded
+        assert!((decoded.lookup_alpha(0.5, 0.5, WrapMode::Clamp, FilterMode::Nearest, 0.0) - value as f64).abs() < 1e-6);
+
+        // Floating point images are linear already
+        let float = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_pixel(1, 1, image::Rgb([0.5, 0.5, 0.5])));
+        assert_eq!(MipMap::from_dynamic(&float, true).lookup(0.5, 0.5, WrapMode::Clamp, FilterMode::Nearest, 0.0).x(), 0.5);
+    }
+}
@@ -0,0 +1,55 @@
+use std::borrow::Borrow;
+use crate::hittable::{HitRecord, Hittable};
+use crate::{Materials, Point3, Vec3};
+use crate::aabb::AABB;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::sphere::Sphere;
+
+// Sphere whose centre moves linearly from `center0` at `time0` to `center1` at `time1`
+#[derive(Clone)]
+pub struct MovingSphere {
+    // The sphere at `time0`
+    sphere: Sphere,
+    center1: Point3,
+    time0: f64,
+    time1: f64
+}
+
+impl MovingSphere {
+    pub fn new_with(center0: Point3, center1: Point3, time0: f64, time1: f64, radius: f64, material: Materials) -> MovingSphere {
+        MovingSphere {
+            sphere: Sphere { center: center0, radius, material },
+            center1, time0, time1
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Point3 {
+        let center0 = self.sphere.center;
+        if self.time1 <= self.time0 {
+            return center0;
+        }
+
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        center0 + (self.center1 - center0) * t
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        self.sphere.hit_at(&self.center(ray.time()), ray, t_min, t_max, rec, sampler)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        let r = Vec3::new(self.sphere.radius, self.sphere.radius, self.sphere.radius);
+        let center0 = self.center(time0);
+        let center1 = self.center(time1);
+
+        *output_box = AABB::surrounding_box(
+            &AABB::new((center0 - r).borrow(), (center0 + r).borrow()),
+            &AABB::new((center1 - r).borrow(), (center1 + r).borrow())
+        );
+
+        true
+    }
+}
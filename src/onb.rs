@@ -0,0 +1,69 @@
+use crate::Vec3;
+
+// Orthonormal basis around a direction, used to move between world space
+// and the local frame of oriented primitives
+#[derive(Debug, Copy, Clone)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3
+}
+
+impl Onb {
+    // Right handed, so u x v = w
+    pub fn build_from_w(n: &Vec3) -> Onb {
+        let w = n.normalized();
+        let a = if w.x().abs() > 0.9 { Vec3::new(0, 1, 0) } else { Vec3::new(1, 0, 0) };
+        let v = w.cross(&a).normalized();
+        let u = v.cross(&w);
+
+        Onb { u, v, w }
+    }
+
+    #[inline(always)]
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        self.u * a + self.v * b + self.w * c
+    }
+
+    #[inline(always)]
+    pub fn to_local(self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+
+    // Half extents along the world axes of a box with the given half extents in this frame
+    pub fn world_extent(&self, half: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.u.x().abs() * half.x() + self.v.x().abs() * half.y() + self.w.x().abs() * half.z(),
+            self.u.y().abs() * half.x() + self.v.y().abs() * half.y() + self.w.y().abs() * half.z(),
+            self.u.z().abs() * half.x() + self.v.z().abs() * half.y() + self.w.z().abs() * half.z()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basis_is_right_handed_and_orthonormal() {
+        let directions = [
+            Vec3::new(0, 0, 1), Vec3::new(0, 1, 0), Vec3::new(1, 0, 0), Vec3::new(-1, 0, 0),
+            Vec3::new(0, -3, 0), Vec3::new(1, 2, -3), Vec3::new(0.95, 0.1, 0.2)
+        ];
+
+        for n in directions {
+            let onb = Onb::build_from_w(&n);
+            assert!((onb.w - n.normalized()).length() < 1e-12);
+            assert!((onb.u.cross(&onb.v) - onb.w).length() < 1e-12, "{:?}", onb);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(&b).abs() < 1e-12);
+                assert!((a.length() - 1.0).abs() < 1e-12);
+            }
+
+            // Moving into the frame and back is lossless
+            let p = Vec3::new(0.3, -1.2, 2.5);
+            let local = onb.to_local(&p);
+            assert!((onb.local(local.x(), local.y(), local.z()) - p).length() < 1e-12);
+        }
+    }
+}
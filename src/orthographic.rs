@@ -0,0 +1,34 @@
+use crate::{Point3, Ray, Vec3};
+use crate::camera::{look_basis, Camera};
+use crate::sampler::Sampler;
+
+// Parallel projection of a `view_height` tall window centred on `look_from`
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3
+}
+
+impl OrthographicCamera {
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, view_height: f64, aspect_ratio: f64) -> OrthographicCamera {
+        let (u, v, w) = look_basis(&look_from, &look_at, &vup);
+        let horizontal = u * (view_height * aspect_ratio);
+        let vertical = v * view_height;
+
+        OrthographicCamera {
+            lower_left_corner: look_from - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let origin = self.lower_left_corner + self.horizontal * s + self.vertical * t;
+        Some(Ray::new(origin, self.direction))
+    }
+}
@@ -0,0 +1,93 @@
+use std::f64::consts::PI;
+use crate::{Point3, Ray, Vec3};
+use crate::camera::{look_basis, Camera};
+use crate::sampler::Sampler;
+
+// Longitude/latitude of screen position (s, t) of an equirectangular image
+// centred on the view direction, and the matching direction
+fn equirect_direction(u: &Vec3, v: &Vec3, w: &Vec3, s: f64, t: f64) -> (f64, f64, Vec3) {
+    let longitude = (s - 0.5) * 2.0 * PI;
+    let latitude = (t - 0.5) * PI;
+
+    let horizontal = *u * longitude.sin() - *w * longitude.cos();
+    let dir = horizontal * latitude.cos() + *v * latitude.sin();
+
+    (longitude, latitude, dir)
+}
+
+// Full 360 by 180 degree panorama in equirectangular layout, for environment
+// probes. Use a 2:1 image.
+#[derive(Debug)]
+pub struct EquirectangularCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3
+}
+
+impl EquirectangularCamera {
+    // `look_at` is the centre of the image
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3) -> EquirectangularCamera {
+        let (u, v, w) = look_basis(&look_from, &look_at, &vup);
+
+        EquirectangularCamera {
+            origin: look_from,
+            u, v, w
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let (_, _, dir) = equirect_direction(&self.u, &self.v, &self.w, s, t);
+        Some(Ray::new(self.origin, dir))
+    }
+
+    fn pixel_spread(&self, image_height: u32) -> f64 {
+        PI / image_height.max(1) as f64
+    }
+}
+
+// Omni-directional stereo for VR stills: two equirectangular panoramas stacked
+// with the left eye on top. Each ray starts on a circle of diameter
+// `eye_separation` tangent to its direction, like a head turning to look
+// everywhere. The separation fades out towards the poles to avoid swirls.
+// Use a 1:1 image.
+#[derive(Debug)]
+pub struct OmniStereoCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    eye_separation: f64
+}
+
+impl OmniStereoCamera {
+    // `eye_separation` is in scene units, about 0.064 for metres
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, eye_separation: f64) -> OmniStereoCamera {
+        let (u, v, w) = look_basis(&look_from, &look_at, &vup);
+
+        OmniStereoCamera {
+            origin: look_from,
+            u, v, w,
+            eye_separation
+        }
+    }
+}
+
+impl Camera for OmniStereoCamera {
+    fn ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let (eye, t) = if t >= 0.5 { (-1.0, 2.0 * t - 1.0) } else { (1.0, 2.0 * t) };
+        let (longitude, latitude, dir) = equirect_direction(&self.u, &self.v, &self.w, s, t);
+
+        // Sideways from the view direction, in the horizontal plane
+        let right = self.u * longitude.cos() + self.w * longitude.sin();
+        let offset = right * (eye * 0.5 * self.eye_separation * latitude.cos());
+
+        Some(Ray::new(self.origin + offset, dir))
+    }
+
+    fn pixel_spread(&self, image_height: u32) -> f64 {
+        2.0 * PI / image_height.max(1) as f64
+    }
+}
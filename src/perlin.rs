@@ -1,7 +1,219 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand::prelude::SliceRandom;
+use crate::{Point3, Vec3};
 
+const POINT_COUNT: usize = 256;
+
+// Settings for summing several octaves of noise. Each octave multiplies the
+// frequency by `lacunarity` and the amplitude by `gain`.
+#[derive(Debug, Copy, Clone)]
+pub struct NoiseParams {
+    pub frequency: f64,
+    pub octaves: u32,
+    pub lacunarity: f64,
+    pub gain: f64
+}
+
+impl NoiseParams {
+    pub fn new_with(frequency: f64, octaves: u32, lacunarity: f64, gain: f64) -> NoiseParams {
+        NoiseParams {
+            frequency,
+            octaves: octaves.max(1),
+            lacunarity,
+            gain
+        }
+    }
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        NoiseParams::new_with(1.0, 7, 2.0, 0.5)
+    }
+}
+
+// Gradient noise on a lattice of random unit vectors. The same seed always
+// produces the same noise, so textures are stable between renders.
+#[derive(Debug, Clone)]
 pub struct Perlin {
-    rand_float: &f64,
-    perm_x: i32,
-    perm_y: i32,
-    perm_z: i32
-}
\ No newline at end of file
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+    // Random offsets in [0, 1) used to place one Worley feature point per cell
+    feature: Vec<Vec3>
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0)
+            ).normalized())
+            .collect();
+
+        let feature = (0..POINT_COUNT)
+            .map(|_| Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()))
+            .collect();
+
+        let mut generate_perm = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+
+        let perm_x = generate_perm();
+        let perm_y = generate_perm();
+        let perm_z = generate_perm();
+
+        Perlin {
+            ranvec,
+            perm_x,
+            perm_y,
+            perm_z,
+            feature
+        }
+    }
+
+    fn hash(&self, i: i64, j: i64, k: i64) -> usize {
+        self.perm_x[(i & 255) as usize] ^ self.perm_y[(j & 255) as usize] ^ self.perm_z[(k & 255) as usize]
+    }
+
+    // Single octave of noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let i = p.x().floor();
+        let j = p.y().floor();
+        let k = p.z().floor();
+        let u = p.x() - i;
+        let v = p.y() - j;
+        let w = p.z() - k;
+
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+
+        // Hermite smoothing of the interpolation weights
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.ranvec[self.hash(i + di, j + dj, k + dk)];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+
+        accum
+    }
+
+    // Fractal sum of signed octaves, normalised back to roughly [-1, 1]
+    pub fn fbm(&self, p: &Point3, params: &NoiseParams) -> f64 {
+        self.octaves(p, params, |n| n)
+    }
+
+    // Fractal sum of absolute octaves in [0, 1], giving creased, billowy patterns
+    pub fn turbulence(&self, p: &Point3, params: &NoiseParams) -> f64 {
+        self.octaves(p, params, f64::abs)
+    }
+
+    fn octaves<F: Fn(f64) -> f64>(&self, p: &Point3, params: &NoiseParams, shape: F) -> f64 {
+        let mut accum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut temp_p = *p * params.frequency;
+
+        for _ in 0..params.octaves {
+            accum += amplitude * shape(self.noise(&temp_p));
+            norm += amplitude;
+            amplitude *= params.gain;
+            temp_p = temp_p * params.lacunarity;
+        }
+
+        if norm > 0.0 { accum / norm } else { 0.0 }
+    }
+
+    // Cellular noise: distance from `p` to the nearest feature point, with one
+    // feature point per unit cell. Roughly in [0, 1].
+    pub fn worley(&self, p: &Point3) -> f64 {
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut nearest = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let cell = Vec3::new(ci as f64, cj as f64, ck as f64);
+                    let point = cell + self.feature[self.hash(ci, cj, ck)];
+
+                    nearest = nearest.min((point - *p).length_squared());
+                }
+            }
+        }
+
+        nearest.sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Points spread over several lattice cells, including negative ones
+    fn points() -> Vec<Point3> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..2000)
+            .map(|_| Point3::new(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0)))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_noise() {
+        let (a, b, c) = (Perlin::new(42), Perlin::new(42), Perlin::new(43));
+        let params = NoiseParams::default();
+
+        for p in points() {
+            assert_eq!(a.noise(&p), b.noise(&p));
+            assert_eq!(a.fbm(&p, &params), b.fbm(&p, &params));
+            assert_eq!(a.worley(&p), b.worley(&p));
+        }
+        assert!(points().iter().any(|p| a.noise(p) != c.noise(p)));
+    }
+
+    #[test]
+    fn noise_vanishes_at_lattice_points() {
+        let perlin = Perlin::new(7);
+        for (i, j, k) in [(0, 0, 0), (1, 2, 3), (-4, 5, -6), (255, 256, -257)] {
+            assert_eq!(perlin.noise(&Point3::new(i, j, k)), 0.0);
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let perlin = Perlin::new(7);
+        let params = NoiseParams::default();
+
+        for p in points() {
+            // Unit gradients bound a single octave by half the cell diagonal
+            assert!(perlin.noise(&p).abs() <= 0.5 * 3f64.sqrt() + 1e-9);
+            assert!(perlin.fbm(&p, &params).abs() <= 1.0);
+
+            let turbulence = perlin.turbulence(&p, &params);
+            assert!((0.0..=1.0).contains(&turbulence));
+            assert!(perlin.worley(&p) >= 0.0);
+        }
+    }
+}
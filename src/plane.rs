@@ -0,0 +1,53 @@
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+
+// Infinite plane. It has no bounding box, so it has to be added to the scene
+// outside of any BvhNode.
+pub struct Plane {
+    point: Point3,
+    frame: Onb,
+    material: Materials
+}
+
+impl Plane {
+    pub fn new_with(point: Point3, normal: Vec3, material: Materials) -> Plane {
+        Plane {
+            point, material,
+            frame: Onb::build_from_w(&normal)
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let denom = self.frame.w.dot(ray.dir());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = (self.point - *ray.origin()).dot(&self.frame.w) / denom;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = ray.at(t);
+
+        // Texture coordinates are distances along the plane, in world units
+        let local = self.frame.to_local(&(rec.p - self.point));
+        rec.u = local.x();
+        rec.v = local.y();
+        rec.dpdu = self.frame.u;
+        rec.dpdv = self.frame.v;
+        rec.set_face_normal(ray, &self.frame.w);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, _output_box: &mut AABB) -> bool {
+        false
+    }
+}
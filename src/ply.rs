@@ -0,0 +1,554 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use crate::{Color, HitList, Materials, Point3, Vec3};
+use crate::texture::Texture;
+use crate::triangle::Triangle;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(std::io::Error),
+    Header(String),
+    Body(String)
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "I/O error reading PLY file: {}", e),
+            PlyError::Header(msg) => write!(f, "Invalid PLY header: {}", msg),
+            PlyError::Body(msg) => write!(f, "Invalid PLY data: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+impl From<std::io::Error> for PlyError {
+    fn from(e: std::io::Error) -> Self {
+        PlyError::Io(e)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ScalarType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, PlyError> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Char),
+            "uchar" | "uint8" => Ok(ScalarType::UChar),
+            "short" | "int16" => Ok(ScalarType::Short),
+            "ushort" | "uint16" => Ok(ScalarType::UShort),
+            "int" | "int32" => Ok(ScalarType::Int),
+            "uint" | "uint32" => Ok(ScalarType::UInt),
+            "float" | "float32" => Ok(ScalarType::Float),
+            "double" | "float64" => Ok(ScalarType::Double),
+            _ => Err(PlyError::Header(format!("unknown property type '{}'", name)))
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Char | ScalarType::UChar => 1,
+            ScalarType::Short | ScalarType::UShort => 2,
+            ScalarType::Int | ScalarType::UInt | ScalarType::Float => 4,
+            ScalarType::Double => 8
+        }
+    }
+
+    // Scale factor that maps integer colour channels onto [0, 1]
+    fn color_scale(&self) -> f64 {
+        match self {
+            ScalarType::UChar => 1.0 / 255.0,
+            ScalarType::UShort => 1.0 / 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType }
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+// Reads scalar values out of the body of the file, independently of its encoding.
+// ASCII bodies are read a line at a time, so large meshes are never held in
+// memory as text.
+enum BodyReader<R: BufRead> {
+    Ascii { reader: R, line: String, pos: usize },
+    Binary { reader: R, big_endian: bool }
+}
+
+impl<R: BufRead> BodyReader<R> {
+    fn new(reader: R, format: Format) -> BodyReader<R> {
+        match format {
+            Format::Ascii => BodyReader::Ascii { reader, line: String::new(), pos: 0 },
+            Format::BinaryLittleEndian => BodyReader::Binary { reader, big_endian: false },
+            Format::BinaryBigEndian => BodyReader::Binary { reader, big_endian: true }
+        }
+    }
+
+    // Next whitespace separated token of an ASCII body, reading further lines
+    // as needed. None at the end of the file.
+    fn next_token<'a>(reader: &mut R, line: &'a mut String, pos: &mut usize) -> Result<Option<&'a str>, PlyError> {
+        loop {
+            let rest = &line[*pos..];
+            if let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+                let len = rest[start..].find(char::is_whitespace).unwrap_or(rest.len() - start);
+                let token_start = *pos + start;
+                *pos = token_start + len;
+                return Ok(Some(&line[token_start..token_start + len]));
+            }
+
+            line.clear();
+            *pos = 0;
+            if reader.read_line(line)? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, PlyError> {
+        match self {
+            BodyReader::Ascii { reader, line, pos } => {
+                let token = BodyReader::next_token(reader, line, pos)?
+                    .ok_or_else(|| PlyError::Body(String::from("unexpected end of file")))?;
+                token.parse::<f64>()
+                    .map_err(|_| PlyError::Body(format!("could not parse '{}' as a number", token)))
+            },
+            BodyReader::Binary { reader, big_endian } => {
+                let mut buf = [0u8; 8];
+                let bytes = &mut buf[..ty.size()];
+                reader.read_exact(bytes)?;
+                if *big_endian {
+                    bytes.reverse();
+                }
+
+                Ok(match ty {
+                    ScalarType::Char => bytes[0] as i8 as f64,
+                    ScalarType::UChar => bytes[0] as f64,
+                    ScalarType::Short => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::UShort => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::Int => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::UInt => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Float => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Double => f64::from_le_bytes(buf)
+                })
+            }
+        }
+    }
+
+    fn read_index(&mut self, ty: ScalarType) -> Result<usize, PlyError> {
+        let value = self.read(ty)?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(PlyError::Body(format!("invalid index or count {}", value)));
+        }
+
+        Ok(value as usize)
+    }
+}
+
+// Triangle mesh read from a PLY file. Faces with more than three vertices
+// are triangulated as fans. Normals, texture coordinates and colours are
+// empty when the file does not provide them; normals, when there, give the
+// triangles smooth shading.
+pub struct PlyMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<usize>
+}
+
+impl PlyMesh {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<PlyMesh, PlyError> {
+        PlyMesh::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: BufRead>(mut reader: R) -> Result<PlyMesh, PlyError> {
+        let (format, elements) = read_header(&mut reader)?;
+        let mut body = BodyReader::new(reader, format);
+
+        let mut mesh = PlyMesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new()
+        };
+
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => mesh.read_vertices(&mut body, element)?,
+                "face" => mesh.read_faces(&mut body, element)?,
+                _ => skip_element(&mut body, element)?
+            }
+        }
+
+        if let Some(&bad) = mesh.indices.iter().find(|&&i| i >= mesh.positions.len()) {
+            return Err(PlyError::Body(format!("face references vertex {} but only {} vertices exist",
+                                              bad, mesh.positions.len())));
+        }
+
+        Ok(mesh)
+    }
+
+    pub fn has_vertex_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    // Builds one `Triangle` per face, all sharing the given material.
+    // Uses the texture coordinates from the file when every vertex has them
+    pub fn to_hitlist(&self, material: Materials) -> HitList {
+        self.build_hitlist(self.uvs.len() == self.positions.len(), |_| material.clone())
+    }
+
+    // Builds one `Triangle` per face with a diffuse material whose albedo is
+    // interpolated from the per-vertex colours. Falls back to a grey
+    // albedo when the file has no colours.
+    pub fn to_hitlist_vertex_colored(&self) -> HitList {
+        if !self.has_vertex_colors() {
+            return self.to_hitlist(Materials::Lambertian {
+                albedo: Texture::SolidColor { color_value: Color::new(0.8, 0.8, 0.8) }
+            });
+        }
+
+        // Vertex colours are interpolated with the barycentric (u, v), so no texture coordinates
+        self.build_hitlist(false, |tri| Materials::Lambertian {
+            albedo: Texture::VertexColor {
+                colors: [self.colors[tri[0]], self.colors[tri[1]], self.colors[tri[2]]]
+            }
+        })
+    }
+
+    fn build_hitlist<F: Fn(&[usize]) -> Materials>(&self, with_uvs: bool, material: F) -> HitList {
+        let mut list = HitList::new();
+        let with_normals = self.normals.len() == self.positions.len();
+
+        for tri in self.indices.chunks(3) {
+            let v0 = self.positions[tri[0]];
+            let v1 = self.positions[tri[1]];
+            let v2 = self.positions[tri[2]];
+
+            // Scanned data often contains zero-area faces, which have no normal
+            if (v1 - v0).cross(&(v2 - v0)).near_zero() {
+                continue;
+            }
+
+            let mut triangle = if with_uvs {
+                let uvs = [self.uvs[tri[0]], self.uvs[tri[1]], self.uvs[tri[2]]];
+                Triangle::new_with_uvs(v0, v1, v2, uvs, material(tri))
+            } else {
+                Triangle::new_with(v0, v1, v2, material(tri))
+            };
+
+            if with_normals {
+                triangle = triangle.with_normals([self.normals[tri[0]], self.normals[tri[1]], self.normals[tri[2]]]);
+            }
+
+            list.add(Arc::new(triangle));
+        }
+
+        list
+    }
+
+    fn read_vertices<R: BufRead>(&mut self, body: &mut BodyReader<R>, element: &Element) -> Result<(), PlyError> {
+        let has = |names: &[&str]| names.iter().all(|n| element.properties.iter().any(|p| p.name == *n));
+        let has_normals = has(&["nx", "ny", "nz"]);
+        let uv_names = if has(&["u", "v"]) {
+            Some(["u", "v"])
+        } else if has(&["s", "t"]) {
+            Some(["s", "t"])
+        } else if has(&["texture_u", "texture_v"]) {
+            Some(["texture_u", "texture_v"])
+        } else {
+            None
+        };
+        let color_names = if has(&["red", "green", "blue"]) {
+            Some(["red", "green", "blue"])
+        } else if has(&["diffuse_red", "diffuse_green", "diffuse_blue"]) {
+            Some(["diffuse_red", "diffuse_green", "diffuse_blue"])
+        } else {
+            None
+        };
+
+        if !has(&["x", "y", "z"]) {
+            return Err(PlyError::Header(String::from("vertex element is missing x, y or z")));
+        }
+
+        self.positions.reserve(element.count);
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                *value = match property.kind {
+                    PropertyKind::Scalar(ty) => body.read(ty)?,
+                    PropertyKind::List { count, item } => {
+                        let n = body.read_index(count)?;
+                        for _ in 0..n {
+                            body.read(item)?;
+                        }
+                        0.0
+                    }
+                };
+            }
+
+            let get = |name: &str| -> f64 {
+                element.properties.iter().position(|p| p.name == name).map(|i| values[i]).unwrap_or(0.0)
+            };
+
+            self.positions.push(Point3::new(get("x"), get("y"), get("z")));
+
+            if has_normals {
+                self.normals.push(Vec3::new(get("nx"), get("ny"), get("nz")));
+            }
+
+            if let Some([u, v]) = uv_names {
+                self.uvs.push((get(u), get(v)));
+            }
+
+            if let Some(names) = color_names {
+                let channel = |name: &str| -> f64 {
+                    let scale = element.properties.iter().find(|p| p.name == name).map(|p| match p.kind {
+                        PropertyKind::Scalar(ty) => ty.color_scale(),
+                        PropertyKind::List { .. } => 1.0
+                    }).unwrap_or(1.0);
+                    get(name) * scale
+                };
+                self.colors.push(Color::new(channel(names[0]), channel(names[1]), channel(names[2])));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_faces<R: BufRead>(&mut self, body: &mut BodyReader<R>, element: &Element) -> Result<(), PlyError> {
+        let index_property = element.properties.iter()
+            .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+            .ok_or_else(|| PlyError::Header(String::from("face element has no vertex_indices list")))?;
+
+        self.indices.reserve(element.count * 3);
+
+        let mut face = Vec::new();
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(ty) => {
+                        body.read(ty)?;
+                    },
+                    PropertyKind::List { count, item } => {
+                        let n = body.read_index(count)?;
+                        face.clear();
+                        for _ in 0..n {
+                            face.push(body.read_index(item)?);
+                        }
+
+                        if i == index_property {
+                            for k in 1..face.len().saturating_sub(1) {
+                                self.indices.push(face[0]);
+                                self.indices.push(face[k]);
+                                self.indices.push(face[k + 1]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn skip_element<R: BufRead>(body: &mut BodyReader<R>, element: &Element) -> Result<(), PlyError> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::Scalar(ty) => {
+                    body.read(ty)?;
+                },
+                PropertyKind::List { count, item } => {
+                    let n = body.read_index(count)?;
+                    for _ in 0..n {
+                        body.read(item)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Format, Vec<Element>), PlyError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "ply" {
+        return Err(PlyError::Header(String::from("missing 'ply' magic number")));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(PlyError::Header(String::from("missing end_header")));
+        }
+
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        match tokens.as_slice() {
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(PlyError::Header(format!("unknown format '{}'", name)))
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse::<usize>()
+                    .map_err(|_| PlyError::Header(format!("invalid element count '{}'", count)))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            },
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut()
+                    .ok_or_else(|| PlyError::Header(String::from("property declared before any element")))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyKind::List { count: ScalarType::parse(count)?, item: ScalarType::parse(item)? }
+                });
+            },
+            ["property", ty, name] => {
+                let element = elements.last_mut()
+                    .ok_or_else(|| PlyError::Header(String::from("property declared before any element")))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyKind::Scalar(ScalarType::parse(ty)?)
+                });
+            },
+            _ => return Err(PlyError::Header(format!("unexpected line '{}'", line.trim())))
+        }
+    }
+
+    let format = format.ok_or_else(|| PlyError::Header(String::from("missing format line")))?;
+    Ok((format, elements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                          element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    // Binary PLY with the same quad as `ascii_quad`, in the given byte order
+    fn binary_quad(format: &str, to_bytes: fn(f32) -> [u8; 4], index_bytes: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for p in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]] {
+            for c in p {
+                data.extend_from_slice(&to_bytes(c));
+            }
+        }
+        data.push(4);
+        for i in 0..4 {
+            data.extend_from_slice(&index_bytes(i));
+        }
+        data
+    }
+
+    fn ascii_quad() -> String {
+        format!("ply\nformat ascii 1.0\n{}0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n", HEADER)
+    }
+
+    fn assert_quad(mesh: &PlyMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert!((mesh.positions[2] - Point3::new(1, 1, 0)).near_zero());
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn reads_ascii() {
+        assert_quad(&PlyMesh::read(ascii_quad().as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_little_endian() {
+        let data = binary_quad("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        assert_quad(&PlyMesh::read(data.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        let data = binary_quad("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        assert_quad(&PlyMesh::read(data.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn triangulates_faces_as_fans() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 5\nproperty float x\nproperty float y\nproperty float z\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0 1 0 0 2 1 0 1 2 0 0 1 0\n5 0 1 2 3 4\n";
+        let mesh = PlyMesh::read(data.as_bytes()).unwrap();
+        assert_eq!(mesh.triangle_count(), 3);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_vertex_normals() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                    property float nx\nproperty float ny\nproperty float nz\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0 0 0 1\n1 0 0 0 0 1\n0 1 0 0 0 1\n3 0 1 2\n";
+        let mesh = PlyMesh::read(data.as_bytes()).unwrap();
+        assert_eq!(mesh.normals.len(), 3);
+        assert!((mesh.normals[1] - Vec3::new(0, 0, 1)).near_zero());
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let data = ascii_quad().replace("4 0 1 2 3", "4 0 1 2 7");
+        assert!(matches!(PlyMesh::read(data.as_bytes()), Err(PlyError::Body(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        let data = ascii_quad().replace("format ascii", "format binary_middle_endian");
+        assert!(matches!(PlyMesh::read(data.as_bytes()), Err(PlyError::Header(_))));
+    }
+}
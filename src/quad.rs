@@ -0,0 +1,96 @@
+use std::borrow::Borrow;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::sampler::Sampler;
+
+// Parallelogram spanned by the edges `u` and `v` from the corner `q`
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    normal: Vec3,
+    d: f64,
+    area: f64,
+    material: Materials
+}
+
+impl Quad {
+    pub fn new_with(q: Point3, u: Vec3, v: Vec3, material: Materials) -> Quad {
+        let n = u.cross(&v);
+        let normal = n.normalized();
+
+        Quad {
+            q, u, v, normal, material,
+            w: n / n.dot(&n),
+            d: normal.dot(&q),
+            area: n.length()
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        let denom = self.normal.dot(ray.dir());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = (self.d - self.normal.dot(ray.origin())) / denom;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        // Express the hit point in the (u, v) frame of the parallelogram
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = p;
+        rec.u = alpha;
+        rec.v = beta;
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+        rec.set_face_normal(ray, &self.normal);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let box_diagonal = AABB::surrounding_box(
+            &AABB::new(&self.q, (self.q + self.u + self.v).borrow()),
+            &AABB::new((self.q + self.u).borrow(), (self.q + self.v).borrow())
+        );
+
+        // Pad the box so axis-aligned quads do not end up with zero thickness
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        *output_box = AABB::new((box_diagonal.min() - pad).borrow(), (box_diagonal.max() + pad).borrow());
+
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3, sampler: &mut dyn Sampler) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(*origin, *dir), 0.001, f64::INFINITY, &mut rec, sampler) {
+            return 0.0;
+        }
+
+        let distance_squared = rec.t * rec.t * dir.length_squared();
+        let cosine = (dir.dot(&self.normal) / dir.length()).abs();
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (a, b) = sampler.get_2d();
+        let p = self.q + self.u * a + self.v * b;
+        p - *origin
+    }
+}
@@ -4,7 +4,10 @@ use crate::vec3::{Point3, Vec3};
 pub struct Ray {
     origin: Point3,
     dir: Vec3,
-    inv_dir: Vec3
+    inv_dir: Vec3,
+    time: f64,
+    // Angle covered by the ray's pixel, per unit distance travelled
+    spread: f64
 }
 
 impl Ray {
@@ -12,12 +15,20 @@ impl Ray {
         Ray {
             origin: Point3::new_empty(),
             dir: Vec3::new_empty(),
-            inv_dir: Vec3::new_empty()
+            inv_dir: Vec3::new_empty(),
+            time: 0.0,
+            spread: 0.0
         }
     }
 
     pub fn new(origin: Point3, dir: Vec3) -> Ray {
+        Ray::new_with_time(origin, dir, 0.0)
+    }
+
+    pub fn new_with_time(origin: Point3, dir: Vec3, time: f64) -> Ray {
         Ray {
+            time,
+            spread: 0.0,
             origin,
             dir,
             inv_dir: Vec3 {
@@ -49,4 +60,24 @@ impl Ray {
     pub fn inv_dir(&self) -> &Vec3 {
         &self.inv_dir
     }
+
+    #[inline(always)]
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    #[inline(always)]
+    pub fn spread(&self) -> f64 {
+        self.spread
+    }
+
+    pub fn with_spread(mut self, spread: f64) -> Ray {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
+    }
 }
\ No newline at end of file
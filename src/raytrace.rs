@@ -1,259 +1,490 @@
-use std::sync::{Arc, Mutex};
+use std::ops::Range;
+use std::sync::Mutex;
 use std::time::Instant;
-use image::{ColorType, Rgba, RgbaImage};
-use rand::{Rng};
-use rand::prelude::SliceRandom;
+use std::path::Path;
+use image::ImageError;
 use rayon::prelude::*;
-use crate::{Camera, Color, HitList, HitRecord, Hittable, Point3, Ray, Vec3};
-use crate::glutin::event::VirtualKeyCode::P;
+use crate::{Color, HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aov::{write_aovs, Aov, AovOutput, AovSample};
+use crate::camera_path::CameraPath;
+use crate::denoise::{denoise_image, Denoiser};
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::framebuffer::{FrameBuffer, PixelStats};
+use crate::sampler::{Sampler, SamplerKind};
+use crate::medium::PhaseFunction;
 use crate::scene::Scene;
 
+// Stops sampling a pixel once its estimated error is below `threshold`, so
+// the budget goes to the pixels that need it. The error is the standard error
+// of the mean luminance after gamma encoding, as a fraction of full white.
+// Pixels are checked every `min_samples` samples and never take more than
+// `max_samples`.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    pub min_samples: u32,
+    pub max_samples: u32,
+    // Also save a map of the samples each pixel took
+    pub heatmap: bool
+}
+
+impl AdaptiveSampling {
+    // Around `samples_per_pixel` on average for a typical scene: a quarter of
+    // it for pixels that converge at once, up to four times it for the noisiest
+    pub fn new_with(samples_per_pixel: u32, threshold: f64) -> AdaptiveSampling {
+        AdaptiveSampling {
+            threshold,
+            min_samples: (samples_per_pixel / 4).max(4),
+            max_samples: samples_per_pixel.max(1) * 4,
+            heatmap: false
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct RTParams {
-    aspect_ratio: f64,
+    film: Film,
+    // Size of the output image, the film's crop window
     width: u32,
     height: u32,
     samples_per_pixel: u32,
     max_depth: i32,
+    filter: Filter,
+    sampler: SamplerKind,
+    adaptive: Option<AdaptiveSampling>,
+    aovs: Vec<Aov>,
+    // Where the AOVs go, if they are wanted as output and not just for the
+    // denoiser
+    aov_output: Option<AovOutput>,
+    denoiser: Option<Denoiser>
 }
 
 impl RTParams {
-    pub fn new(aspect_ratio: f64, image_width: u32, samples_per_pixel: u32, max_depth: i32) -> RTParams {
+    pub fn new(film: Film, samples_per_pixel: u32, max_depth: i32) -> RTParams {
+        let (width, height) = film.output_size();
+
         RTParams {
-            height: (image_width as f64 / aspect_ratio) as u32,
-            width: image_width,
-            aspect_ratio,
+            film,
+            width,
+            height,
             samples_per_pixel,
             max_depth,
+            filter: Filter::default(),
+            sampler: SamplerKind::Sobol,
+            adaptive: None,
+            aovs: Vec::new(),
+            aov_output: None,
+            denoiser: None
+        }
+    }
+
+    // Same settings on another film, e.g. a smaller one for quick previews
+    #[cfg(feature = "viewer")]
+    pub fn with_film(mut self, film: Film) -> RTParams {
+        let (width, height) = film.output_size();
+        self.film = film;
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> RTParams {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: SamplerKind) -> RTParams {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: AdaptiveSampling) -> RTParams {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn with_aovs(mut self, aovs: &[Aov], output: AovOutput) -> RTParams {
+        for aov in aovs {
+            if !self.aovs.contains(aov) {
+                self.aovs.push(*aov);
+            }
+        }
+        self.aov_output = Some(output);
+        self
+    }
+
+    // Saves a denoised image next to every rendered one, guided by the albedo
+    // and normal AOVs
+    pub fn with_denoise(mut self, denoiser: Option<Denoiser>) -> RTParams {
+        self.denoiser = denoiser;
+        if denoiser.is_some() {
+            for aov in [Aov::Albedo, Aov::Normal] {
+                if !self.aovs.contains(&aov) {
+                    self.aovs.push(aov);
+                }
+            }
+        }
+        self
+    }
+
+    #[cfg(feature = "viewer")]
+    pub fn film(&self) -> Film {
+        self.film
+    }
+
+    // Most samples any pixel can take
+    pub fn max_samples(&self) -> u32 {
+        match self.adaptive {
+            Some(adaptive) => adaptive.max_samples.max(adaptive.min_samples),
+            None => self.samples_per_pixel
         }
     }
 }
 
-fn ray_color(ray: &Ray, background: &Color, world: &dyn Hittable, depth: i32) -> Color {
+// Light arriving at a scattering event inside a medium directly from the area
+// lights, attenuated by everything along the shadow ray
+fn medium_direct_light(scene: &Scene, p: &Point3, dir: &Vec3, phase: &PhaseFunction, time: f64,
+                       sampler: &mut dyn Sampler) -> Color {
+    let to_light = scene.lights.random(p, sampler);
+    let pdf = scene.lights.pdf_value(p, &to_light, sampler);
+    if pdf <= 0.0 {
+        return Color::new_empty();
+    }
+
+    let shadow_ray = Ray::new_with_time(*p, to_light, time);
+    let mut light_rec = HitRecord::default();
+    if !scene.lights.hit(&shadow_ray, 0.001, f64::INFINITY, &mut light_rec, sampler) {
+        return Color::new_empty();
+    }
+
+    // Stop just short of the light so it does not occlude itself
+    let t_light = light_rec.t * (1.0 - 1e-4);
+    let mut transmittance = scene.hit_list.transmittance(&shadow_ray, 0.001, t_light, sampler);
+    if let Some(fog) = &scene.fog {
+        transmittance *= fog.transmittance(&shadow_ray, t_light);
+    }
+
+    if transmittance <= 0.0 {
+        return Color::new_empty();
+    }
+
+    let cos_theta = dir.normalized().dot(&to_light.normalized());
+    light_rec.material.emitted(light_rec.u, light_rec.v, &light_rec.p)
+        * (transmittance * phase.eval(cos_theta) / pdf)
+}
+
+// `count_lights` is false on paths continuing from a medium event whose direct
+// lighting was already sampled, so the lights are not counted twice. `aov`
+// receives the AOVs of the first surface the ray hits.
+fn ray_color(ray: &Ray, scene: &Scene, depth: i32, count_lights: bool, sampler: &mut dyn Sampler,
+             mut aov: Option<&mut AovSample>) -> Color {
     let mut rec = HitRecord::default();
 
     if depth <= 0 {
         return Color::new_empty();
     }
 
-    if !world.hit(ray, 0.001, f64::INFINITY, &mut rec) {
-        return *background;
+    let hit = scene.hit_list.hit(ray, 0.001, f64::INFINITY, &mut rec, sampler);
+
+    // Global fog may scatter the ray before it reaches the surface or the sky
+    if let Some(fog) = &scene.fog {
+        let t_end = if hit { rec.t } else { f64::INFINITY };
+        if let Some(scattered) = fog.sample_scatter(ray, t_end, sampler) {
+            if scene.lights.is_empty() {
+                return fog.albedo * ray_color(&scattered, scene, depth - 1, true, sampler, None);
+            }
+
+            let direct = medium_direct_light(scene, scattered.origin(), ray.dir(), &fog.phase, ray.time(), sampler);
+            return fog.albedo * (direct + ray_color(&scattered, scene, depth - 1, false, sampler, None));
+        }
     }
 
+    if !hit {
+        if let Some(aov) = aov.as_deref_mut() {
+            aov.set(Aov::Emission, scene.sky_color);
+        }
+        return scene.sky_color;
+    }
+
+    // Only camera rays carry a cone, so bounces fall back to the finest mip level
+    rec.set_uv_footprint(ray);
+    Materials::apply_shading_normal(&mut rec);
+
     let mut scattered = Ray::new_empty();
     let mut attenuation = Color::new_empty();
-    let emitted = rec.material.emitted(rec.u, rec.v, &rec.p);
+    let mut emitted = rec.material.emitted(rec.u, rec.v, &rec.p);
 
-    if !rec.material.scatter(ray, &rec, &mut attenuation, &mut scattered) {
-        return emitted;
+    if !count_lights {
+        let mut light_rec = HitRecord::default();
+        if scene.lights.hit(ray, 0.001, rec.t + 1e-4, &mut light_rec, sampler) && light_rec.t > rec.t - 1e-4 {
+            emitted = Color::new_empty();
+        }
     }
 
-    return emitted + attenuation * ray_color(&scattered, background, world, depth - 1);
-    /*if !rec.material.scatter(&ray, &rec, &mut attenuation, &mut scattered) {
-        if attenuation.length() < 0.1 {
-            return attenuation;
+    let scatters = rec.material.scatter(ray, &rec, &mut attenuation, &mut scattered, sampler);
+
+    if let Some(aov) = aov.as_deref_mut() {
+        aov.set(Aov::Emission, emitted);
+        aov.set(Aov::Normal, rec.normal);
+        aov.set(Aov::Position, rec.p);
+        aov.set_scalar(Aov::Depth, (rec.p - *ray.origin()).length());
+        aov.set_scalar(Aov::ObjectId, rec.object_id as f64);
+        aov.set_scalar(Aov::MaterialId, rec.material.id() as f64);
+        if scatters {
+            aov.set(Aov::Albedo, attenuation);
         }
-        return attenuation * ray_color(&scattered, world, depth - 1);
     }
-    return Color::new_empty();
 
-    let unit_direction = ray.dir().normalized();
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t*/
-}
+    if !scatters {
+        return emitted;
+    }
 
-fn rgba_to_color(rgba: &Rgba<u8>) -> Color {
-    Color::new(rgba.0[0], rgba.0[1], rgba.0[2])
-}
+    // The camera ray's bounce is traced with a record of its own, whose
+    // emission is the light arriving directly
+    let primary = aov.as_ref().is_some_and(|aov| aov.primary);
+    let mut next = AovSample::new_empty();
+    let next_aov = if primary { Some(&mut next) } else { None };
 
-fn trace_pixel(px: u32, py: u32, params: &RTParams, scene: &Scene) -> Rgba<u8> {
-    let mut rng = rand::thread_rng();
+    // Scattering inside a volume samples the lights directly through the phase function
+    if let Some(phase) = rec.material.phase() {
+        if scene.lights.is_empty() {
+            return attenuation * ray_color(&scattered, scene, depth - 1, true, sampler, None);
+        }
 
-    let mut pixel_color = Color::new_empty();
+        let direct = medium_direct_light(scene, &rec.p, ray.dir(), &phase, ray.time(), sampler);
+        return attenuation * (direct + ray_color(&scattered, scene, depth - 1, false, sampler, None));
+    }
 
-    for _ in 0..params.samples_per_pixel {
-        let u = (px as f64 + rng.gen::<f64>()) / (params.width as f64 - 1.0);
-        let v = ((params.height - py) as f64 + rng.gen::<f64>()) / (params.height as f64 - 1.0);
+    // Diffuse surfaces send half of their rays towards the area lights and weight
+    // the result by the combined density of both strategies
+    if !scene.lights.is_empty() && rec.material.scattering_pdf(ray, &rec, &scattered) > 0.0 {
+        if sampler.get_1d() < 0.5 {
+            scattered = Ray::new_with_time(rec.p, scene.lights.random(&rec.p, sampler), ray.time());
+        }
 
-        let r = scene.camera.ray(u, v);
-        pixel_color += ray_color(&r, &scene.sky_color, &scene.hit_list, params.max_depth);
-    }
+        let scattering_pdf = rec.material.scattering_pdf(ray, &rec, &scattered);
+        let pdf = 0.5 * scattering_pdf + 0.5 * scene.lights.pdf_value(&rec.p, scattered.dir(), sampler);
+        if pdf <= 0.0 {
+            return emitted;
+        }
 
-    let scale = 1.0 / params.samples_per_pixel as f64;
-    //pixel_color = pixel_color * scale;
-    pixel_color.e[0] = (scale * pixel_color.x()).sqrt();
-    pixel_color.e[1] = (scale * pixel_color.y()).sqrt();
-    pixel_color.e[2] = (scale * pixel_color.z()).sqrt();
+        let weight = scattering_pdf / pdf;
+        let reflected = attenuation * ray_color(&scattered, scene, depth - 1, true, sampler, next_aov) * weight;
+        if let Some(aov) = aov {
+            record_bounce(aov, true, reflected, attenuation * next.get(Aov::Emission) * weight);
+        }
+
+        return emitted + reflected;
+    }
 
-    let ir = (pixel_color.x().clamp(0.0, 0.999) * 256.0) as u8;
-    let ig = (pixel_color.y().clamp(0.0, 0.999) * 256.0) as u8;
-    let ib = (pixel_color.z().clamp(0.0, 0.999) * 256.0) as u8;
+    let diffuse = rec.material.scattering_pdf(ray, &rec, &scattered) > 0.0;
+    let reflected = attenuation * ray_color(&scattered, scene, depth - 1, true, sampler, next_aov);
+    if let Some(aov) = aov {
+        record_bounce(aov, diffuse, reflected, attenuation * next.get(Aov::Emission));
+    }
 
-    return Rgba([ir, ig, ib, 255]);
+    emitted + reflected
 }
 
-fn oidn(image: &Arc<Mutex<RgbaImage>>) {
-    let input = image.lock().unwrap().clone();
+// Splits the light reflected at the first surface into its direct and
+// indirect part
+fn record_bounce(aov: &mut AovSample, diffuse: bool, reflected: Color, direct: Color) {
+    if !aov.primary {
+        return;
+    }
+
+    let (direct_aov, indirect_aov) = if diffuse {
+        (Aov::DiffuseDirect, Aov::DiffuseIndirect)
+    } else {
+        (Aov::SpecularDirect, Aov::SpecularIndirect)
+    };
 
-    // OIDN works on float images only, so convert this to a floating point image
-    let mut input_img = vec![0.0f32; (3 * input.width() * input.height()) as usize];
-    for y in 0..input.height() {
-        for x in 0..input.width() {
-            let p = input.get_pixel(x, y);
-            for c in 0..3 {
-                input_img[3 * ((y * input.width() + x) as usize) + c] = p[c] as f32 / 255.0;
+    aov.set(direct_aov, direct);
+    aov.set(indirect_aov, reflected - direct);
+}
+
+// Traces samples `samples` of output pixel (px, py) and splats them into
+// `buffer`. Adaptive sampling also counts the samples `previous` already
+// took, so a pixel rendered pass by pass stops where it would in one go.
+fn trace_pixel((px, py): (u32, u32), params: &RTParams, scene: &Scene, samples: Range<u32>, sampler: &mut dyn Sampler,
+               previous: &PixelStats, buffer: &mut FrameBuffer) {
+    let spread = scene.camera.pixel_spread(params.film.height);
+    let mut stats = PixelStats::default();
+
+    for index in samples {
+        if let Some(adaptive) = params.adaptive {
+            let total = previous.combined(&stats);
+            let check = adaptive.min_samples.max(1);
+            if total.count >= check && total.count.is_multiple_of(check) && display_error(&total) < adaptive.threshold {
+                break;
             }
         }
-    }
 
-    println!("Image dims {}x{}", input.width(), input.height());
+        sampler.start_pixel_sample(px, py, index);
+        let (dx, dy) = sampler.get_2d();
+        let (u, v) = params.film.screen_position(px, py, dx, dy);
 
-    let mut filter_output = vec![0.0f32; input_img.len()];
+        let with_aovs = !params.aovs.is_empty();
+        let mut aov = AovSample::new_primary();
 
-    let device = oidn::Device::new();
-    let mut filter = oidn::RayTracing::new(&device);
-    filter
-        .srgb(true)
-        .image_dimensions(input.width() as usize, input.height() as usize);
-    filter
-        .filter(&input_img[..], &mut filter_output[..])
-        .expect("Invalid input image dimensions?");
+        // Samples outside the projection stay black
+        let color = match scene.camera.ray(u, v, sampler) {
+            Some(r) => ray_color(&r.with_spread(spread), scene, params.max_depth, true, sampler,
+                                 if with_aovs { Some(&mut aov) } else { None }),
+            None => Color::new_empty()
+        };
 
-    if let Err(e) = device.get_error() {
-        println!("Error denoising image: {}", e.1);
-    }
+        buffer.splat(px as f64 + dx, py as f64 + dy, color, if with_aovs { Some(&aov) } else { None }, &params.filter);
 
-    let mut original_image = image.lock().unwrap();
+        stats.add(luminance(&color));
+    }
 
-    for (idx, chunk) in filter_output.chunks(3).enumerate() {
-        let px = (idx % input.width() as usize) as u32;
-        let py = (idx / input.width() as usize) as u32;
+    buffer.add_stats(px, py, &stats);
+}
 
-        let pr = (chunk[0] * 255.0).max(0.0).min(255.0) as u8;
-        let pg = (chunk[1] * 255.0).max(0.0).min(255.0) as u8;
-        let pb = (chunk[2] * 255.0).max(0.0).min(255.0) as u8;
-        let pa = 255 as u8;
+fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
 
-        original_image.put_pixel(px, py, Rgba([pr, pg, pb, pa]));
+// Standard error of the pixel mean once encoded with gamma 2, whose slope at
+// `mean` is 1 / (2 sqrt(mean)). Dark pixels need a smaller absolute error
+// for the same visible noise.
+fn display_error(stats: &PixelStats) -> f64 {
+    if stats.count < 2 {
+        return f64::INFINITY;
     }
 
-    let mut output_img = vec![0u8; filter_output.len()];
-    for i in 0..filter_output.len() {
-        let p = filter_output[i] * 255.0;
-        if p < 0.0 {
-            output_img[i] = 0;
-        } else if p > 255.0 {
-            output_img[i] = 255;
-        } else {
-            output_img[i] = p as u8;
+    let variance = stats.m2 / (stats.count - 1) as f64;
+    let std_error = (variance / stats.count as f64).sqrt();
+    std_error / (2.0 * stats.mean.max(1e-4).sqrt())
+}
+
+// Square tiles covering the output image, as (x0, y0, x1, y1)
+fn tiles(params: &RTParams) -> Vec<(u32, u32, u32, u32)> {
+    const TILE_SIZE: u32 = 16;
+
+    (0..params.height).step_by(TILE_SIZE as usize)
+        .flat_map(|y0| (0..params.width).step_by(TILE_SIZE as usize).map(move |x0| (x0, y0)))
+        .map(|(x0, y0)| (x0, y0, (x0 + TILE_SIZE).min(params.width), (y0 + TILE_SIZE).min(params.height)))
+        .collect()
+}
+
+// Renders one tile into a buffer that also covers the border its samples
+// splat onto
+fn render_tile(tile: (u32, u32, u32, u32), params: &RTParams, scene: &Scene, samples: Range<u32>,
+               previous: Option<&FrameBuffer>) -> FrameBuffer {
+    let (x0, y0, x1, y1) = tile;
+    let border = params.filter.radius().ceil() as u32;
+    let mut buffer = FrameBuffer::new_region(
+        x0.saturating_sub(border),
+        y0.saturating_sub(border),
+        (x1 + border).min(params.width),
+        (y1 + border).min(params.height)).with_aovs(&params.aovs);
+
+    let mut sampler = params.sampler.create(params.max_samples());
+    for py in y0..y1 {
+        for px in x0..x1 {
+            let stats = previous.map_or(PixelStats::default(), |previous| previous.stats(px, py));
+            trace_pixel((px, py), params, scene, samples.clone(), sampler.as_mut(), &stats, &mut buffer);
         }
     }
 
-    image::save_buffer(
-        "output_oidn.png",
-        &output_img[..],
-        input.width(),
-        input.height(),
-        ColorType::Rgb8,
-    ).expect("Failed to save output image");
+    buffer
 }
 
-pub fn run_rt(mut params: RTParams, scene: &Scene, image: Arc<Mutex<RgbaImage>>) {
-    let start = Instant::now();
+pub fn render_buffer(params: &RTParams, scene: &Scene) -> FrameBuffer {
+    render_samples(params, scene, 0..params.max_samples(), None)
+}
 
-    // Helper functions for raytracing single pixels
-    //  as well as flushing to the output image when needed
-    let flush_pixels = |imge: &Arc<Mutex<RgbaImage>>, mut pixels: &mut Vec<((u32, u32), Rgba<u8>)>, force: bool| {
-        if pixels.len() > 250 || force {
-            let mut img = image.lock().unwrap();
-            pixels.iter().for_each(|pixel|{
-                img.put_pixel(pixel.0.0, pixel.0.1, pixel.1);
-            });
-            pixels.clear();
-        }
-    };
+// Renders only samples `samples` of every pixel, on top of the earlier
+// samples in `previous`. Merging the buffers of consecutive ranges gives the
+// same image as rendering them all at once, which is how the viewer refines
+// its image pass by pass.
+pub fn render_samples(params: &RTParams, scene: &Scene, samples: Range<u32>, previous: Option<&FrameBuffer>) -> FrameBuffer {
+    let buffer = Mutex::new(FrameBuffer::new(params.width, params.height).with_aovs(&params.aovs));
 
-    // Run the raytrace on half of the pixels
-    (0..16).into_par_iter().for_each(|j| {
-        let mut rng = rand::thread_rng();
-        let mut pixels: Vec<((u32, u32), Rgba<u8>)> = Vec::new();
-        pixels.reserve((params.width * params.height) as usize);
-
-        (j..(params.width * params.height)).step_by(16)
-            .filter(|x| {
-                /*let row = x / params.width;
-                return if row % 2 == 0 {
-                    x % 2 == 0
-                } else {
-                    x % 2 == 1
-                }*/
-                true
-            }).for_each(|i| {
-            let px = i % params.width;
-            let py = i / params.width;
-
-            pixels.push(((px, py), trace_pixel(px, py, &params, &scene)));
-            flush_pixels(&image, &mut pixels, false);
-        });
-
-        flush_pixels(&image, &mut pixels, true);
+    tiles(params).into_par_iter().for_each(|tile| {
+        let tile_buffer = render_tile(tile, params, scene, samples.clone(), previous);
+        buffer.lock().unwrap().merge(&tile_buffer);
     });
 
-    // Fill the other half of the pixels via interpolation
-    // Only raytracing if interpolation would be too extreme
-    /*(0..16).into_par_iter().for_each(|j| {
-        let local_copy = image.lock().unwrap().clone();
-        let mut rng = rand::thread_rng();
-        let mut pixels: Vec<((u32, u32), Rgba<u8>)> = Vec::new();
-        pixels.reserve((params.width * params.height) as usize);
-
-        (j..(params.width * params.height))
-            .step_by(16)
-            .filter(|x| {
-                let row = x / params.width;
-                return if row % 2 == 0 {
-                    x % 2 == 1
-                } else {
-                    x % 2 == 0
-                }
-            }).for_each(|i| {
-            let px = i % params.width;
-            let py = i / params.width;
-
-            let avg = Color::new(255.0,255.0,255.0);
-            pixels.push(((px, py), Rgba([avg.x() as u8, avg.y() as u8, avg.z() as u8, 255])));
-
-            if px == 0 || px == params.width - 1 || py == 0 || py == params.height - 1 {
-                pixels.push(((px, py), rt_pixel(px, py)));
-            } else {
-                let left = rgba_to_color(local_copy.get_pixel(px - 1, py));
-                let right = rgba_to_color(local_copy.get_pixel(px + 1, py));
-                let top = rgba_to_color(local_copy.get_pixel(px + 1, py));
-                let bottom = rgba_to_color(local_copy.get_pixel(px + 1, py));
-                let avg = (left + right + top + bottom) / 4.0;
-
-                if (left - avg).length() > 25.0
-                    || (right - avg).length() > 25.0
-                    || (top - avg).length() > 25.0
-                    || (bottom - avg).length() > 25.0 {
-                    pixels.push(((px, py), rt_pixel(px, py)));
-                } else {
-                    pixels.push(((px, py), Rgba([avg.x() as u8, avg.y() as u8, avg.z() as u8, 255])));
-                }
-            }
+    buffer.into_inner().unwrap()
+}
 
-            flush_pixels(&image, &mut pixels, false);
-        });
+// Renders `frame_count` frames spread over the camera path into
+// `out_dir` as frame_0001.png, frame_0002.png, ... Frames already on disk are
+// skipped, so an interrupted sequence picks up where it stopped.
+pub fn render_sequence<P: AsRef<Path>>(params: &RTParams, scene: &mut Scene, path: &CameraPath,
+                                       frame_count: u32, out_dir: P) -> Result<(), ImageError> {
+    let out_dir = out_dir.as_ref();
+    std::fs::create_dir_all(out_dir)?;
+
+    for frame in 0..frame_count {
+        let file = out_dir.join(format!("frame_{:04}.png", frame + 1));
+        if file.exists() {
+            println!("Skipping {}, already rendered", file.display());
+            continue;
+        }
+
+        scene.camera = Box::new(path.camera_at(path.frame_time(frame, frame_count)));
 
-        flush_pixels(&image, &mut pixels, true);
-    });*/
+        let start = Instant::now();
+        let buffer = render_buffer(params, scene);
 
-    let elapsed = Instant::now() - start;
-    println!("Total time taken for rt: {}.{}s", elapsed.as_secs(), elapsed.as_millis() % 1000);
+        // Extras go first and the frame is written to a temporary name, so a
+        // frame that exists is always complete
+        save_extras(params, &buffer, &file);
+        let partial = out_dir.join(format!("frame_{:04}.partial.png", frame + 1));
+        buffer.to_image().save(&partial)?;
+        std::fs::rename(&partial, &file)?;
 
-    image.lock().unwrap().save("output.png").expect("Error saving image!");
-    //oidn(&image);
+        println!("Rendered {} in {:.1}s", file.display(), start.elapsed().as_secs_f64());
+    }
 
-    // Run rt again with double the sample count
-    params.samples_per_pixel *= 2;
-    run_rt(params, scene, image);
-}
\ No newline at end of file
+    Ok(())
+}
+
+// Writes the AOVs, the denoised image and the adaptive sampling heatmap that go
+// with the image at `path`, e.g. output_denoised.png and output_samples.png next
+// to output.png. Failures are reported but do not stop the render.
+pub fn save_extras(params: &RTParams, buffer: &FrameBuffer, path: &Path) {
+    if let Some(output) = params.aov_output {
+        if let Err(e) = write_aovs(buffer, output, path) {
+            println!("{}", e);
+        }
+    }
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    if params.adaptive.is_some_and(|adaptive| adaptive.heatmap) {
+        if let Err(e) = buffer.sample_heatmap().save(path.with_file_name(format!("{}_samples.png", stem))) {
+            println!("Error saving sample heatmap: {}", e);
+        }
+    }
+
+    if let Some(denoiser) = params.denoiser {
+        let denoised = path.with_file_name(format!("{}_denoised.png", stem));
+
+        match denoise_image(buffer, denoiser) {
+            Ok(image) => if let Err(e) = image.save(&denoised) {
+                println!("Error saving denoised image: {}", e);
+            },
+            Err(e) => println!("{}", e)
+        }
+    }
+}
+
+// Renders the image once and saves it as output.png with its extras. Used
+// without the viewer, which refines its image itself.
+#[cfg(not(feature = "viewer"))]
+pub fn run_rt(params: &RTParams, scene: &Scene) -> Result<(), ImageError> {
+    let start = Instant::now();
+    let buffer = render_buffer(params, scene);
+    println!("Total time taken for rt: {:.1}s", start.elapsed().as_secs_f64());
+
+    let path = Path::new("output.png");
+    buffer.to_image().save(path)?;
+    save_extras(params, &buffer, path);
+
+    Ok(())
+}
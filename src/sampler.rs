@@ -0,0 +1,571 @@
+use std::sync::OnceLock;
+use rand::Rng;
+
+// Source of the numbers that place samples: pixel position, lens, time, light
+// choice and scattering directions. Every pixel sample asks for its dimensions
+// in the same order, so samplers that spread points evenly over several
+// dimensions at once can do so. All values are in [0, 1).
+pub trait Sampler {
+    // Starts sample `index` of pixel (px, py) at its first dimension
+    fn start_pixel_sample(&mut self, px: u32, py: u32, index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    // Uncorrelated random numbers
+    Independent,
+    // Jittered strata, shuffled independently for each dimension
+    Stratified,
+    // Halton sequence with per-pixel Owen scrambling
+    Halton,
+    // Sobol points, Owen scrambled per pixel and padded pairwise across
+    // dimensions
+    Sobol,
+    // The same Sobol points in every pixel, shifted by a blue-noise mask so the
+    // remaining error looks like fine grain instead of blotches
+    BlueNoise
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" => Some(SamplerKind::BlueNoise),
+            _ => None
+        }
+    }
+
+    pub fn create(&self, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new()),
+            SamplerKind::Sobol => Box::new(SobolSampler::new()),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new())
+        }
+    }
+}
+
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _px: u32, _py: u32, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        rand::thread_rng().gen::<f64>()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let mut rng = rand::thread_rng();
+        (rng.gen::<f64>(), rng.gen::<f64>())
+    }
+}
+
+// Each dimension is split into `samples_per_pixel` strata, or a grid of about
+// as many cells for pairs, and every sample of a pixel lands in its own
+// stratum. Strata are visited in a different order for each dimension so the
+// dimensions are not correlated.
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    nx: u32,
+    ny: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let nx = (samples_per_pixel as f64).sqrt().ceil() as u32;
+
+        StratifiedSampler {
+            samples_per_pixel,
+            nx,
+            ny: samples_per_pixel.div_ceil(nx),
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, px: u32, py: u32, index: u32) {
+        self.pixel_seed = hash(&[px as u64, py as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = hash(&[self.pixel_seed, self.dimension as u64]);
+        let stratum = permutation_element(self.index % self.samples_per_pixel, self.samples_per_pixel, seed as u32);
+        let jitter = hash_float(&[seed, self.index as u64]);
+        self.dimension += 1;
+
+        (stratum as f64 + jitter) / self.samples_per_pixel as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        // With fewer samples than cells each sample takes a distinct, randomly
+        // chosen cell
+        let cells = self.nx * self.ny;
+        let seed = hash(&[self.pixel_seed, self.dimension as u64]);
+        let cell = permutation_element(self.index % cells, cells, seed as u32);
+        let jx = hash_float(&[seed, self.index as u64, 0]);
+        let jy = hash_float(&[seed, self.index as u64, 1]);
+        self.dimension += 2;
+
+        (((cell % self.nx) as f64 + jx) / self.nx as f64,
+         ((cell / self.nx) as f64 + jy) / self.ny as f64)
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+
+// Radical inverse of the sample index in a different prime base per
+// dimension. Dimensions past the prime table fall back to hashed random values.
+pub struct HaltonSampler {
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl HaltonSampler {
+    pub fn new() -> HaltonSampler {
+        HaltonSampler { pixel_seed: 0, index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, px: u32, py: u32, index: u32) {
+        self.pixel_seed = hash(&[px as u64, py as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension as usize;
+        let seed = hash(&[self.pixel_seed, dimension as u64]);
+        self.dimension += 1;
+
+        match PRIMES.get(dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.index as u64, seed),
+            None => hash_float(&[seed, self.index as u64])
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Owen-scrambled Sobol points. Only the first two Sobol dimensions are used;
+// higher dimensions reuse them with the sample order shuffled per pair, which
+// keeps every pair well stratified without a table of direction numbers.
+pub struct SobolSampler {
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl SobolSampler {
+    pub fn new() -> SobolSampler {
+        SobolSampler { pixel_seed: 0, index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, px: u32, py: u32, index: u32) {
+        self.pixel_seed = hash(&[px as u64, py as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let u = sobol_1d(self.index, hash(&[self.pixel_seed, self.dimension as u64]));
+        self.dimension += 1;
+        u
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let u = sobol_2d(self.index, hash(&[self.pixel_seed, self.dimension as u64]));
+        self.dimension += 2;
+        u
+    }
+}
+
+// Dithered Sobol: every pixel uses the same scrambled points, rotated by a
+// blue-noise value that differs per dimension. Neighbouring pixels then err in
+// different directions, pushing the error to high frequencies.
+pub struct BlueNoiseSampler {
+    px: u32,
+    py: u32,
+    index: u32,
+    dimension: u32
+}
+
+impl BlueNoiseSampler {
+    pub fn new() -> BlueNoiseSampler {
+        BlueNoiseSampler { px: 0, py: 0, index: 0, dimension: 0 }
+    }
+
+    // Mask value for this pixel, moved around the tiled mask for each dimension
+    fn shift(&self, dimension: u32) -> f64 {
+        let offset = hash(&[dimension as u64, 0x5bd1e995]);
+        let x = (self.px as usize + (offset & 0xffff) as usize) % MASK_SIZE;
+        let y = (self.py as usize + ((offset >> 16) & 0xffff) as usize) % MASK_SIZE;
+
+        blue_noise_mask()[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, px: u32, py: u32, index: u32) {
+        self.px = px;
+        self.py = py;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let u = sobol_1d(self.index, hash(&[self.dimension as u64]));
+        let shift = self.shift(self.dimension);
+        self.dimension += 1;
+
+        (u + shift).fract()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (u, v) = sobol_2d(self.index, hash(&[self.dimension as u64]));
+        let (su, sv) = (self.shift(self.dimension), self.shift(self.dimension + 1));
+        self.dimension += 2;
+
+        ((u + su).fract(), (v + sv).fract())
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+fn hash_float(values: &[u64]) -> f64 {
+    (hash(values) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Element `i` of a random permutation of 0..n chosen by `seed`, without
+// building the permutation (Kensler, "Correlated Multi-Jittered Sampling")
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < n {
+            break;
+        }
+    }
+
+    (i.wrapping_add(seed)) % n
+}
+
+// Digits of `a` in `base` mirrored around the radix point, with every digit
+// permuted depending on the digits before it
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut value = 0.0;
+    // The digits so far, only used to seed the next permutation. Large bases
+    // need more than 64 bits for all of them, so this is allowed to wrap.
+    let mut prefix = 0u64;
+
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_seed = mix_bits(seed ^ prefix) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_seed) as u64;
+
+        prefix = prefix.wrapping_mul(base).wrapping_add(digit);
+        inv_base_m *= inv_base;
+        value += digit as f64 * inv_base_m;
+        a = next;
+    }
+
+    value.min(ONE_MINUS_EPSILON)
+}
+
+// Owen scrambling of the bits of `x`, top bit first (Burley, "Practical
+// Hash-based Owen Scrambling")
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn to_unit(x: u32) -> f64 {
+    (x as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+fn sobol_1d(index: u32, seed: u64) -> f64 {
+    let index = nested_uniform_scramble(index, seed as u32);
+    to_unit(nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32))
+}
+
+fn sobol_2d(index: u32, seed: u64) -> (f64, f64) {
+    let index = nested_uniform_scramble(index, seed as u32);
+
+    // Second Sobol dimension: each direction number is the previous one xor
+    // itself shifted right by one
+    let mut y = 0u32;
+    let mut direction = 1u32 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            y ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    let seed_y = mix_bits(seed);
+    (to_unit(nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32)),
+     to_unit(nested_uniform_scramble(y, (seed_y >> 32) as u32)))
+}
+
+const MASK_SIZE: usize = 64;
+
+// Tileable blue-noise mask of ranks mapped to [0, 1), built once with
+// Ulichney's void-and-cluster method
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+fn void_and_cluster() -> Vec<f64> {
+    const N: usize = MASK_SIZE * MASK_SIZE;
+    const SIGMA: f64 = 1.5;
+
+    // Gaussian energy between cells, wrapping around the edges
+    let kernel: Vec<f64> = (0..N).map(|i| {
+        let (x, y) = (i % MASK_SIZE, i / MASK_SIZE);
+        let dx = x.min(MASK_SIZE - x) as f64;
+        let dy = y.min(MASK_SIZE - y) as f64;
+        (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+    }).collect();
+
+    let update = |energy: &mut [f64], cell: usize, sign: f64| {
+        let (cx, cy) = (cell % MASK_SIZE, cell / MASK_SIZE);
+        for y in 0..MASK_SIZE {
+            for x in 0..MASK_SIZE {
+                let dx = (x + MASK_SIZE - cx) % MASK_SIZE;
+                let dy = (y + MASK_SIZE - cy) % MASK_SIZE;
+                energy[y * MASK_SIZE + x] += sign * kernel[dy * MASK_SIZE + dx];
+            }
+        }
+    };
+
+    // Tightest cluster among set cells, or largest void among empty ones
+    let extreme = |energy: &[f64], points: &[bool], set: bool| -> usize {
+        let candidates = (0..N).filter(|&i| points[i] == set);
+        if set {
+            candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+        } else {
+            candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+        }
+    };
+
+    // Random initial pattern, relaxed by moving the tightest cluster into the
+    // largest void until that no longer changes anything
+    let initial_count = N / 10;
+    let mut points = vec![false; N];
+    let mut energy = vec![0.0; N];
+    let mut placed = 0;
+    let mut attempt = 0u64;
+    while placed < initial_count {
+        let cell = (hash(&[attempt]) % N as u64) as usize;
+        attempt += 1;
+        if !points[cell] {
+            points[cell] = true;
+            update(&mut energy, cell, 1.0);
+            placed += 1;
+        }
+    }
+
+    for _ in 0..N {
+        let cluster = extreme(&energy, &points, true);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+
+        let void = extreme(&energy, &points, false);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; N];
+
+    // Ranks below the initial pattern come from removing its tightest clusters
+    let (mut phase_points, mut phase_energy) = (points.clone(), energy.clone());
+    for r in (0..initial_count).rev() {
+        let cluster = extreme(&phase_energy, &phase_points, true);
+        phase_points[cluster] = false;
+        update(&mut phase_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // The rest fill the largest voids in turn
+    for r in initial_count..N {
+        let void = extreme(&energy, &points, false);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.into_iter().map(|r| (r as f64 + 0.5) / N as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Number of the interval of width 1/n that `u` falls into
+    fn stratum(u: f64, n: u32) -> usize {
+        assert!((0.0..1.0).contains(&u), "{} is outside [0, 1)", u);
+        (u * n as f64) as usize
+    }
+
+    // The first `n` samples of one pixel, each drawn after skipping `skip` dimensions
+    fn samples_1d(sampler: &mut dyn Sampler, n: u32, skip: u32) -> Vec<f64> {
+        (0..n).map(|i| {
+            sampler.start_pixel_sample(3, 7, i);
+            for _ in 0..skip {
+                sampler.get_1d();
+            }
+            sampler.get_1d()
+        }).collect()
+    }
+
+    fn samples_2d(sampler: &mut dyn Sampler, n: u32, skip: u32) -> Vec<(f64, f64)> {
+        (0..n).map(|i| {
+            sampler.start_pixel_sample(3, 7, i);
+            for _ in 0..skip {
+                sampler.get_2d();
+            }
+            sampler.get_2d()
+        }).collect()
+    }
+
+    fn assert_stratified_1d(samples: &[f64]) {
+        let n = samples.len() as u32;
+        let mut hits = vec![0; n as usize];
+        for &u in samples {
+            hits[stratum(u, n)] += 1;
+        }
+        assert!(hits.iter().all(|&h| h == 1), "{:?}", samples);
+    }
+
+    // One sample in every cell of a `cells` x `cells` grid
+    fn assert_stratified_2d(samples: &[(f64, f64)], cells: u32) {
+        let mut hits = vec![0; (cells * cells) as usize];
+        for &(u, v) in samples {
+            hits[stratum(v, cells) * cells as usize + stratum(u, cells)] += 1;
+        }
+        assert!(hits.iter().all(|&h| h == 1), "{:?}", samples);
+    }
+
+    #[test]
+    fn halton_stratifies_each_dimension_by_its_base() {
+        let mut sampler = HaltonSampler::new();
+        assert_stratified_1d(&samples_1d(&mut sampler, 16, 0));
+        assert_stratified_1d(&samples_1d(&mut sampler, 9, 1));
+        assert_stratified_1d(&samples_1d(&mut sampler, 25, 2));
+    }
+
+    #[test]
+    fn halton_stays_in_range_in_every_dimension() {
+        // Large bases have more digits than fit in 64 bits, and dimensions past
+        // the prime table fall back to hashed values
+        let mut sampler = HaltonSampler::new();
+        for i in [0, 1, 17, 1000, u32::MAX] {
+            sampler.start_pixel_sample(3, 7, i);
+            for _ in 0..PRIMES.len() + 4 {
+                stratum(sampler.get_1d(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_stratifies_pairs_and_padded_dimensions() {
+        let mut sampler = SobolSampler::new();
+        assert_stratified_1d(&samples_1d(&mut sampler, 16, 0));
+        assert_stratified_2d(&samples_2d(&mut sampler, 16, 0), 4);
+        assert_stratified_2d(&samples_2d(&mut sampler, 64, 3), 8);
+    }
+
+    #[test]
+    fn samplers_repeat_per_pixel_and_differ_between_pixels() {
+        let samplers: Vec<Box<dyn Sampler>> = vec![
+            Box::new(HaltonSampler::new()),
+            Box::new(SobolSampler::new()),
+            Box::new(BlueNoiseSampler::new())
+        ];
+
+        for mut sampler in samplers {
+            sampler.start_pixel_sample(1, 2, 5);
+            let first = (sampler.get_1d(), sampler.get_2d());
+            sampler.start_pixel_sample(1, 2, 5);
+            assert_eq!(first, (sampler.get_1d(), sampler.get_2d()));
+
+            sampler.start_pixel_sample(2, 1, 5);
+            assert_ne!(first, (sampler.get_1d(), sampler.get_2d()));
+        }
+    }
+}
@@ -1,45 +1,86 @@
+use std::f64::consts::PI;
 use std::sync::Arc;
-use image::io::Reader;
-use image::RgbaImage;
-use noise::Turbulence;
 use rand::Rng;
-use crate::{Camera, Color, HitList, Hittable, Materials, Point3, Sphere, Vec3};
+use crate::{Color, HitList, Hittable, Materials, Point3, Sphere, Vec3};
+use crate::material::AlphaMode;
+use crate::aabb::AABB;
 use crate::bvh::BvhNode;
+use crate::camera::{Camera, Focus, Lens, PerspectiveCamera, Shutter};
+use crate::cone::Cone;
+use crate::constant_medium::ConstantMedium;
+use crate::cuboid::Cuboid;
+use crate::cylinder::Cylinder;
+use crate::film::Film;
+use crate::mipmap::MipMap;
+use crate::fisheye::FisheyeCamera;
+use crate::disk::Disk;
+use crate::perlin::{NoiseParams, Perlin};
+use crate::plane::Plane;
+use crate::ply::PlyMesh;
+use crate::quad::Quad;
+use crate::sampler::IndependentSampler;
 use crate::texture::Texture;
-use crate::texture::Texture::{Checker, SolidColor};
+use crate::texture_cache::TextureCache;
+use crate::texture::Texture::SolidColor;
+use crate::texture::TextureSpace;
+use crate::heterogeneous_medium::HeterogeneousMedium;
+use crate::medium::{Fog, PhaseFunction};
+use crate::orthographic::OrthographicCamera;
+use crate::panorama::{EquirectangularCamera, OmniStereoCamera};
+use crate::moving_sphere::MovingSphere;
+use crate::transform::{AnimatedInstance, Instance, Mat4, Pose, Quat, Transformed};
+use crate::torus::Torus;
 use crate::triangle::Triangle;
+use crate::voxel_grid::VoxelGrid;
 
-use std::fs::File;
-use std::io::BufReader;
 use itertools::Itertools;
 use tobj::{load_obj, LoadOptions};
 
 pub struct Scene {
     pub hit_list: HitList,
-    pub camera: Camera,
-    pub sky_color: Color
+    pub lights: HitList,
+    pub camera: Box<dyn Camera>,
+    pub sky_color: Color,
+    pub fog: Option<Fog>
 }
 
-pub fn random_scene() -> Scene {
+// Builder of the scene called `name` on the command line, None for names that
+// are not known. Builders load image textures through the cache they are given,
+// which the caller shares between all the scenes it builds.
+pub fn from_name(name: &str) -> Option<fn(&Film, &TextureCache) -> Scene> {
+    let build: fn(&Film, &TextureCache) -> Scene = match name {
+        "random" => random_scene,
+        "triangles" => tri_test,
+        "shapes" => shapes_test,
+        "motion" => motion_test,
+        "fog" => fog_test,
+        "volume" => volume_test,
+        "noise" => noise_test,
+        "bump" => bump_test,
+        "textures" => texture_graph_test,
+        "lens" => lens_test,
+        "orthographic" => |film, textures| projection_test(film, textures, "orthographic"),
+        "fisheye" => |film, textures| projection_test(film, textures, "fisheye"),
+        "equirectangular" => |film, textures| projection_test(film, textures, "equirectangular"),
+        "stereo" => |film, textures| projection_test(film, textures, "stereo"),
+        _ => return None
+    };
+
+    Some(build)
+}
+
+pub fn random_scene(film: &Film, textures: &TextureCache) -> Scene {
     let mut world = HitList::new();
 
     let mat_ground: Materials = Materials::Lambertian {
-        albedo: Checker {
-            texture_odd: Arc::from(Texture::SolidColor {
-                color_value: Color::new(0.2, 0.3, 0.1)
-            }),
-            texture_even: Arc::from(Texture::SolidColor {
-                color_value: Color::new(0.9, 0.9, 0.9)
-            }),
-        }
+        albedo: Texture::checker(
+            Color::new(0.2, 0.3, 0.1),
+            Color::new(0.9, 0.9, 0.9),
+            3.0,
+            TextureSpace::WorldPlanar
+        )
     };
 
-    world.add(Arc::new(Sphere {
-        center: Point3::new(0.0, -1000.0, 0.0),
-        radius: 1000.0,
-        material: mat_ground.clone(),
-    }));
-
     let mut rng = rand::thread_rng();
 
     for a in -33..33 {
@@ -55,7 +96,7 @@ pub fn random_scene() -> Scene {
             if (origin - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 let mat = if mat_rand < 0.8 {
                     let albedo = Color::random() * Color::random();
-                    Materials::Lambertian { albedo: SolidColor {color_value: albedo.clone()}}
+                    Materials::Lambertian { albedo: SolidColor {color_value: albedo}}
                 }
                 else if mat_rand < 0.95 {
                     let albedo = Color::random_range(0.5, 1.0);
@@ -77,39 +118,33 @@ pub fn random_scene() -> Scene {
         }
     }
 
+    let earth = textures.image_texture("earthmap.jpg").unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        SolidColor { color_value: Color::new(0.5, 0.5, 0.5) }
+    });
+    println!("{} textures, {} KiB", textures.len(), textures.memory_usage() / 1024);
+
     let mat_center = Materials::Lambertian {
-        albedo: Texture::Image {
-            image: Arc::from(Reader::open("earthmap.jpg").unwrap().decode().unwrap().to_rgba8())
-        }
-        /*albedo: Texture::Perlin {
-            turbulence: Turbulence::new(noise::Perlin::new())
+        albedo: earth
+        /*albedo: Texture::Marble {
+            noise: Arc::new(Perlin::new(0)),
+            params: NoiseParams::default(),
+            color_a: Color::new(0.1, 0.1, 0.1),
+            color_b: Color::new(1, 1, 1),
+            scale: 10.0,
+            distortion: 10.0
         }*/
     };
 
-    let mat_left = Materials::DiElectric {
-        ir: 1.5
-
-    };
-    let mat_right = Materials::Metal {
-        albedo: Color::new(0.7, 0.6, 0.5),
-        fuzz: 0.0,
-    };
-
     let mat_left = Materials::DiffuseLight {
         tex: Texture::SolidColor {color_value: Color::new(4,4,4)}
     };
 
     {
-        let mut options = LoadOptions::default();
-        options.triangulate = true;
+        let options = LoadOptions { triangulate: true, ..Default::default() };
         let (model, _) = load_obj("xyzrgb_dragon.obj", &options)
             .expect("Error loading OBJ file.");
 
-        let factor = *model[0].mesh.positions.iter().reduce(|a, b| {
-            if a > b { a } else { b }
-        }).unwrap_or(&1f32) as f64;
-        let factor = 0.5;
-
         let mut mdl_bvh = HitList::new();
 
         for mut chunk in &model[0].mesh.indices.iter().chunks(3) {
@@ -119,21 +154,21 @@ pub fn random_scene() -> Scene {
 
             let v0 = Point3::new(
                 model[0].mesh.positions[i0 * 3],
-                model[0].mesh.positions[i0 * 3 + 1]* -1f32,
+                model[0].mesh.positions[i0 * 3 + 1],
                 model[0].mesh.positions[i0 * 3 + 2]
-            ) / factor * -1f64;
+            );
 
             let v1 = Point3::new(
                 model[0].mesh.positions[i1 * 3],
-                model[0].mesh.positions[i1 * 3 + 1]* -1f32,
+                model[0].mesh.positions[i1 * 3 + 1],
                 model[0].mesh.positions[i1 * 3 + 2]
-            ) / factor * -1f64;
+            );
 
             let v2 = Point3::new(
                 model[0].mesh.positions[i2 * 3],
-                model[0].mesh.positions[i2 * 3 + 1]* -1f32,
+                model[0].mesh.positions[i2 * 3 + 1],
                 model[0].mesh.positions[i2 * 3 + 2]
-            ) / factor * -1f64;
+            );
 
             mdl_bvh.add(Arc::new(Triangle::new_with(
                 v0,
@@ -147,9 +182,15 @@ pub fn random_scene() -> Scene {
             )));
         }
 
-        println!("{} {}", factor, model[0].mesh.indices.len());
+        println!("{}", model[0].mesh.indices.len());
 
-        world.add(Arc::new(BvhNode::new_from_hitlist(&mdl_bvh, 0f64, 1f64)));
+        // Turn the scan around to face the camera and double its size
+        if let Some(scan) = Transformed::new(
+            Arc::new(BvhNode::new_from_hitlist(&mdl_bvh, 0f64, 1f64)),
+            Mat4::rotate_y(180.0) * Mat4::uniform_scale(2.0)
+        ) {
+            world.add(Arc::new(scan));
+        }
     }
 
     /*world.add(Arc::new(Sphere {
@@ -172,23 +213,31 @@ pub fn random_scene() -> Scene {
 
     world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
 
-    let cam = Camera::new(
+    // The ground plane is unbounded, so it has to stay outside of the BVH
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    // The old 70 degree pinhole view: a 16:9 crop of the full frame sensor is
+    // 20.25mm tall, which a 14.46mm lens spans at 70 degrees, and at f/1446
+    // the aperture is the old 0.01mm, so everything stays sharp. It focuses
+    // on the dragon at the origin regardless.
+    let lens = Lens::new_with(14.46, 1446.0, Focus::Point(Point3::new_empty()));
+    let cam = PerspectiveCamera::new_physical(
         Point3::new(0, 3, -5),
         Point3::new(0, 0, 0),
         Vec3::new(0, 1, 0),
-        70.0,
-        16.0 / 9.0,
-        0.00001,
-        10.0);
+        lens,
+        film.aspect_ratio());
 
     Scene {
         hit_list: world,
-        camera: cam,
-        sky_color: Color::new(0.7,0.8,1.0)
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7,0.8,1.0),
+        fog: None
     }
 }
 
-pub fn tri_test() -> Scene {
+pub fn tri_test(film: &Film, _textures: &TextureCache) -> Scene {
     let mut world = HitList::new();
 
     let mat_ground: Materials = Materials::Lambertian {
@@ -213,8 +262,7 @@ pub fn tri_test() -> Scene {
     )));
 
     // Load sample mesh
-    let mut options = LoadOptions::default();
-    options.triangulate = true;
+    let options = LoadOptions { triangulate: true, ..Default::default() };
     let (model, _) = load_obj("pumpkin_tall_10k.obj", &options)
         .expect("Error loading OBJ file.");
 
@@ -257,18 +305,851 @@ pub fn tri_test() -> Scene {
 
     world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
 
-    let cam = Camera::new(
+    let cam = PerspectiveCamera::new(
         Point3::new(0, 3, -5),
         Point3::new(0, 0, 0),
         Vec3::new(0, 1, 0),
         70.0,
-        16.0 / 9.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7,0.8,1),
+        fog: None
+    }
+}
+
+// The mesh in the PLY file at `path`, scaled to about two units and standing
+// on a ground plane. Vertex colours are used when the file has them.
+pub fn ply_scene(film: &Film, path: &str) -> Scene {
+    let mut world = HitList::new();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.5, 0.5, 0.5)
+        }
+    };
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    match PlyMesh::load(path) {
+        Ok(mesh) if !mesh.positions.is_empty() => {
+            // Move the mesh onto the ground at the origin
+            let (min, max) = mesh.positions.iter().fold(
+                (Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)),
+                |(min, max), p| (
+                    Point3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+                    Point3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()))
+                ));
+            let size = max - min;
+            let scale = 2.0 / size.x().max(size.y()).max(size.z()).max(1e-12);
+            let offset = Vec3::new(-(min.x() + max.x()) / 2.0, -min.y(), -(min.z() + max.z()) / 2.0);
+
+            let mesh_list = mesh.to_hitlist_vertex_colored();
+            if let Some(placed) = Transformed::new(
+                Arc::new(BvhNode::new_from_hitlist(&mesh_list, 0.0, 1.0)),
+                Mat4::uniform_scale(scale) * Mat4::translate(offset)
+            ) {
+                world.add(Arc::new(placed));
+            }
+            println!("Loaded {} triangles from {}", mesh.triangle_count(), path);
+        },
+        Ok(_) => println!("{} has no vertices", path),
+        Err(e) => println!("{}", e)
+    }
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 2, -4),
+        Point3::new(0, 0.8, 0),
+        Vec3::new(0, 1, 0),
+        50.0,
+        film.aspect_ratio(),
+        0.0,
+        4.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7, 0.8, 1.0),
+        fog: None
+    }
+}
+
+pub fn shapes_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let mut lights = HitList::new();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.8, 0.8, 0.8)
+        }
+    };
+
+    let mat_red = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.7, 0.2, 0.2)
+        }
+    };
+
+    let mat_blue = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.2, 0.3, 0.7)
+        }
+    };
+
+    let mat_metal = Materials::Metal {
+        albedo: Color::new(0.8, 0.8, 0.8),
+        fuzz: 0.1
+    };
+
+    let mat_light = Materials::DiffuseLight {
+        tex: Texture::SolidColor {color_value: Color::new(6, 6, 6)}
+    };
+
+    // Solid checker, so the cells carry on across the edges of the box
+    world.add(Arc::new(Cuboid::new_with(
+        Point3::new(-3.5, 0, -0.5),
+        Point3::new(-2.5, 1, 0.5),
+        Materials::Lambertian {
+            albedo: Texture::checker(Color::new(0.7, 0.2, 0.2), Color::new(0.9, 0.9, 0.9), 4.0, TextureSpace::World)
+        }
+    )));
+
+    if let Some(cuboid) = Cuboid::new_oriented(
+        Point3::new(-1.2, 0.5, 1.5),
+        Vec3::new(1, 1, 1),
+        Mat4::rotate_y(30.0),
+        mat_blue.clone()
+    ) {
+        world.add(Arc::new(cuboid));
+    }
+
+    world.add(Arc::new(Cylinder::new_with(
+        Point3::new(-1, 0, -1),
+        Vec3::new(0, 1, 0),
+        0.5,
+        1.2,
+        mat_metal.clone()
+    )));
+
+    world.add(Arc::new(Cone::new_with(
+        Point3::new(1, 0, -1),
+        Vec3::new(0, 1, 0),
+        0.6,
+        1.4,
+        mat_red.clone()
+    )));
+
+    world.add(Arc::new(Torus::new_with(
+        Point3::new(3, 0.8, 0),
+        Vec3::new(0, 0, 1),
+        0.6,
+        0.2,
+        mat_blue.clone()
+    )));
+
+    // One ring placed three times in the back row, two of them repainted
+    let ring: Arc<dyn Hittable> = Arc::new(Torus::new_with(
+        Point3::new_empty(),
+        Vec3::new(0, 1, 0),
+        0.5,
+        0.15,
+        mat_metal.clone()
+    ));
+
+    let placements = [
+        (Mat4::translate(Vec3::new(-2.5, 0.8, 3)) * Mat4::rotate_x(90.0), None),
+        (Mat4::translate(Vec3::new(0, 0.8, 3)) * Mat4::rotate_z(90.0), Some(mat_red)),
+        (Mat4::translate(Vec3::new(2, 0.8, 3)) * Mat4::rotate_x(45.0) * Mat4::rotate_z(45.0), Some(mat_blue))
+    ];
+
+    for (matrix, material) in placements {
+        let instance = match material {
+            Some(material) => Instance::new_with_material(ring.clone(), matrix, material),
+            None => Instance::new(ring.clone(), matrix)
+        };
+
+        if let Some(instance) = instance {
+            world.add(Arc::new(instance));
+        }
+    }
+
+    world.add(Arc::new(Disk::new_with(
+        Point3::new(1.2, 0.01, 1.5),
+        Vec3::new(0, 1, 0),
+        0.6,
+        mat_metal
+    )));
+
+    // Area lights are added to both the world and the list used for light sampling
+    let quad_light = Arc::new(Quad::new_with(
+        Point3::new(-1, 4, -1),
+        Vec3::new(2, 0, 0),
+        Vec3::new(0, 0, 2),
+        mat_light.clone()
+    ));
+    world.add(quad_light.clone());
+    lights.add(quad_light);
+
+    let disk_light = Arc::new(Disk::new_with(
+        Point3::new(3, 3, 2),
+        Vec3::new(-1, -1, -1),
+        0.5,
+        mat_light
+    ));
+    world.add(disk_light.clone());
+    lights.add(disk_light);
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 3, -6),
+        Point3::new(0, 0.5, 0),
+        Vec3::new(0, 1, 0),
+        60.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights,
+        camera: Box::new(cam),
+        sky_color: Color::new(0.1, 0.1, 0.15),
+        fog: None
+    }
+}
+
+pub fn motion_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let mut rng = rand::thread_rng();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.8, 0.8, 0.8)
+        }
+    };
+
+    // Spheres bouncing upwards while the shutter is open
+    for a in -4..4 {
+        for b in -2..2 {
+            let center = Point3::new(a as f64 + 0.5, 0.3, b as f64 + 0.5);
+
+            world.add(Arc::new(MovingSphere::new_with(
+                center,
+                center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0),
+                0.0,
+                1.0,
+                0.3,
+                Materials::Lambertian {
+                    albedo: SolidColor { color_value: Color::random() * Color::random() }
+                }
+            )));
+        }
+    }
+
+    // A box that spins half a turn and slides sideways during the exposure
+    let cube: Arc<dyn Hittable> = Arc::new(Cuboid::new_with(
+        Point3::new(-0.5, -0.5, -0.5),
+        Point3::new(0.5, 0.5, 0.5),
+        Materials::Metal {
+            albedo: Color::new(0.8, 0.6, 0.4),
+            fuzz: 0.05
+        }
+    ));
+
+    world.add(Arc::new(AnimatedInstance::new(
+        cube.clone(),
+        Pose::new(Vec3::new(-1.0, 1.5, 2.5), Quat::identity(), Vec3::new(1, 1, 1)),
+        Pose::new(Vec3::new(1.0, 1.5, 2.5), Quat::from_axis_angle(Vec3::new(0, 1, 0), 90.0), Vec3::new(1, 1, 1)),
+        0.0,
+        1.0
+    )));
+
+    // The same box in glass, rising out of the ground as it turns
+    world.add(Arc::new(AnimatedInstance::new_with_material(
+        cube,
+        Pose::new(Vec3::new(0.0, -0.5, -2.7), Quat::identity(), Vec3::new(1, 1, 1)),
+        Pose::new(Vec3::new(0.0, 0.5, -2.7), Quat::from_axis_angle(Vec3::new(0, 1, 0), 45.0), Vec3::new(1, 1, 1)),
+        0.0,
+        1.0,
+        Materials::DiElectric { ir: 1.5 }
+    )));
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    let cam = Shutter::new_with(PerspectiveCamera::new(
+        Point3::new(0, 3, -6),
+        Point3::new(0, 0.5, 0),
+        Vec3::new(0, 1, 0),
+        60.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0), 0.0, 1.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7,0.8,1.0),
+        fog: None
+    }
+}
+
+pub fn fog_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let mut lights = HitList::new();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.8, 0.8, 0.8)
+        }
+    };
+
+    let mat_light = Materials::DiffuseLight {
+        tex: Texture::SolidColor {color_value: Color::new(8, 8, 8)}
+    };
+
+    // Dense white smoke in a box, and a forward scattering haze in a sphere
+    let smoke_boundary: Arc<dyn Hittable> = Arc::new(Cuboid::new_with(
+        Point3::new(-2.5, 0, -0.5),
+        Point3::new(-0.5, 2, 1.5),
+        mat_ground.clone()
+    ));
+    world.add(Arc::new(ConstantMedium::new(
+        smoke_boundary,
+        1.5,
+        Texture::SolidColor { color_value: Color::new(0.9, 0.9, 0.9) }
+    )));
+
+    let haze_boundary: Arc<dyn Hittable> = Arc::new(Sphere {
+        center: Point3::new(1.5, 1.0, 0.5),
+        radius: 1.0,
+        material: mat_ground.clone(),
+    });
+    world.add(Arc::new(ConstantMedium::new_with_phase(
+        haze_boundary,
+        2.0,
+        Texture::SolidColor { color_value: Color::new(0.8, 0.5, 0.3) },
+        PhaseFunction::HenyeyGreenstein { g: 0.7 }
+    )));
+
+    let quad_light = Arc::new(Quad::new_with(
+        Point3::new(-1, 4, -1),
+        Vec3::new(2, 0, 0),
+        Vec3::new(0, 0, 2),
+        mat_light
+    ));
+    world.add(quad_light.clone());
+    lights.add(quad_light);
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 3, -6),
+        Point3::new(0, 0.8, 0),
+        Vec3::new(0, 1, 0),
+        60.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights,
+        camera: Box::new(cam),
+        sky_color: Color::new(0.3, 0.35, 0.4),
+        fog: Some(Fog {
+            center: Point3::new_empty(),
+            radius: 50.0,
+            density: 0.02,
+            albedo: Color::new(0.8, 0.8, 0.8),
+            phase: PhaseFunction::Isotropic
+        })
+    }
+}
+
+pub fn volume_test(film: &Film, _textures: &TextureCache) -> Scene {
+    // Procedural cloud: a few overlapping soft blobs falling off towards the edges
+    let n = 64;
+    let blobs = [
+        (Vec3::new(0.5, 0.45, 0.5), 0.3),
+        (Vec3::new(0.3, 0.4, 0.45), 0.2),
+        (Vec3::new(0.7, 0.4, 0.55), 0.22),
+        (Vec3::new(0.5, 0.65, 0.5), 0.18),
+    ];
+    let mut data = vec![0.0f32; n * n * n];
+    for k in 0..n {
+        for j in 0..n {
+            for i in 0..n {
+                let p = Vec3::new((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64, (k as f64 + 0.5) / n as f64);
+                let density = blobs.iter()
+                    .map(|(c, r)| (1.0 - (p - *c).length() / r).max(0.0))
+                    .fold(0.0, f64::max);
+                data[(k * n + j) * n + i] = density as f32;
+            }
+        }
+    }
+
+    let grid = VoxelGrid::new_dense(
+        [n, n, n],
+        AABB::new(&Point3::new(-2, 0, -2), &Point3::new(2, 4, 2)),
+        data
+    ).expect("Invalid procedural volume");
+
+    volume_scene(film, grid)
+}
+
+// `grid` as a cloud standing on a ground plane, lit from above, with the
+// camera framing its bounds
+pub fn volume_scene(film: &Film, grid: VoxelGrid) -> Scene {
+    let mut world = HitList::new();
+    let mut lights = HitList::new();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::SolidColor {
+            color_value: Color::new(0.8, 0.8, 0.8)
+        }
+    };
+
+    let mat_light = Materials::DiffuseLight {
+        tex: Texture::SolidColor {color_value: Color::new(10, 10, 10)}
+    };
+
+    let (min, max) = (grid.bounds().min(), grid.bounds().max());
+    let center = (min + max) / 2.0;
+    let size = (max - min).x().max((max - min).y()).max((max - min).z());
+
+    world.add(Arc::new(HeterogeneousMedium::new_in_bounds(
+        Arc::new(grid),
+        6.0,
+        Color::new(0.9, 0.9, 0.9),
+        PhaseFunction::HenyeyGreenstein { g: 0.4 }
+    )));
+
+    let quad_light = Arc::new(Quad::new_with(
+        Point3::new(center.x() - 0.25 * size, max.y() + 0.5 * size, center.z() - 0.25 * size),
+        Vec3::new(0.5 * size, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 0.5 * size),
+        mat_light
+    ));
+    world.add(quad_light.clone());
+    lights.add(quad_light);
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(Point3::new(0.0, min.y(), 0.0), Vec3::new(0, 1, 0), mat_ground)));
+
+    let cam = PerspectiveCamera::new(
+        center + Vec3::new(0.0, 0.25, -2.0) * size,
+        center - Vec3::new(0.0, 0.05, 0.0) * size,
+        Vec3::new(0, 1, 0),
+        50.0,
+        film.aspect_ratio(),
         0.00001,
         10.0);
 
     Scene {
         hit_list: world,
-        camera: cam,
-        sky_color: Color::new(0.7,0.8,1)
+        lights,
+        camera: Box::new(cam),
+        sky_color: Color::new(0.2, 0.25, 0.35),
+        fog: None
     }
-}
\ No newline at end of file
+}
+
+pub fn noise_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let noise = Arc::new(Perlin::new(42));
+
+    let textures = vec![
+        Texture::Marble {
+            noise: noise.clone(),
+            params: NoiseParams::new_with(4.0, 7, 2.0, 0.5),
+            color_a: Color::new(0.15, 0.15, 0.2),
+            color_b: Color::new(0.95, 0.95, 0.9),
+            scale: 6.0,
+            distortion: 8.0
+        },
+        Texture::Wood {
+            noise: noise.clone(),
+            params: NoiseParams::new_with(1.5, 4, 2.0, 0.5),
+            color_light: Color::new(0.8, 0.6, 0.35),
+            color_dark: Color::new(0.4, 0.22, 0.1),
+            rings: 12.0,
+            distortion: 0.6
+        },
+        Texture::Clouds {
+            noise: noise.clone(),
+            params: NoiseParams::new_with(2.0, 6, 2.0, 0.5),
+            sky: Color::new(0.25, 0.45, 0.85),
+            cloud: Color::new(1, 1, 1),
+            coverage: 0.5
+        },
+        Texture::Worley {
+            noise: noise.clone(),
+            frequency: 5.0,
+            color_a: Color::new(0.05, 0.2, 0.1),
+            color_b: Color::new(0.6, 0.9, 0.5)
+        },
+    ];
+
+    for (i, texture) in textures.into_iter().enumerate() {
+        world.add(Arc::new(Sphere {
+            center: Point3::new(-3.3 + 2.2 * i as f64, 1, 0),
+            radius: 1.0,
+            material: Materials::Lambertian { albedo: texture },
+        }));
+    }
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(
+        Point3::new_empty(),
+        Vec3::new(0, 1, 0),
+        Materials::Lambertian {
+            albedo: Texture::Turbulence {
+                noise,
+                params: NoiseParams::default(),
+                color: Color::new(0.8, 0.8, 0.8)
+            }
+        }
+    )));
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 3, -9),
+        Point3::new(0, 1, 0),
+        Vec3::new(0, 1, 0),
+        45.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7, 0.8, 1.0),
+        fog: None
+    }
+}
+
+// Tangent space normal map of `count` rounded flutes running along v, as if
+// carved into the surface
+fn fluted_normal_map(size: usize, count: usize) -> MipMap {
+    let texels = (0..size * size).map(|i| {
+        let u = ((i % size) as f64 + 0.5) / size as f64;
+        let slope = -(2.0 * PI * count as f64 * u).cos();
+        let normal = Vec3::new(slope, 0, 1).normalized();
+
+        [0.5 + 0.5 * normal.x() as f32, 0.5 + 0.5 * normal.y() as f32, 0.5 + 0.5 * normal.z() as f32, 1.0]
+    }).collect();
+
+    MipMap::new_linear(size, size, texels, false)
+}
+
+pub fn bump_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let noise = Arc::new(Perlin::new(7));
+
+    let base = Arc::new(Materials::Lambertian {
+        albedo: SolidColor { color_value: Color::new(0.7, 0.3, 0.2) }
+    });
+
+    // Hammered metal look from cellular noise, and a rough stone from fBm
+    world.add(Arc::new(Sphere {
+        center: Point3::new(-1.2, 1, 0),
+        radius: 1.0,
+        material: Materials::BumpMap {
+            base: Arc::new(Materials::Metal { albedo: Color::new(0.8, 0.8, 0.85), fuzz: 0.1 }),
+            height: Texture::Worley {
+                noise: noise.clone(),
+                frequency: 30.0,
+                color_a: Color::new_empty(),
+                color_b: Color::new(1, 1, 1)
+            },
+            scale: 0.01
+        },
+    }));
+
+    world.add(Arc::new(Sphere {
+        center: Point3::new(1.2, 1, 0),
+        radius: 1.0,
+        material: Materials::BumpMap {
+            base,
+            height: Texture::Noise {
+                noise,
+                params: NoiseParams::new_with(40.0, 4, 2.0, 0.5),
+                color: Color::new(1, 1, 1)
+            },
+            scale: 0.008
+        },
+    }));
+
+    // Fluted ball in front, its ridges only in the normal map
+    world.add(Arc::new(Sphere {
+        center: Point3::new(0, 0.5, -1.5),
+        radius: 0.5,
+        material: Materials::NormalMap {
+            base: Arc::new(Materials::Lambertian { albedo: SolidColor { color_value: Color::new(0.8, 0.8, 0.75) } }),
+            map: Texture::new_image_shared(Arc::new(fluted_normal_map(256, 24))),
+            strength: 1.0
+        },
+    }));
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(
+        Point3::new_empty(),
+        Vec3::new(0, 1, 0),
+        Materials::Lambertian { albedo: SolidColor { color_value: Color::new(0.5, 0.5, 0.5) } }
+    )));
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 2, -6),
+        Point3::new(0, 1, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7, 0.8, 1.0),
+        fog: None
+    }
+}
+
+pub fn texture_graph_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+    let noise = Arc::new(Perlin::new(3));
+
+    let moss = Arc::new(Texture::Noise {
+        noise: noise.clone(),
+        params: NoiseParams::new_with(6.0, 5, 2.0, 0.5),
+        color: Color::new(0.25, 0.45, 0.15)
+    });
+    let stone = Arc::new(Texture::Ramp {
+        input: Arc::new(Texture::Turbulence {
+            noise: noise.clone(),
+            params: NoiseParams::new_with(3.0, 6, 2.0, 0.5),
+            color: Color::new(1, 1, 1)
+        }),
+        stops: vec![
+            (0.0, Color::new(0.35, 0.33, 0.3)),
+            (0.4, Color::new(0.55, 0.52, 0.48)),
+            (1.0, Color::new(0.8, 0.78, 0.74))
+        ]
+    });
+
+    // Moss creeping over stone where a low frequency mask is high
+    let mask = Arc::new(Texture::Ramp {
+        input: Arc::new(Texture::Noise {
+            noise: noise.clone(),
+            params: NoiseParams::new_with(1.0, 3, 2.0, 0.5),
+            color: Color::new(1, 1, 1)
+        }),
+        stops: vec![(0.45, Color::new_empty()), (0.6, Color::new(1, 1, 1))]
+    });
+
+    world.add(Arc::new(Sphere {
+        center: Point3::new(-1.2, 1, 0),
+        radius: 1.0,
+        material: Materials::Lambertian {
+            albedo: Texture::Mix { a: stone, b: moss, factor: mask }
+        },
+    }));
+
+    // Tiled checker in texture space, darkened by inverted cellular noise
+    let tiles = Arc::new(Texture::UvTransform {
+        input: Arc::new(Texture::Checker {
+            texture_odd: Arc::new(Texture::constant(0.9)),
+            texture_even: Arc::new(Texture::constant(0.2)),
+            scale: 1.0,
+            space: TextureSpace::Uv
+        }),
+        rotation: 45.0,
+        scale: [4.0, 4.0],
+        offset: [0.0, 0.0]
+    });
+
+    world.add(Arc::new(Sphere {
+        center: Point3::new(1.2, 1, 0),
+        radius: 1.0,
+        material: Materials::Lambertian {
+            albedo: Texture::Multiply {
+                a: tiles.clone(),
+                b: Arc::new(Texture::Invert {
+                    input: Arc::new(Texture::Scale {
+                        input: Arc::new(Texture::Worley {
+                            noise,
+                            frequency: 8.0,
+                            color_a: Color::new(1, 1, 1),
+                            color_b: Color::new_empty()
+                        }),
+                        factor: 0.5
+                    })
+                })
+            }
+        },
+    }));
+
+    // Lattice sphere with every other checker cell cut away, so its inside and
+    // far side show through the holes
+    world.add(Arc::new(Sphere {
+        center: Point3::new(0, 1, 2.5),
+        radius: 1.0,
+        material: Materials::Cutout {
+            base: Arc::new(Materials::Metal { albedo: Color::new(0.8, 0.7, 0.4), fuzz: 0.2 }),
+            opacity: Texture::Checker {
+                texture_odd: Arc::new(Texture::constant(1.0)),
+                texture_even: Arc::new(Texture::constant(0.0)),
+                scale: 12.0,
+                space: TextureSpace::Uv
+            },
+            mode: AlphaMode::Threshold(0.5)
+        },
+    }));
+
+    // Lace curtain leaning back behind everything, facing the camera and
+    // letting light through in proportion to the noise
+    let curtain = Materials::Cutout {
+        base: Arc::new(Materials::Lambertian { albedo: Texture::constant(0.9) }),
+        opacity: Texture::Noise {
+            noise: Arc::new(Perlin::new(5)),
+            params: NoiseParams::new_with(4.0, 4, 2.0, 0.5),
+            color: Color::new(1, 1, 1)
+        },
+        mode: AlphaMode::Stochastic
+    };
+    let corners = [Point3::new(4, 0, 4), Point3::new(-4, 0, 4), Point3::new(-4, 3, 4.5), Point3::new(4, 3, 4.5)];
+    world.add(Arc::new(Triangle::new_with_uvs(
+        corners[0], corners[1], corners[2], [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], curtain.clone())));
+    world.add(Arc::new(Triangle::new_with_uvs(
+        corners[0], corners[2], corners[3], [(0.0, 0.0), (1.0, 1.0), (0.0, 1.0)], curtain)));
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(
+        Point3::new_empty(),
+        Vec3::new(0, 1, 0),
+        Materials::Lambertian {
+            albedo: Texture::Triplanar { input: tiles, scale: 0.25, sharpness: 4.0 }
+        }
+    )));
+
+    let cam = PerspectiveCamera::new(
+        Point3::new(0, 2, -6),
+        Point3::new(0, 1, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        film.aspect_ratio(),
+        0.00001,
+        10.0);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.7, 0.8, 1.0),
+        fog: None
+    }
+}
+
+// The shapes scene seen through the other projections. The panoramas want a
+// 2:1 (equirectangular) or 1:1 (stereo) image.
+pub fn projection_test(film: &Film, textures: &TextureCache, projection: &str) -> Scene {
+    let mut scene = shapes_test(film, textures);
+    let look_from = Point3::new(0, 1, -3);
+    let look_at = Point3::new(0, 0.5, 0);
+    let vup = Vec3::new(0, 1, 0);
+
+    scene.camera = match projection {
+        "orthographic" => Box::new(OrthographicCamera::new(Point3::new(0, 3, -6), look_at, vup, 6.0, film.aspect_ratio())),
+        "fisheye" => Box::new(FisheyeCamera::new(look_from, look_at, vup, 180.0, film.aspect_ratio())),
+        "equirectangular" => Box::new(EquirectangularCamera::new(look_from, look_at, vup)),
+        "stereo" => Box::new(OmniStereoCamera::new(look_from, look_at, vup, 0.064)),
+        _ => scene.camera
+    };
+
+    scene
+}
+
+pub fn lens_test(film: &Film, _textures: &TextureCache) -> Scene {
+    let mut world = HitList::new();
+
+    let mat_ground: Materials = Materials::Lambertian {
+        albedo: Texture::checker(
+            Color::new(0.2, 0.2, 0.2),
+            Color::new(0.8, 0.8, 0.8),
+            0.5,
+            TextureSpace::WorldPlanar
+        )
+    };
+
+    let colors = [
+        Color::new(0.8, 0.2, 0.2),
+        Color::new(0.8, 0.6, 0.2),
+        Color::new(0.3, 0.7, 0.3),
+        Color::new(0.2, 0.4, 0.8)
+    ];
+
+    // A row of spheres running away from the camera, the middle one under the
+    // centre of the screen
+    for i in -1..4 {
+        world.add(Arc::new(Sphere {
+            center: Point3::new(i as f64 * 0.3, 0.15, i as f64 * 0.8),
+            radius: 0.15,
+            material: Materials::Lambertian {
+                albedo: SolidColor { color_value: colors[(i + 1) as usize % colors.len()] }
+            }
+        }));
+    }
+
+    // Small bright lights far behind the focus, which blur into the shape of
+    // the aperture
+    let mat_light = Materials::DiffuseLight {
+        tex: Texture::SolidColor { color_value: Color::new(20, 20, 20) }
+    };
+    for x in -4..5 {
+        for y in 1..4 {
+            world.add(Arc::new(Sphere {
+                center: Point3::new(x as f64 * 0.75, y as f64 * 0.6, 14.0),
+                radius: 0.05,
+                material: mat_light.clone()
+            }));
+        }
+    }
+
+    world = HitList::new_with(Arc::new(BvhNode::new_from_hitlist(&world, 0.0, 1.0)));
+    world.add(Arc::new(Plane::new_with(Point3::new_empty(), Vec3::new(0, 1, 0), mat_ground)));
+
+    // 85mm wide open with a six bladed aperture, focused on whatever is in
+    // the middle of the frame
+    let mut lens = Lens::new_with(85.0, 1.4, Focus::Distance(1.0));
+    lens.blades = 6;
+    lens.blade_rotation = 15.0;
+    lens.cat_eye = 0.5;
+
+    let mut cam = PerspectiveCamera::new_physical(
+        Point3::new(0, 0.15, -2.5),
+        Point3::new(0, 0.15, 0),
+        Vec3::new(0, 1, 0),
+        lens,
+        film.aspect_ratio());
+    cam.autofocus(&world, 0.5, 0.5, &mut IndependentSampler);
+
+    Scene {
+        hit_list: world,
+        lights: HitList::new(),
+        camera: Box::new(cam),
+        sky_color: Color::new(0.1, 0.1, 0.15),
+        fog: None
+    }
+}
@@ -4,6 +4,8 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::{Materials, Point3, Vec3};
 use crate::aabb::AABB;
 use crate::ray::Ray;
+use crate::texture::TexCoord;
+use crate::sampler::Sampler;
 
 #[derive(Clone)]
 pub struct Sphere {
@@ -12,19 +14,37 @@ pub struct Sphere {
     pub material: Materials
 }
 
-impl Sphere {
-    fn get_sphere_uv(&self, p: &Point3, u: &mut f64, v: &mut f64) {
-        let theta = (-p.y()).acos();
-        let phi = (-p.z().atan2(p.x())) + PI;
-
-        *u = phi / (2.0 * PI);
-        *v = theta / PI;
+// Derivatives of the position on a sphere of `radius` with respect to the
+// texture coordinates from `get_sphere_uv`, for the unit `normal` at the hit
+pub(crate) fn sphere_tangents(normal: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    let sin_theta = (1.0 - normal.y() * normal.y()).max(0.0).sqrt();
+    let dpdu = Vec3::new(normal.z(), 0.0, -normal.x()) * (2.0 * PI * radius);
+    if sin_theta < 1e-6 {
+        return (dpdu, Vec3::new_empty());
     }
+
+    let dpdv = Vec3::new(
+        -normal.x() * normal.y() / sin_theta,
+        sin_theta,
+        -normal.y() * normal.z() / sin_theta
+    ) * (PI * radius);
+
+    (dpdu, dpdv)
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let oc = *ray.origin() - self.center;
+fn get_sphere_uv(p: &Point3, u: &mut f64, v: &mut f64) {
+    let theta = (-p.y()).acos();
+    let phi = (-p.z().atan2(p.x())) + PI;
+
+    *u = phi / (2.0 * PI);
+    *v = theta / PI;
+}
+
+impl Sphere {
+    // Intersection with the sphere moved to `center`, shared with the moving
+    // sphere, which passes its centre at the ray's time
+    pub(crate) fn hit_at(&self, center: &Point3, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        let oc = *ray.origin() - *center;
         let a = ray.dir().length_squared();
         let half_b = oc.dot(ray.dir());
         let c = oc.length_squared() - self.radius * self.radius;
@@ -35,22 +55,39 @@ impl Hittable for Sphere {
         }
 
         let sqrt_d = disc.sqrt();
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrt_d) / a;
+        for root in [(-half_b - sqrt_d) / a, (-half_b + sqrt_d) / a] {
             if root < t_min || t_max < root {
-                return false;
+                continue;
             }
+
+            let p = ray.at(root);
+            let outward_normal = (p - *center) / self.radius;
+            let (mut u, mut v) = (0.0, 0.0);
+            get_sphere_uv(&outward_normal, &mut u, &mut v);
+
+            // Cutouts can reject the near side and let the ray reach the far one
+            if !self.material.is_opaque_at(&TexCoord { u, v, p, normal: outward_normal, footprint: 0.0 }, sampler) {
+                continue;
+            }
+
+            rec.t = root;
+            rec.p = p;
+            rec.u = u;
+            rec.v = v;
+            rec.set_face_normal(ray, &outward_normal);
+            (rec.dpdu, rec.dpdv) = sphere_tangents(&outward_normal, self.radius);
+            rec.material = self.material.clone();
+
+            return true;
         }
 
-        rec.t = root;
-        rec.p = ray.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(ray, &outward_normal);
-        self.get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
-        rec.material = self.material.clone();
+        false
+    }
+}
 
-        true
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        self.hit_at(&self.center, ray, t_min, t_max, rec, sampler)
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
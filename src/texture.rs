@@ -1,7 +1,8 @@
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use image::RgbaImage;
-use noise::{NoiseFn, Perlin, Turbulence};
-use crate::{Color, Point3};
+use crate::{Color, HitRecord, Point3, Vec3};
+use crate::mipmap::{FilterMode, MipMap, WrapMode};
+use crate::perlin::{NoiseParams, Perlin};
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
@@ -9,54 +10,362 @@ pub enum Texture {
     SolidColor {
         color_value: Color
     },
+    // Alternating cells `1 / scale` wide in the chosen space
     Checker {
         texture_odd: Arc<Texture>,
-        texture_even: Arc<Texture>
+        texture_even: Arc<Texture>,
+        scale: f64,
+        space: TextureSpace
     },
+    // Texture coordinates are scaled, then offset, before the lookup
     Image {
-        image: Arc<RgbaImage>
+        image: Arc<MipMap>,
+        wrap: WrapMode,
+        filter: FilterMode,
+        uv_scale: [f64; 2],
+        uv_offset: [f64; 2]
     },
-    Perlin {
-        turbulence: Turbulence<Perlin>
+    // Plain fBm noise remapped to [0, 1] and tinted
+    Noise {
+        noise: Arc<Perlin>,
+        params: NoiseParams,
+        color: Color
+    },
+    Turbulence {
+        noise: Arc<Perlin>,
+        params: NoiseParams,
+        color: Color
+    },
+    // Sine bands along z distorted by turbulence
+    Marble {
+        noise: Arc<Perlin>,
+        params: NoiseParams,
+        color_a: Color,
+        color_b: Color,
+        // Band frequency and how strongly turbulence bends the bands
+        scale: f64,
+        distortion: f64
+    },
+    // Concentric rings around the y axis perturbed by noise
+    Wood {
+        noise: Arc<Perlin>,
+        params: NoiseParams,
+        color_light: Color,
+        color_dark: Color,
+        rings: f64,
+        distortion: f64
+    },
+    // fBm thresholded by `coverage`, blending from sky to cloud
+    Clouds {
+        noise: Arc<Perlin>,
+        params: NoiseParams,
+        sky: Color,
+        cloud: Color,
+        coverage: f64
+    },
+    // Cellular pattern, dark at the feature points and light at cell borders
+    Worley {
+        noise: Arc<Perlin>,
+        frequency: f64,
+        color_a: Color,
+        color_b: Color
+    },
+    VertexColor {
+        colors: [Color; 3]
+    },
+    // Blends from `a` to `b` by `factor`, which can be a constant or a mask
+    Mix {
+        a: Arc<Texture>,
+        b: Arc<Texture>,
+        factor: Arc<Texture>
+    },
+    Multiply {
+        a: Arc<Texture>,
+        b: Arc<Texture>
+    },
+    Add {
+        a: Arc<Texture>,
+        b: Arc<Texture>
+    },
+    // One minus the input
+    Invert {
+        input: Arc<Texture>
+    },
+    Scale {
+        input: Arc<Texture>,
+        factor: f64
+    },
+    // Rotates (in degrees), scales and then offsets the texture coordinates
+    // seen by `input`
+    UvTransform {
+        input: Arc<Texture>,
+        rotation: f64,
+        scale: [f64; 2],
+        offset: [f64; 2]
+    },
+    // Projects `input` along the three world axes and blends by the normal, for
+    // surfaces without usable texture coordinates. Higher `sharpness` narrows
+    // the blend between projections.
+    Triplanar {
+        input: Arc<Texture>,
+        scale: f64,
+        sharpness: f64
+    },
+    // Maps the input's value through colour stops sorted by position in [0, 1]
+    Ramp {
+        input: Arc<Texture>,
+        stops: Vec<(f64, Color)>
+    },
+    // Evaluates `input` with the position replaced by the point of `space`, so
+    // solid procedural textures can follow the surface parametrisation instead
+    Mapped {
+        input: Arc<Texture>,
+        space: TextureSpace
+    }
+}
+
+// Which coordinates position based textures are evaluated at
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureSpace {
+    // (u, v, 0), following the surface parametrisation
+    Uv,
+    // World position flattened onto the xz plane, for ground planes
+    WorldPlanar,
+    // Full world position, a solid texture
+    World
+}
+
+impl TextureSpace {
+    pub fn point(&self, coords: &TexCoord) -> Point3 {
+        match self {
+            TextureSpace::Uv => Point3::new(coords.u, coords.v, 0.0),
+            TextureSpace::WorldPlanar => Point3::new(coords.p.x(), 0.0, coords.p.z()),
+            TextureSpace::World => coords.p
+        }
+    }
+}
+
+// Everything a texture lookup may depend on
+#[derive(Debug, Copy, Clone)]
+pub struct TexCoord {
+    pub u: f64,
+    pub v: f64,
+    pub p: Point3,
+    // Shading normal, zero when not known
+    pub normal: Vec3,
+    // Width of the lookup in texture space, see `HitRecord::uv_footprint`
+    pub footprint: f64
+}
+
+impl TexCoord {
+    pub fn new(u: f64, v: f64, p: Point3) -> TexCoord {
+        TexCoord {
+            u, v, p,
+            normal: Vec3::new_empty(),
+            footprint: 0.0
+        }
+    }
+
+    pub fn from_record(rec: &HitRecord) -> TexCoord {
+        TexCoord {
+            u: rec.u,
+            v: rec.v,
+            p: rec.p,
+            normal: rec.normal,
+            footprint: rec.uv_footprint
+        }
+    }
+
+    fn with_uv(&self, u: f64, v: f64) -> TexCoord {
+        TexCoord { u, v, ..*self }
     }
 }
 
 impl Texture {
+    // Image repeated across the surface and trilinearly filtered
+    pub fn new_image_shared(image: Arc<MipMap>) -> Texture {
+        Texture::Image {
+            image,
+            wrap: WrapMode::Repeat,
+            filter: FilterMode::Trilinear,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0]
+        }
+    }
+
+    pub fn checker(odd: Color, even: Color, scale: f64, space: TextureSpace) -> Texture {
+        Texture::Checker {
+            texture_odd: Arc::new(Texture::SolidColor { color_value: odd }),
+            texture_even: Arc::new(Texture::SolidColor { color_value: even }),
+            scale,
+            space
+        }
+    }
+
+    pub fn constant(value: f64) -> Texture {
+        Texture::SolidColor { color_value: Color::new(value, value, value) }
+    }
+
     pub fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
-        return match self {
+        self.sample(&TexCoord::new(u, v, *p))
+    }
+
+    // Single number for textures used as masks, factors or heights
+    pub fn scalar(&self, coords: &TexCoord) -> f64 {
+        let c = self.sample(coords);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+
+    // Coverage for cutouts: the alpha channel of images that have one, otherwise
+    // the texture's value like any other mask
+    pub fn opacity(&self, coords: &TexCoord) -> f64 {
+        match self {
+            Texture::Image { image, wrap, filter, uv_scale, uv_offset } if image.has_alpha() => {
+                let u = coords.u * uv_scale[0] + uv_offset[0];
+                let v = coords.v * uv_scale[1] + uv_offset[1];
+                let footprint = coords.footprint * uv_scale[0].abs().max(uv_scale[1].abs());
+
+                image.lookup_alpha(u, v, *wrap, *filter, footprint)
+            },
+            _ => self.scalar(coords)
+        }
+    }
+
+    // Feeds what tells textures apart into `state`, for material ids. Only
+    // solid colours and images go beyond the kind of texture.
+    pub(crate) fn hash_identity<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Texture::SolidColor { color_value } => color_value.e.iter().for_each(|c| c.to_bits().hash(state)),
+            Texture::Image { image, .. } => image.content_hash().hash(state),
+            _ => ()
+        }
+    }
+
+    pub fn sample(&self, coords: &TexCoord) -> Color {
+        let (u, v, p, footprint) = (coords.u, coords.v, &coords.p, coords.footprint);
+
+        match self {
             Texture::SolidColor { color_value } => {
                 *color_value
             },
-            Texture::Checker { texture_odd, texture_even } => {
-                let sines = (10.0 * p.x()).sin() * (10.0 * p.y()).sin() * (10.0 * p.z()).sin();
-                if sines < 0.0 {
-                    return texture_even.value(u, v, p);
+            Texture::Checker { texture_odd, texture_even, scale, space } => {
+                // Rounding puts the cell borders at half integers, so surfaces lying
+                // on whole coordinates such as the y = 0 ground sit mid-cell instead
+                // of flickering between cells
+                let q = space.point(coords) * *scale;
+                let cell = q.x().round() as i64 + q.y().round() as i64 + q.z().round() as i64;
+                if cell.rem_euclid(2) == 0 {
+                    texture_even.sample(coords)
                 } else {
-                    return texture_odd.value(u, v, p);
+                    texture_odd.sample(coords)
                 }
             }
-            Texture::Image { image } => {
-                if image.is_empty() {
-                    return Color::new(0, 1, 1);
-                }
+            Texture::Image { image, wrap, filter, uv_scale, uv_offset } => {
+                let u = u * uv_scale[0] + uv_offset[0];
+                let v = v * uv_scale[1] + uv_offset[1];
+                let footprint = footprint * uv_scale[0].abs().max(uv_scale[1].abs());
+
+                image.lookup(u, v, *wrap, *filter, footprint)
+            }
+            Texture::Noise { noise, params, color } => {
+                *color * (0.5 * (1.0 + noise.fbm(p, params))).clamp(0.0, 1.0)
+            }
+            Texture::Turbulence { noise, params, color } => {
+                *color * noise.turbulence(p, params).clamp(0.0, 1.0)
+            }
+            Texture::Marble { noise, params, color_a, color_b, scale, distortion } => {
+                let t = 0.5 * (1.0 + (scale * p.z() + distortion * noise.turbulence(p, params)).sin());
+                lerp(color_a, color_b, t)
+            }
+            Texture::Wood { noise, params, color_light, color_dark, rings, distortion } => {
+                let r = (p.x() * p.x() + p.z() * p.z()).sqrt() * rings + distortion * noise.fbm(p, params);
+                let t = r - r.floor();
+                // Sharpen the ring edges so the late wood reads as thin dark bands
+                lerp(color_light, color_dark, t * t * t)
+            }
+            Texture::Clouds { noise, params, sky, cloud, coverage } => {
+                let density = 0.5 * (1.0 + noise.fbm(p, params));
+                let t = ((density - (1.0 - coverage)) / coverage.max(1e-3)).clamp(0.0, 1.0);
+                lerp(sky, cloud, t * t * (3.0 - 2.0 * t))
+            }
+            Texture::Worley { noise, frequency, color_a, color_b } => {
+                let d = noise.worley(&(*p * *frequency));
+                lerp(color_a, color_b, d.clamp(0.0, 1.0))
+            }
+            Texture::VertexColor { colors } => {
+                // Triangles report their barycentric coordinates as (u, v)
+                colors[0] * (1.0 - u - v) + colors[1] * u + colors[2] * v
+            }
+            Texture::Mix { a, b, factor } => {
+                let t = factor.scalar(coords).clamp(0.0, 1.0);
+                lerp(&a.sample(coords), &b.sample(coords), t)
+            }
+            Texture::Multiply { a, b } => {
+                a.sample(coords) * b.sample(coords)
+            }
+            Texture::Add { a, b } => {
+                a.sample(coords) + b.sample(coords)
+            }
+            Texture::Invert { input } => {
+                Color::new(1, 1, 1) - input.sample(coords)
+            }
+            Texture::Scale { input, factor } => {
+                input.sample(coords) * *factor
+            }
+            Texture::UvTransform { input, rotation, scale, offset } => {
+                let (sin, cos) = rotation.to_radians().sin_cos();
+                let ru = cos * u - sin * v;
+                let rv = sin * u + cos * v;
 
-                let u = u.clamp(0.0, 1.0);
-                let v = 1.0 - v.clamp(0.0, 1.0);
+                let mut transformed = coords.with_uv(ru * scale[0] + offset[0], rv * scale[1] + offset[1]);
+                transformed.footprint *= scale[0].abs().max(scale[1].abs());
+                input.sample(&transformed)
+            }
+            Texture::Triplanar { input, scale, sharpness } => {
+                let n = coords.normal;
+                let mut weights = [n.x().abs(), n.y().abs(), n.z().abs()].map(|w| w.powf(*sharpness));
+                let total: f64 = weights.iter().sum();
+                if total <= 0.0 {
+                    // No normal to blend with, project from above
+                    weights = [0.0, 1.0, 0.0];
+                } else {
+                    weights = weights.map(|w| w / total);
+                }
 
-                let i = ((u * image.width() as f64) as u32).min(image.width() - 1);
-                let j = ((v * image.height() as f64) as u32).min(image.height() - 1);
+                let q = *p * *scale;
+                let projections = [(q.z(), q.y()), (q.x(), q.z()), (q.x(), q.y())];
 
-                let color_scale = 1.0 / 255.0;
-                let pixel = image.get_pixel(i, j);
+                let mut color = Color::new_empty();
+                for (weight, (pu, pv)) in weights.iter().zip(projections) {
+                    if *weight > 0.0 {
+                        let mut projected = coords.with_uv(pu, pv);
+                        projected.footprint *= scale;
+                        color += input.sample(&projected) * *weight;
+                    }
+                }
 
-                return Color::new(color_scale * pixel.0[0] as f32, color_scale * pixel.0[1] as f32, color_scale * pixel.0[2] as f32);
+                color
             }
-            Texture::Perlin { turbulence } => {
-                return Color::new(1, 1,1) * 0.5 * (1.0 + (1.0 * p.z() * 10.0 * turbulence.get([p.x(), p.y(), p.z()])).sin())
+            Texture::Mapped { input, space } => {
+                input.sample(&TexCoord { p: space.point(coords), ..*coords })
             }
-            _ => {
-                Color::new_empty()
+            Texture::Ramp { input, stops } => {
+                let t = input.scalar(coords);
+                match stops.iter().position(|(position, _)| *position > t) {
+                    None => stops.last().map_or(Color::new_empty(), |s| s.1),
+                    Some(0) => stops[0].1,
+                    Some(i) => {
+                        let (p0, c0) = stops[i - 1];
+                        let (p1, c1) = stops[i];
+                        lerp(&c0, &c1, (t - p0) / (p1 - p0))
+                    }
+                }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn lerp(a: &Color, b: &Color, t: f64) -> Color {
+    *a * (1.0 - t) + *b * t
+}
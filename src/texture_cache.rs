@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use image::ImageError;
+use crate::mipmap::{FilterMode, MipMap, WrapMode};
+use crate::texture::Texture;
+
+#[derive(Debug)]
+pub enum TextureError {
+    NotFound(PathBuf),
+    Io(PathBuf, std::io::Error),
+    Decode(PathBuf, String)
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::NotFound(path) => write!(f, "texture {} does not exist", path.display()),
+            TextureError::Io(path, e) => write!(f, "failed to read texture {}: {}", path.display(), e),
+            TextureError::Decode(path, e) => write!(f, "failed to decode texture {}: {}", path.display(), e)
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+// Loads image textures on first use and hands out shared handles afterwards,
+// so materials referencing the same file share one copy of the texels. Files
+// are told apart by their canonical path, so different spellings of the same
+// path share an entry too.
+pub struct TextureCache {
+    images: Mutex<HashMap<(PathBuf, bool), Arc<MipMap>>>,
+    // Largest size of all cached mip chains together in bytes. Images loaded
+    // once the budget is nearly spent lose their most detailed levels.
+    memory_budget: Option<usize>,
+    // How the textures from `image_texture` wrap and filter
+    wrap: WrapMode,
+    filter: FilterMode
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache {
+            images: Mutex::new(HashMap::new()),
+            memory_budget: None,
+            wrap: WrapMode::Repeat,
+            filter: FilterMode::Trilinear
+        }
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> TextureCache {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> TextureCache {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_budget(mut self, memory_budget: usize) -> TextureCache {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    // `srgb` decodes 8 and 16-bit colour images to linear, see `MipMap::from_dynamic`
+    pub fn load<P: AsRef<Path>>(&self, path: P, srgb: bool) -> Result<Arc<MipMap>, TextureError> {
+        let path = std::fs::canonicalize(path.as_ref()).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => TextureError::NotFound(path.as_ref().to_path_buf()),
+            _ => TextureError::Io(path.as_ref().to_path_buf(), e)
+        })?;
+        let key = (path.clone(), srgb);
+
+        if let Some(image) = self.images.lock().unwrap().get(&key) {
+            return Ok(image.clone());
+        }
+
+        // Decoding happens without holding the lock so other threads can keep
+        // loading; if two race for the same file the first one inserted wins
+        let decoded = image::open(&path).map_err(|e| match e {
+            ImageError::IoError(e) => TextureError::Io(path.clone(), e),
+            e => TextureError::Decode(path.clone(), e.to_string())
+        })?;
+
+        let mut image = MipMap::from_dynamic(&decoded, srgb);
+
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.get(&key) {
+            return Ok(image.clone());
+        }
+        if let Some(budget) = self.memory_budget {
+            let used: usize = images.values().map(|image| image.size_in_bytes()).sum();
+            image.fit_to_budget(budget.saturating_sub(used));
+        }
+
+        let image = Arc::new(image);
+        images.insert(key, image.clone());
+        Ok(image)
+    }
+
+    // Colour texture wrapped and filtered the way this cache is set up to
+    pub fn image_texture<P: AsRef<Path>>(&self, path: P) -> Result<Texture, TextureError> {
+        Ok(Texture::Image {
+            image: self.load(path, true)?,
+            wrap: self.wrap,
+            filter: self.filter,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0]
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.lock().unwrap().len()
+    }
+
+    // Bytes taken by all cached mip chains
+    pub fn memory_usage(&self) -> usize {
+        self.images.lock().unwrap().values().map(|image| image.size_in_bytes()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // Writes a small test image into its own temporary directory
+    fn write_image(name: &str, size: u32) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("texture_cache_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+        RgbImage::from_fn(size, size, |x, y| Rgb([x as u8, y as u8, 128])).save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn spellings_of_a_path_share_an_entry() {
+        let path = write_image("spellings", 4);
+        let dir = path.parent().unwrap();
+        let cache = TextureCache::new();
+
+        let a = cache.load(&path, true).unwrap();
+        let b = cache.load(dir.join(".").join("image.png"), true).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(cache.len(), 1);
+
+        // Linear and sRGB decodes of one file are different textures
+        cache.load(&path, false).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        assert!(matches!(cache.load(dir.join("missing.png"), true), Err(TextureError::NotFound(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn budget_covers_the_whole_cache() {
+        let first = write_image("budget_a", 64);
+        let second = write_image("budget_b", 64);
+        let full_size = MipMap::from_dynamic(&image::open(&first).unwrap(), true).size_in_bytes();
+
+        // Room for one full chain and a quarter of another
+        let budget = full_size + full_size / 4;
+        let cache = TextureCache::new().with_budget(budget);
+        let a = cache.load(&first, true).unwrap();
+        let b = cache.load(&second, true).unwrap();
+
+        assert_eq!(a.width(), 64);
+        assert!(b.width() < 64);
+        assert!(cache.memory_usage() <= budget);
+
+        for path in [first, second] {
+            std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+}
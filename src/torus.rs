@@ -0,0 +1,322 @@
+use std::borrow::Borrow;
+use std::f64::consts::PI;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+
+// Torus around `axis` through `center`, with the tube of radius `minor_radius`
+// swept along a circle of radius `major_radius`
+pub struct Torus {
+    center: Point3,
+    frame: Onb,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Materials
+}
+
+impl Torus {
+    pub fn new_with(center: Point3, axis: Vec3, major_radius: f64, minor_radius: f64, material: Materials) -> Torus {
+        Torus {
+            center, major_radius, minor_radius, material,
+            frame: Onb::build_from_w(&axis)
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, _sampler: &mut dyn Sampler) -> bool {
+        // Work with a unit direction so the quartic stays well conditioned
+        let dir_length = ray.dir().length();
+        let o = self.frame.to_local(&(*ray.origin() - self.center));
+        let d = self.frame.to_local(ray.dir()) / dir_length;
+
+        // Skip rays that miss the bounding sphere before solving the quartic
+        let bound = self.major_radius + self.minor_radius;
+        let half_b = o.dot(&d);
+        if half_b * half_b - (o.length_squared() - bound * bound) < 0.0 {
+            return false;
+        }
+
+        let r2 = self.major_radius * self.major_radius;
+        let e = o.length_squared() - r2 - self.minor_radius * self.minor_radius;
+        let f = o.dot(&d);
+
+        let coeffs = [
+            e * e - 4.0 * r2 * (self.minor_radius * self.minor_radius - o.z() * o.z()),
+            4.0 * f * e + 8.0 * r2 * o.z() * d.z(),
+            2.0 * e + 4.0 * f * f + 4.0 * r2 * d.z() * d.z(),
+            4.0 * f,
+            1.0
+        ];
+
+        let mut roots = [0.0; 4];
+        let count = solve_quartic(&coeffs, &mut roots);
+
+        let mut closest = f64::INFINITY;
+        for root in roots[..count].iter() {
+            let s = polish_root(&coeffs, *root);
+            let t = s / dir_length;
+            if t >= t_min && t <= t_max && t < closest {
+                closest = t;
+            }
+        }
+
+        if closest == f64::INFINITY {
+            return false;
+        }
+
+        let p = o + d * (closest * dir_length);
+        let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        let ring = if rho > 0.0 {
+            Vec3::new(p.x(), p.y(), 0.0) * (self.major_radius / rho)
+        } else {
+            Vec3::new(self.major_radius, 0.0, 0.0)
+        };
+        let local_normal = (p - ring).normalized();
+
+        rec.t = closest;
+        rec.p = ray.at(closest);
+        rec.u = (p.y().atan2(p.x()) + PI) / (2.0 * PI);
+        rec.v = (p.z().atan2(rho - self.major_radius) + PI) / (2.0 * PI);
+
+        // Around the axis, then around the tube by turning the normal towards the axis
+        let radial = ring / self.major_radius;
+        let tube_tangent = radial * -local_normal.z() + Vec3::new(0.0, 0.0, local_normal.dot(&radial));
+        rec.dpdu = self.frame.local(-p.y(), p.x(), 0.0) * (2.0 * PI);
+        rec.dpdv = self.frame.local(tube_tangent.x(), tube_tangent.y(), tube_tangent.z())
+            * (2.0 * PI * self.minor_radius);
+
+        let outward_normal = self.frame.local(local_normal.x(), local_normal.y(), local_normal.z());
+        rec.set_face_normal(ray, &outward_normal);
+        rec.material = self.material.clone();
+
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = self.frame.world_extent(&Vec3::new(outer, outer, self.minor_radius));
+
+        *output_box = AABB::new((self.center - extent).borrow(), (self.center + extent).borrow());
+        true
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+// Closed form solvers after Schwarze, "Cubic and Quartic Roots" (Graphics Gems).
+// Coefficients are given lowest order first, and the number of real roots is returned.
+fn solve_quadric(c: &[f64; 3], s: &mut [f64]) -> usize {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let disc = p * p - q;
+
+    if is_zero(disc) {
+        s[0] = -p;
+        1
+    } else if disc < 0.0 {
+        0
+    } else {
+        let sqrt_d = disc.sqrt();
+        s[0] = sqrt_d - p;
+        s[1] = -sqrt_d - p;
+        2
+    }
+}
+
+fn solve_cubic(c: &[f64; 4], s: &mut [f64]) -> usize {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    let sq_a = a * a;
+    let p = 1.0 / 3.0 * (-1.0 / 3.0 * sq_a + b);
+    let q = 1.0 / 2.0 * (2.0 / 27.0 * a * sq_a - 1.0 / 3.0 * a * b + cc);
+
+    let cb_p = p * p * p;
+    let disc = q * q + cb_p;
+
+    let count = if is_zero(disc) {
+        if is_zero(q) {
+            s[0] = 0.0;
+            1
+        } else {
+            let u = (-q).cbrt();
+            s[0] = 2.0 * u;
+            s[1] = -u;
+            2
+        }
+    } else if disc < 0.0 {
+        let phi = 1.0 / 3.0 * (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos();
+        let t = 2.0 * (-p).sqrt();
+        s[0] = t * phi.cos();
+        s[1] = -t * (phi + PI / 3.0).cos();
+        s[2] = -t * (phi - PI / 3.0).cos();
+        3
+    } else {
+        let sqrt_d = disc.sqrt();
+        s[0] = (sqrt_d - q).cbrt() - (sqrt_d + q).cbrt();
+        1
+    };
+
+    let sub = 1.0 / 3.0 * a;
+    for root in s[..count].iter_mut() {
+        *root -= sub;
+    }
+
+    count
+}
+
+fn solve_quartic(c: &[f64; 5], s: &mut [f64; 4]) -> usize {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // Substitute x = y - a/4 to eliminate the cubic term
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = 1.0 / 8.0 * sq_a * a - 1.0 / 2.0 * a * b + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + 1.0 / 16.0 * sq_a * b - 1.0 / 4.0 * a * cc + d;
+
+    let count = if is_zero(r) {
+        let count = solve_cubic(&[q, p, 0.0, 1.0], s);
+        s[count] = 0.0;
+        count + 1
+    } else {
+        // Solve the resolvent cubic, then split into two quadrics
+        solve_cubic(&[1.0 / 2.0 * r * p - 1.0 / 8.0 * q * q, -r, -1.0 / 2.0 * p, 1.0], s);
+        let z = s[0];
+
+        let mut u = z * z - r;
+        let mut v = 2.0 * z - p;
+
+        if is_zero(u) {
+            u = 0.0;
+        } else if u > 0.0 {
+            u = u.sqrt();
+        } else {
+            return 0;
+        }
+
+        if is_zero(v) {
+            v = 0.0;
+        } else if v > 0.0 {
+            v = v.sqrt();
+        } else {
+            return 0;
+        }
+
+        let count = solve_quadric(&[z - u, if q < 0.0 { -v } else { v }, 1.0], &mut s[..]);
+        count + solve_quadric(&[z + u, if q < 0.0 { v } else { -v }, 1.0], &mut s[count..])
+    };
+
+    let sub = 1.0 / 4.0 * a;
+    for root in s[..count].iter_mut() {
+        *root -= sub;
+    }
+
+    count
+}
+
+// A couple of Newton steps on the original polynomial to clean up the closed form roots
+fn polish_root(c: &[f64; 5], mut x: f64) -> f64 {
+    for _ in 0..2 {
+        let f = (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+        let df = ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+        if df.abs() < EPSILON {
+            break;
+        }
+        x -= f / df;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::texture::Texture;
+    use crate::Color;
+
+    fn sorted_roots(c: &[f64; 5]) -> Vec<f64> {
+        let mut roots = [0.0; 4];
+        let count = solve_quartic(c, &mut roots);
+        let mut roots: Vec<f64> = roots[..count].iter().map(|&r| polish_root(c, r)).collect();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        roots
+    }
+
+    fn assert_roots(c: &[f64; 5], expected: &[f64]) {
+        let roots = sorted_roots(c);
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+    }
+
+    fn torus() -> Torus {
+        Torus::new_with(
+            Point3::new_empty(),
+            Vec3::new(0, 0, 1),
+            2.0,
+            0.5,
+            Materials::Lambertian { albedo: Texture::SolidColor { color_value: Color::new(0.5, 0.5, 0.5) } }
+        )
+    }
+
+    #[test]
+    fn quartic_finds_four_distinct_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(&[24.0, -50.0, 35.0, -10.0, 1.0], &[1.0, 2.0, 3.0, 4.0]);
+        // 2 (x + 3)(x + 1)(x - 0.5)(x - 2), not monic
+        assert_roots(&[6.0, -7.0, -12.0, 3.0, 2.0], &[-3.0, -1.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn quartic_with_zero_constant_term_has_root_at_zero() {
+        // x (x - 1)(x + 2)(x - 3)
+        assert_roots(&[0.0, 6.0, -5.0, -2.0, 1.0], &[-2.0, 0.0, 1.0, 3.0]);
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        assert!(sorted_roots(&[1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+        // (x^2 + 1)(x^2 + 4)
+        assert!(sorted_roots(&[4.0, 0.0, 5.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn ray_through_the_tube_hits_the_outside_first() {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Point3::new(-5, 0, 0), Vec3::new(2, 0, 0));
+        assert!(torus().hit(&ray, 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+
+        // Half a unit short of the outer equator at x = -2.5, with the direction doubled
+        assert!((rec.t - 1.25).abs() < 1e-9, "{}", rec.t);
+        assert!((rec.normal - Vec3::new(-1, 0, 0)).length() < 1e-9);
+        assert!(rec.front_face);
+
+        // Starting inside the hole, the nearest hit is the inner equator
+        let ray = Ray::new(Point3::new_empty(), Vec3::new(0, 1, 0));
+        assert!(torus().hit(&ray, 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+        assert!((rec.t - 1.5).abs() < 1e-9, "{}", rec.t);
+    }
+
+    #[test]
+    fn rays_through_the_hole_or_over_the_tube_miss() {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Point3::new(0, 0, -5), Vec3::new(0, 0, 1));
+        assert!(!torus().hit(&ray, 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+
+        // Grazing past the top of the tube
+        let ray = Ray::new(Point3::new(-5, 0, 0.51), Vec3::new(1, 0, 0));
+        assert!(!torus().hit(&ray, 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+    }
+}
@@ -0,0 +1,647 @@
+use std::sync::Arc;
+use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
+use crate::aabb::AABB;
+use crate::sampler::Sampler;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4]
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Mat4 {
+        let mut mat = Mat4::identity();
+        mat.m[0][3] = offset.x();
+        mat.m[1][3] = offset.y();
+        mat.m[2][3] = offset.z();
+        mat
+    }
+
+    pub fn scale(factor: Vec3) -> Mat4 {
+        let mut mat = Mat4::identity();
+        mat.m[0][0] = factor.x();
+        mat.m[1][1] = factor.y();
+        mat.m[2][2] = factor.z();
+        mat
+    }
+
+    pub fn uniform_scale(factor: f64) -> Mat4 {
+        Mat4::scale(Vec3::new(factor, factor, factor))
+    }
+
+    pub fn rotate_x(degrees: f64) -> Mat4 {
+        Mat4::rotate(Vec3::new(1, 0, 0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Mat4 {
+        Mat4::rotate(Vec3::new(0, 1, 0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Mat4 {
+        Mat4::rotate(Vec3::new(0, 0, 1), degrees)
+    }
+
+    // Rotation by the given angle around an arbitrary axis through the origin
+    pub fn rotate(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = axis.normalized();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+
+        Mat4 {
+            m: [
+                [t * a.x() * a.x() + cos, t * a.x() * a.y() - sin * a.z(), t * a.x() * a.z() + sin * a.y(), 0.0],
+                [t * a.x() * a.y() + sin * a.z(), t * a.y() * a.y() + cos, t * a.y() * a.z() - sin * a.x(), 0.0],
+                [t * a.x() * a.z() - sin * a.y(), t * a.y() * a.z() + sin * a.x(), t * a.z() * a.z() + cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut out = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                out.m[i][j] = self.m[j][i];
+            }
+        }
+        out
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&x, &y| a[x][col].abs().partial_cmp(&a[y][col].abs()).unwrap())
+                .unwrap();
+
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4 { m: inv })
+    }
+
+    #[inline(always)]
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+
+        if w == 1.0 { Point3::new(x, y, z) } else { Point3::new(x, y, z) / w }
+    }
+
+    #[inline(always)]
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z()
+        )
+    }
+
+    // Normals transform with the inverse transpose, so this must be called on the inverse matrix
+    #[inline(always)]
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * n.x() + m[1][0] * n.y() + m[2][0] * n.z(),
+            m[0][1] * n.x() + m[1][1] * n.y() + m[2][1] * n.z(),
+            m[0][2] * n.x() + m[1][2] * n.y() + m[2][2] * n.z()
+        )
+    }
+
+    pub fn transform_box(&self, bbox: &AABB) -> AABB {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { bbox.min().x() } else { bbox.max().x() },
+                if i & 2 == 0 { bbox.min().y() } else { bbox.max().y() },
+                if i & 4 == 0 { bbox.min().z() } else { bbox.max().z() }
+            );
+            let p = self.transform_point(&corner);
+
+            for c in 0..3 {
+                min.e[c] = min.e[c].min(p.e[c]);
+                max.e[c] = max.e[c].max(p.e[c]);
+            }
+        }
+
+        AABB::new(&min, &max)
+    }
+}
+
+impl std::ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        Mat4 {
+            m: std::array::from_fn(|i| std::array::from_fn(|j| (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum()))
+        }
+    }
+}
+
+// Places a hittable in the world with an affine transform. Rays are moved into
+// object space for intersection, and the hit point and normal are moved back.
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4
+}
+
+impl Transformed {
+    // None when `matrix` cannot be inverted, e.g. when it scales an axis to zero
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Transformed> {
+        Some(Transformed {
+            object,
+            inverse: matrix.inverse()?,
+            matrix
+        })
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        // The direction is not renormalised, so t is the same in both spaces
+        let object_ray = Ray::new_with_time(
+            self.inverse.transform_point(ray.origin()),
+            self.inverse.transform_vector(ray.dir()),
+            ray.time()
+        );
+
+        if !self.object.hit(&object_ray, t_min, t_max, rec, sampler) {
+            return false;
+        }
+
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = self.inverse.transform_normal(&rec.normal).normalized();
+        rec.dpdu = self.matrix.transform_vector(&rec.dpdu);
+        rec.dpdv = self.matrix.transform_vector(&rec.dpdv);
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        let mut object_box = AABB::new_empty();
+        if !self.object.bounding_box(time0, time1, &mut object_box) {
+            return false;
+        }
+
+        *output_box = self.matrix.transform_box(&object_box);
+        true
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        let object_ray = Ray::new_with_time(
+            self.inverse.transform_point(ray.origin()),
+            self.inverse.transform_vector(ray.dir()),
+            ray.time()
+        );
+
+        self.object.transmittance(&object_ray, t_min, t_max, sampler)
+    }
+}
+
+// A transformed reference to a shared hittable, optionally overriding the
+// material of everything it hits. Meshes are built into a BVH once and can
+// then be placed any number of times.
+pub struct Instance {
+    transformed: Transformed,
+    material: Option<Materials>
+}
+
+impl Instance {
+    // None for a matrix that cannot be inverted, as for `Transformed`
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Instance> {
+        Some(Instance {
+            transformed: Transformed::new(object, matrix)?,
+            material: None
+        })
+    }
+
+    pub fn new_with_material(object: Arc<dyn Hittable>, matrix: Mat4, material: Materials) -> Option<Instance> {
+        Some(Instance {
+            transformed: Transformed::new(object, matrix)?,
+            material: Some(material)
+        })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        if !self.transformed.hit(ray, t_min, t_max, rec, sampler) {
+            return false;
+        }
+
+        if let Some(material) = &self.material {
+            rec.material = material.clone();
+        }
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.transformed.bounding_box(time0, time1, output_box)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        self.transformed.transmittance(ray, t_min, t_max, sampler)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Quat {
+        let a = axis.normalized();
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Quat { w: cos, x: a.x() * sin, y: a.y() * sin, z: a.z() * sin }
+    }
+
+    fn dot(&self, rhs: &Quat) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    fn normalized(&self) -> Quat {
+        let len = self.dot(self).sqrt();
+        Quat { w: self.w / len, x: self.x / len, y: self.y / len, z: self.z / len }
+    }
+
+    // Angle swept when rotating from `self` to `rhs`, in radians
+    pub fn angle_to(&self, rhs: &Quat) -> f64 {
+        2.0 * self.dot(rhs).abs().min(1.0).acos()
+    }
+
+    // Spherical interpolation along the shortest arc
+    pub fn slerp(&self, rhs: &Quat, t: f64) -> Quat {
+        let mut cos = self.dot(rhs);
+        let mut end = *rhs;
+        if cos < 0.0 {
+            cos = -cos;
+            end = Quat { w: -rhs.w, x: -rhs.x, y: -rhs.y, z: -rhs.z };
+        }
+
+        // Nearly identical rotations are lerped to avoid dividing by sin(0)
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+
+        Quat {
+            w: a * self.w + b * end.w,
+            x: a * self.x + b * end.x,
+            y: a * self.y + b * end.y,
+            z: a * self.z + b * end.z
+        }.normalized()
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        let Quat { w, x, y, z } = *self;
+
+        Mat4 {
+            m: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+}
+
+// Translation, rotation and scale kept separate so that they can be
+// interpolated independently. Applied as scale, then rotation, then translation.
+#[derive(Debug, Copy, Clone)]
+pub struct Pose {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3
+}
+
+impl Pose {
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Pose {
+        Pose { translation, rotation, scale }
+    }
+
+    pub fn lerp(&self, rhs: &Pose, t: f64) -> Pose {
+        Pose {
+            translation: self.translation + (rhs.translation - self.translation) * t,
+            rotation: self.rotation.slerp(&rhs.rotation, t),
+            scale: self.scale + (rhs.scale - self.scale) * t
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translate(self.translation) * self.rotation.matrix() * Mat4::scale(self.scale)
+    }
+
+    // None when an axis is scaled to zero, like `Mat4::inverse`
+    pub fn inverse_matrix(&self) -> Option<Mat4> {
+        if self.scale.e.iter().any(|s| s.abs() < 1e-12) {
+            return None;
+        }
+
+        let inv_scale = Vec3::new(1.0 / self.scale.x(), 1.0 / self.scale.y(), 1.0 / self.scale.z());
+        Some(Mat4::scale(inv_scale) * self.rotation.matrix().transpose() * Mat4::translate(-self.translation))
+    }
+}
+
+// Instance whose transform is interpolated between two poses over `time0..time1`,
+// which together with the camera shutter gives motion blur
+pub struct AnimatedInstance {
+    object: Arc<dyn Hittable>,
+    start: Pose,
+    end: Pose,
+    time0: f64,
+    time1: f64,
+    material: Option<Materials>
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<dyn Hittable>, start: Pose, end: Pose, time0: f64, time1: f64) -> AnimatedInstance {
+        AnimatedInstance {
+            object, start, end, time0, time1,
+            material: None
+        }
+    }
+
+    pub fn new_with_material(object: Arc<dyn Hittable>, start: Pose, end: Pose, time0: f64, time1: f64,
+                             material: Materials) -> AnimatedInstance {
+        AnimatedInstance {
+            object, start, end, time0, time1,
+            material: Some(material)
+        }
+    }
+
+    fn pose(&self, time: f64) -> Pose {
+        if self.time1 <= self.time0 {
+            return self.start;
+        }
+
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.start.lerp(&self.end, t)
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
+        // Poses scaled to nothing, e.g. while shrinking away, cannot be hit
+        let pose = self.pose(ray.time());
+        let matrix = pose.matrix();
+        let Some(inverse) = pose.inverse_matrix() else {
+            return false;
+        };
+
+        let object_ray = Ray::new_with_time(
+            inverse.transform_point(ray.origin()),
+            inverse.transform_vector(ray.dir()),
+            ray.time()
+        );
+
+        if !self.object.hit(&object_ray, t_min, t_max, rec, sampler) {
+            return false;
+        }
+
+        rec.p = matrix.transform_point(&rec.p);
+        rec.normal = inverse.transform_normal(&rec.normal).normalized();
+        rec.dpdu = matrix.transform_vector(&rec.dpdu);
+        rec.dpdv = matrix.transform_vector(&rec.dpdv);
+
+        if let Some(material) = &self.material {
+            rec.material = material.clone();
+        }
+
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        let mut object_box = AABB::new_empty();
+        if !self.object.bounding_box(time0, time1, &mut object_box) {
+            return false;
+        }
+
+        // Union of the box at evenly spaced times. Corners move along arcs around
+        // the pivot while rotating, so the result is padded by the largest
+        // possible bulge of such an arc between steps.
+        const STEPS: usize = 32;
+        let mut result = self.pose(time0).matrix().transform_box(&object_box);
+        for i in 1..=STEPS {
+            let time = time0 + (time1 - time0) * (i as f64 / STEPS as f64);
+            result = AABB::surrounding_box(&result, &self.pose(time).matrix().transform_box(&object_box));
+        }
+
+        // Farthest any corner gets from the pivot, the object's origin. Scales
+        // are interpolated linearly, so the largest is at one of the ends.
+        let (lo, hi) = (object_box.min(), object_box.max());
+        let reach = (0..3)
+            .map(|i| lo.e[i].abs().max(hi.e[i].abs()).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        let scale = self.start.scale.e.iter().chain(self.end.scale.e.iter()).fold(0.0f64, |m, s| m.max(s.abs()));
+        let radius = reach * scale;
+
+        let angle = self.pose(time0).rotation.angle_to(&self.pose(time1).rotation);
+        let bulge = radius * (1.0 - (angle / (2.0 * STEPS as f64)).cos());
+        let pad = Vec3::new(bulge, bulge, bulge);
+
+        *output_box = AABB::new(&(result.min() - pad), &(result.max() + pad));
+        true
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler) -> f64 {
+        let Some(inverse) = self.pose(ray.time()).inverse_matrix() else {
+            return 1.0;
+        };
+        let object_ray = Ray::new_with_time(
+            inverse.transform_point(ray.origin()),
+            inverse.transform_vector(ray.dir()),
+            ray.time()
+        );
+
+        self.object.transmittance(&object_ray, t_min, t_max, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::sphere::Sphere;
+    use crate::texture::Texture;
+    use crate::Color;
+
+    fn assert_identity(m: &Mat4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((m.m[i][j] - expected).abs() < 1e-9, "{:?}", m.m);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_affine_transform() {
+        let m = Mat4::translate(Vec3::new(1, -2, 3)) * Mat4::rotate(Vec3::new(1, 1, 0), 37.0)
+            * Mat4::scale(Vec3::new(2, 0.5, 3));
+        let inverse = m.inverse().unwrap();
+        assert_identity(&(m * inverse));
+        assert_identity(&(inverse * m));
+    }
+
+    #[test]
+    fn inverse_needs_pivoting() {
+        // Zero on the diagonal, fine once rows are swapped
+        let m = Mat4 { m: [[0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] };
+        assert_identity(&(m * m.inverse().unwrap()));
+    }
+
+    #[test]
+    fn zero_scale_is_not_invertible() {
+        let m = Mat4::scale(Vec3::new(1, 0, 1));
+        assert!(m.inverse().is_none());
+
+        let sphere = Arc::new(Sphere {
+            center: Point3::new_empty(),
+            radius: 1.0,
+            material: Materials::Lambertian { albedo: Texture::SolidColor { color_value: Color::new(0.5, 0.5, 0.5) } }
+        });
+        assert!(Transformed::new(sphere, m).is_none());
+    }
+
+    #[test]
+    fn slerp_hits_endpoints_and_midpoint() {
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(Vec3::new(0, 1, 0), 90.0);
+
+        assert!(a.slerp(&b, 0.0).angle_to(&a) < 1e-9);
+        assert!(a.slerp(&b, 1.0).angle_to(&b) < 1e-9);
+
+        let mid = a.slerp(&b, 0.5);
+        let expected = Quat::from_axis_angle(Vec3::new(0, 1, 0), 45.0);
+        assert!(mid.angle_to(&expected) < 1e-9);
+    }
+
+    #[test]
+    fn slerp_takes_shortest_arc() {
+        // -q is the same rotation as q, so interpolating to it goes nowhere
+        let a = Quat::from_axis_angle(Vec3::new(1, 0, 0), 30.0);
+        let b = Quat { w: -a.w, x: -a.x, y: -a.y, z: -a.z };
+        assert!(a.slerp(&b, 0.5).angle_to(&a) < 1e-9);
+    }
+
+    #[test]
+    fn quat_matrix_matches_axis_rotation() {
+        let q = Quat::from_axis_angle(Vec3::new(0, 0, 1), 90.0).matrix();
+        let m = Mat4::rotate_z(90.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((q.m[i][j] - m.m[i][j]).abs() < 1e-12);
+            }
+        }
+    }
+
+    fn sphere_at(center: Point3) -> Arc<Sphere> {
+        Arc::new(Sphere {
+            center,
+            radius: 0.5,
+            material: Materials::Lambertian { albedo: Texture::SolidColor { color_value: Color::new(0.5, 0.5, 0.5) } }
+        })
+    }
+
+    #[test]
+    fn animated_box_covers_the_swept_object() {
+        // A sphere off to the side of the pivot, swinging a quarter turn around it
+        let sphere = sphere_at(Point3::new(3, 0, 0));
+        let start = Pose::new(Vec3::new(0, 1, 0), Quat::identity(), Vec3::new(1, 1, 1));
+        let end = Pose::new(Vec3::new(0, 1, 0), Quat::from_axis_angle(Vec3::new(0, 1, 0), 90.0), Vec3::new(2, 2, 2));
+        let animated = AnimatedInstance::new(sphere.clone(), start, end, 0.0, 1.0);
+
+        let mut animated_box = AABB::new_empty();
+        assert!(animated.bounding_box(0.0, 1.0, &mut animated_box));
+
+        let mut object_box = AABB::new_empty();
+        sphere.bounding_box(0.0, 1.0, &mut object_box);
+        for i in 0..=1000 {
+            let t = i as f64 / 1000.0;
+            let posed = start.lerp(&end, t).matrix().transform_box(&object_box);
+            for axis in 0..3 {
+                assert!(posed.min().e[axis] >= animated_box.min().e[axis] - 1e-9, "t = {}", t);
+                assert!(posed.max().e[axis] <= animated_box.max().e[axis] + 1e-9, "t = {}", t);
+            }
+        }
+
+        // Padding by the arc around the pivot keeps the box close: the swept
+        // sphere reaches 7 units from the pivot at most
+        assert!(animated_box.max().x() < 7.5 && animated_box.min().z() > -7.5);
+    }
+
+    #[test]
+    fn zero_scale_pose_is_not_invertible() {
+        let flat = Pose::new(Vec3::new(1, 2, 3), Quat::identity(), Vec3::new(1, 0, 1));
+        assert!(flat.inverse_matrix().is_none());
+
+        let pose = Pose::new(Vec3::new(1, 2, 3), Quat::from_axis_angle(Vec3::new(1, 0, 0), 30.0), Vec3::new(2, 1, 3));
+        assert_identity(&(pose.matrix() * pose.inverse_matrix().unwrap()));
+
+        // Shrinking away to nothing: hits at full size, none once flat
+        let full = Pose::new(Vec3::new(0, 0, 0), Quat::identity(), Vec3::new(1, 1, 1));
+        let gone = Pose::new(Vec3::new(0, 0, 0), Quat::identity(), Vec3::new(0, 0, 0));
+        let animated = AnimatedInstance::new(sphere_at(Point3::new_empty()), full, gone, 0.0, 1.0);
+
+        let mut rec = HitRecord::default();
+        let ray = |time: f64| Ray::new_with_time(Point3::new(0, 0, -5), Vec3::new(0, 0, 1), time);
+        assert!(animated.hit(&ray(0.0), 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+        assert!(!animated.hit(&ray(1.0), 0.001, f64::INFINITY, &mut rec, &mut IndependentSampler));
+    }
+}
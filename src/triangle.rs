@@ -1,40 +1,85 @@
 use std::borrow::Borrow;
 use crate::{HitRecord, Hittable, Materials, Point3, Ray, Vec3};
 use crate::aabb::AABB;
+use crate::texture::TexCoord;
+use crate::sampler::Sampler;
 
 pub struct Triangle {
     v1: Point3,
     v2: Point3,
     v3: Point3,
     n: Vec3,
+    area2: f64,
+    // Per-vertex texture coordinates. Without them (u, v) are barycentric.
+    uvs: Option<[(f64, f64); 3]>,
+    // Per-vertex normals for smooth shading. Without them the face is flat.
+    normals: Option<[Vec3; 3]>,
     material: Materials
 }
 
 impl Triangle {
     pub fn new_with(v1: Point3, v2: Point3, v3: Point3, material: Materials) -> Triangle {
+        let cross = (v2 - v1).cross(&(v3 - v1));
+
         Triangle {
             v1, v2, v3, material,
-            n: (v2 - v1).cross(&(v3 - v1)).normalized()
+            n: cross.normalized(),
+            area2: cross.length(),
+            uvs: None,
+            normals: None
+        }
+    }
+
+    pub fn new_with_uvs(v1: Point3, v2: Point3, v3: Point3, uvs: [(f64, f64); 3], material: Materials) -> Triangle {
+        let mut triangle = Triangle::new_with(v1, v2, v3, material);
+        triangle.uvs = Some(uvs);
+        triangle
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    // Interpolated texture coordinates and their position derivatives at the
+    // barycentric coordinates (b2, b3) of the second and third vertex
+    fn texture_coords(&self, uvs: &[(f64, f64); 3], b2: f64, b3: f64) -> (f64, f64, Vec3, Vec3) {
+        let b1 = 1.0 - b2 - b3;
+        let u = b1 * uvs[0].0 + b2 * uvs[1].0 + b3 * uvs[2].0;
+        let v = b1 * uvs[0].1 + b2 * uvs[1].1 + b3 * uvs[2].1;
+
+        let duv13 = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+        let duv23 = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+        let dp13 = self.v1 - self.v3;
+        let dp23 = self.v2 - self.v3;
+
+        let det = duv13.0 * duv23.1 - duv13.1 * duv23.0;
+        if det.abs() < 1e-12 {
+            // Degenerate mapping, fall back to the edges
+            return (u, v, self.v2 - self.v1, self.v3 - self.v1);
         }
+
+        let dpdu = (dp13 * duv23.1 - dp23 * duv13.1) / det;
+        let dpdv = (dp23 * duv13.0 - dp13 * duv23.0) / det;
+
+        (u, v, dpdu, dpdv)
     }
 }
 
 impl Hittable for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> bool {
         if ray.dir().dot(&self.n) > 0.0 {
             return false;
         }
 
-        let area2 = self.n.length();
-
-        let n_dot_ray_dir = self.n.dot(&ray.dir());
+        let n_dot_ray_dir = self.n.dot(ray.dir());
         if n_dot_ray_dir.abs() < f64::EPSILON {
             return false;
         }
 
         let d = (-self.n).dot(&self.v1);
 
-        let t = -(self.n.dot(&ray.origin()) + d) / n_dot_ray_dir;
+        let t = -(self.n.dot(ray.origin()) + d) / n_dot_ray_dir;
         if t < t_min || t > t_max {
             return false;
         }
@@ -44,7 +89,8 @@ impl Hittable for Triangle {
         let edge0 = self.v2 - self.v1;
         let vp0 = p - self.v1;
         let c = edge0.cross(&vp0);
-        if self.n.dot(&c) < 0.0 {
+        let w3 = self.n.dot(&c);
+        if w3 < 0.0 {
             return false;
         }
 
@@ -58,13 +104,39 @@ impl Hittable for Triangle {
         let edge2 = self.v1 - self.v3;
         let vp2 = p - self.v3;
         let c = edge2.cross(&vp2);
-        if self.n.dot(&c) < 0.0 {
+        let w2 = self.n.dot(&c);
+        if w2 < 0.0 {
             return false;
         }
 
+        // Barycentric coordinates of the hit, so per-vertex attributes can be interpolated
+        let b2 = w2 / self.area2;
+        let b3 = w3 / self.area2;
+        let (u, v, dpdu, dpdv) = match &self.uvs {
+            Some(uvs) => self.texture_coords(uvs, b2, b3),
+            None => (b2, b3, self.v2 - self.v1, self.v3 - self.v1)
+        };
+
+        if !self.material.is_opaque_at(&TexCoord { u, v, p, normal: self.n, footprint: 0.0 }, sampler) {
+            return false;
+        }
+
+        rec.u = u;
+        rec.v = v;
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
         rec.t = t;
         rec.set_face_normal(ray, &self.n);
         rec.p = ray.at(rec.t);
+
+        // Interpolated vertex normal, kept on the side the ray arrived from
+        if let Some(normals) = &self.normals {
+            let shading = normals[0] * (1.0 - b2 - b3) + normals[1] * b2 + normals[2] * b3;
+            if !shading.near_zero() {
+                let shading = shading.normalized();
+                rec.normal = if shading.dot(&rec.normal) < 0.0 { -shading } else { shading };
+            }
+        }
         rec.material = self.material.clone();
         true
     }
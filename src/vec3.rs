@@ -1,5 +1,4 @@
 use std::ops::Neg;
-use rand;
 use rand::Rng;
 
 #[derive(Debug, Copy, Clone)]
@@ -65,11 +64,6 @@ impl Vec3 {
       self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
    }
 
-   #[inline(always)]
-   pub fn normalize(&mut self) {
-      *self = *self / self.length();
-   }
-
    #[inline(always)]
    pub fn normalized(&self) -> Vec3 {
       *self / self.length()
@@ -95,32 +89,9 @@ impl Vec3 {
       )
    }
 
-   pub fn random_in_unit_sphere() -> Vec3 {
-      loop {
-         let p = Vec3::random_range(-1.0, 1.0);
-         if p.length_squared() >= 1.0 {
-            continue
-         }
-         return p;
-      }
-   }
-
-   pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
-      let in_sphere = Vec3::random_in_unit_sphere();
-      return if in_sphere.dot(normal) > 0.0 {
-         in_sphere
-      } else {
-         in_sphere * -1.0
-      }
-   }
-
-   pub fn random_unit_vector() -> Vec3 {
-      Vec3::random_in_unit_sphere().normalized()
-   }
-
    pub fn near_zero(&self) -> bool {
       let s = 1e-8;
-      return (self.e[0].abs() < s) & (self.e[1].abs() < s) & (self.e[2].abs() < s);
+      (self.e[0].abs() < s) & (self.e[1].abs() < s) & (self.e[2].abs() < s)
    }
 
    #[inline(always)]
@@ -133,18 +104,39 @@ impl Vec3 {
       let cos_theta = 1.0_f64.min(n.dot(&negated));
       let r_out_perp = (*uv + *n * cos_theta) * etai_over_etat;
       let r_out_parallel = *n * (-((1.0 - r_out_perp.length_squared()).abs().sqrt()));
-      return r_out_perp + r_out_parallel;
+      r_out_perp + r_out_parallel
+   }
+
+   // Random directions and points built from uniform numbers in [0, 1) from a
+   // sampler, so stratification carries over to the result
+
+   pub fn unit_vector_from(u: (f64, f64)) -> Vec3 {
+      let z = 1.0 - 2.0 * u.0;
+      let r = (1.0 - z * z).max(0.0).sqrt();
+      let phi = 2.0 * std::f64::consts::PI * u.1;
+
+      Vec3::new(r * phi.cos(), r * phi.sin(), z)
    }
 
-   pub fn random_in_unit_disk() -> Vec3 {
-      loop {
-         let mut p = Vec3::random_range(-1.0, 1.0);
-         p.e[2] = 0.0;
-         if p.length_squared()  >= 1.0 {
-            continue;
-         }
-         return p;
+   pub fn in_unit_sphere_from(u: (f64, f64), w: f64) -> Vec3 {
+      Vec3::unit_vector_from(u) * w.cbrt()
+   }
+
+   // Concentric mapping of the square onto the disk, which keeps strata compact
+   pub fn in_unit_disk_from(u: (f64, f64)) -> Vec3 {
+      let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+      if a == 0.0 && b == 0.0 {
+         return Vec3::new_empty();
       }
+
+      let quarter_pi = std::f64::consts::FRAC_PI_4;
+      let (r, theta) = if a.abs() > b.abs() {
+         (a, quarter_pi * (b / a))
+      } else {
+         (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+      };
+
+      Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
    }
 }
 
@@ -287,6 +279,6 @@ impl Neg for Vec3 {
    type Output = Vec3;
 
    fn neg(self) -> Self::Output {
-      return self * -1.0;
+      self * -1.0
    }
 }
\ No newline at end of file
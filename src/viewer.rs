@@ -0,0 +1,432 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use glium::*;
+use glium::glutin::dpi::{PhysicalPosition, PhysicalSize};
+use glium::glutin::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+use glium::texture::RawImage2d;
+use image::RgbaImage;
+use crate::{Point3, Vec3};
+use crate::camera::{PerspectiveCamera, Shutter};
+use crate::camera_path::CameraKey;
+use crate::film::Film;
+use crate::framebuffer::FrameBuffer;
+use crate::raytrace::{render_samples, save_extras, RTParams};
+use crate::scene::Scene;
+
+#[derive(Copy, Clone)]
+struct TexVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+}
+
+implement_vertex!(TexVertex, position, tex_coords);
+
+// Largest window the viewer opens, bigger images are shown scaled down
+const MAX_WINDOW_WIDTH: u32 = 1280;
+// While the camera moves, and for a moment after, frames are traced with one
+// sample at this fraction of the resolution
+const PREVIEW_DOWNSCALE: u32 = 4;
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+// Radians per pixel the mouse is dragged when orbiting
+const ORBIT_SPEED: f64 = 0.005;
+// Pivot distances per second when flying, times four with shift held
+const FLY_SPEED: f64 = 0.5;
+
+// The camera the viewer navigates: it looks at `pivot` from `distance` away,
+// in the direction given by `yaw` and `pitch` in radians. Orbiting turns
+// around the pivot, panning and flying move the pivot along.
+#[derive(Debug, Copy, Clone)]
+struct ViewCamera {
+    pivot: Point3,
+    distance: f64,
+    yaw: f64,
+    pitch: f64,
+    fov: f64,
+    time: f64
+}
+
+impl ViewCamera {
+    fn new_from(key: &CameraKey) -> ViewCamera {
+        let offset = key.look_from - key.look_at;
+        let distance = offset.length().max(1e-3);
+
+        ViewCamera {
+            pivot: key.look_at,
+            distance,
+            yaw: offset.x().atan2(offset.z()),
+            pitch: (offset.y() / distance).clamp(-1.0, 1.0).asin(),
+            fov: key.fov,
+            time: key.time
+        }
+    }
+
+    fn look_from(&self) -> Point3 {
+        self.pivot + Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos()) * self.distance
+    }
+
+    // Forward, right and up vectors of the view
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let forward = (self.pivot - self.look_from()).normalized();
+        let right = forward.cross(&Vec3::new(0, 1, 0)).normalized();
+        let up = right.cross(&forward);
+
+        (forward, right, up)
+    }
+
+    fn orbit(&mut self, dx: f64, dy: f64) {
+        self.yaw -= dx * ORBIT_SPEED;
+        // Stop short of the poles, where the up vector flips
+        self.pitch = (self.pitch + dy * ORBIT_SPEED).clamp(-1.5, 1.5);
+    }
+
+    // Moves the view by a drag of (dx, dy) pixels in an image `height` pixels
+    // tall, so whatever is at the pivot follows the mouse
+    fn pan(&mut self, dx: f64, dy: f64, height: u32) {
+        let (_, right, up) = self.basis();
+        let scale = 2.0 * (self.fov.to_radians() / 2.0).tan() * self.distance / height.max(1) as f64;
+        self.pivot += (-right * dx + up * dy) * scale;
+    }
+
+    // Moves towards the pivot, by ten percent of the way per step
+    fn zoom(&mut self, steps: f64) {
+        self.distance = (self.distance * 0.9f64.powf(steps)).max(1e-3);
+    }
+
+    fn fly(&mut self, forward: f64, right: f64, up: f64) {
+        let (f, r, _) = self.basis();
+        self.pivot += (f * forward + r * right + Vec3::new(0, 1, 0) * up) * self.distance;
+    }
+
+    // A pinhole camera: depth of field focused once would blur whatever the
+    // view is moved to
+    fn camera(&self, aspect_ratio: f64) -> Shutter<PerspectiveCamera> {
+        let camera = PerspectiveCamera::new(
+            self.look_from(),
+            self.pivot,
+            Vec3::new(0, 1, 0),
+            self.fov,
+            aspect_ratio,
+            0.0,
+            self.distance);
+
+        Shutter::new_instant(camera, self.time)
+    }
+}
+
+// Camera as last set by the window. `version` goes up with every change, so
+// the renderer knows to start over.
+struct ViewState {
+    camera: ViewCamera,
+    version: u64,
+    changed: Instant
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Progress {
+    samples_per_pixel: u32,
+    elapsed: Duration,
+    // Camera rays traced per second
+    rays_per_second: f64,
+    preview: bool
+}
+
+impl Progress {
+    fn title(&self) -> String {
+        if self.preview {
+            return "rtiaw-rs - preview".to_string();
+        }
+
+        format!("rtiaw-rs - {} spp - {:.1}s - {:.2} Mrays/s",
+                self.samples_per_pixel, self.elapsed.as_secs_f64(), self.rays_per_second / 1e6)
+    }
+}
+
+// State shared between the window and the render thread
+struct Shared {
+    view: Mutex<ViewState>,
+    image: Mutex<RgbaImage>,
+    progress: Mutex<Progress>
+}
+
+// Renders the scene `build_scene` makes in a window that can be navigated:
+// drag with the left mouse button to orbit, with the right or middle one to
+// pan, scroll to zoom and fly with WASD, Q and E, faster with shift. The view
+// is rendered through a pinhole camera, so the scene's lens and depth of field
+// are not shown. The image refines one sample per pixel at a time, carrying
+// adaptive sampling across passes, and starts over when the camera moves.
+// Once every pixel has had its samples it is saved as output.png. Only
+// returns by exiting the process.
+pub fn run_viewer<F>(params: RTParams, build_scene: F) -> !
+    where F: FnOnce(&Film) -> Scene + Send + 'static {
+    let film = params.film();
+    let (width, height) = film.output_size();
+
+    // The scene is not Send, so it is built on the render thread, which hands
+    // back the view it starts from
+    let (camera_sender, camera_receiver) = std::sync::mpsc::channel();
+    let shared = Arc::new(Shared {
+        view: Mutex::new(ViewState {
+            camera: ViewCamera::new_from(&CameraKey::new_with(0.0, Point3::new(0, 0, 5), Point3::new_empty(), 60.0, 5.0)),
+            version: 0,
+            changed: Instant::now()
+        }),
+        image: Mutex::new(RgbaImage::new(width, height)),
+        progress: Mutex::new(Progress {
+            samples_per_pixel: 0,
+            elapsed: Duration::ZERO,
+            rays_per_second: 0.0,
+            preview: false
+        })
+    });
+
+    let render_shared = shared.clone();
+    std::thread::spawn(move || {
+        let scene = build_scene(&film);
+        if let Some(key) = scene.camera.view() {
+            render_shared.view.lock().unwrap().camera = ViewCamera::new_from(&key);
+        } else {
+            println!("The scene camera cannot be navigated, starting from a default view");
+        }
+        camera_sender.send(()).unwrap();
+        render_loop(params, scene, &render_shared);
+    });
+    camera_receiver.recv().unwrap();
+
+    println!("Drag to orbit, right drag to pan, scroll to zoom, WASD, Q and E to fly. \
+              The viewer shows a pinhole camera in place of the scene's lens.");
+
+    // Initialize window and opengl context
+    let scale = (MAX_WINDOW_WIDTH as f64 / width as f64).min(1.0);
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let wb = glutin::window::WindowBuilder::new()
+        .with_title("rtiaw-rs")
+        .with_inner_size(PhysicalSize {
+            width: (width as f64 * scale) as u32,
+            height: (height as f64 * scale) as u32
+        });
+    let cb = glutin::ContextBuilder::new();
+    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+
+    // Create square shape to pass to GPU
+    let shape = vec![
+        TexVertex { position: [-1.0, 1.0], tex_coords: [0.0, 1.0] },
+        TexVertex { position: [1.0, 1.0], tex_coords: [1.0, 1.0] },
+        TexVertex { position: [-1.0, -1.0], tex_coords: [0.0, 0.0] },
+        TexVertex { position: [1.0, -1.0], tex_coords: [1.0, 0.0] }
+    ];
+
+    // Create vertex buffer for shape
+    let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
+
+    // Create index buffer
+    let indices: [u16; 6] = [0, 1, 2, 1, 2, 3];
+    let index_buffer = glium::IndexBuffer::new(&display,
+                                               glium::index::PrimitiveType::TrianglesList,
+                                               &indices).unwrap();
+
+    // Compile the shader program
+    let vertex_shader_src = include_str!("shaders/vert.vs");
+    let fragment_shader_src = include_str!("shaders/frag.fs");
+    let program = glium::Program::from_source(&display,
+                                              vertex_shader_src,
+                                              fragment_shader_src,
+                                              None).unwrap();
+
+    let mut keys: HashSet<VirtualKeyCode> = HashSet::new();
+    let mut dragging: Option<MouseButton> = None;
+    let mut cursor: Option<PhysicalPosition<f64>> = None;
+    let mut window_height = (height as f64 * scale) as u32;
+    let mut last_frame = Instant::now();
+    let mut title = String::new();
+
+    event_loop.run(move |ev, _, control_flow| {
+        let camera = || shared.view.lock().unwrap().camera;
+
+        match ev {
+            glutin::event::Event::WindowEvent { event, .. } => match event {
+                glutin::event::WindowEvent::CloseRequested => {
+                    *control_flow = glutin::event_loop::ControlFlow::Exit;
+                    return;
+                }
+                glutin::event::WindowEvent::KeyboardInput { input, .. } => {
+                    let key = match input.virtual_keycode {
+                        Some(key) => key,
+                        None => return
+                    };
+
+                    if key == VirtualKeyCode::Escape {
+                        *control_flow = glutin::event_loop::ControlFlow::Exit;
+                        return;
+                    }
+
+                    match input.state {
+                        ElementState::Pressed => keys.insert(key),
+                        ElementState::Released => keys.remove(&key)
+                    };
+                    return;
+                }
+                glutin::event::WindowEvent::MouseInput { state, button, .. } => {
+                    dragging = match state {
+                        ElementState::Pressed => Some(button),
+                        ElementState::Released => None
+                    };
+                    return;
+                }
+                glutin::event::WindowEvent::CursorMoved { position, .. } => {
+                    if let (Some(button), Some(last)) = (dragging, cursor) {
+                        let (dx, dy) = (position.x - last.x, position.y - last.y);
+                        let mut view = camera();
+                        match button {
+                            MouseButton::Left => view.orbit(dx, dy),
+                            MouseButton::Right | MouseButton::Middle => view.pan(dx, dy, window_height),
+                            _ => ()
+                        }
+                        update_view(&shared, view);
+                    }
+                    cursor = Some(position);
+                    return;
+                }
+                glutin::event::WindowEvent::MouseWheel { delta, .. } => {
+                    let steps = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y as f64,
+                        MouseScrollDelta::PixelDelta(p) => p.y / 50.0
+                    };
+                    let mut view = camera();
+                    view.zoom(steps);
+                    update_view(&shared, view);
+                    return;
+                }
+                glutin::event::WindowEvent::Resized(size) => {
+                    window_height = size.height;
+                    return;
+                }
+                _ => return,
+            },
+            glutin::event::Event::NewEvents(cause) => match cause {
+                glutin::event::StartCause::ResumeTimeReached { .. } => (),
+                glutin::event::StartCause::Init => (),
+                _ => return,
+            },
+            _ => return,
+        }
+
+        // Fly with the keys held down, at a speed independent of the frame rate
+        let dt = last_frame.elapsed().as_secs_f64();
+        last_frame = Instant::now();
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| -> f64 {
+            keys.contains(&positive) as i32 as f64 - keys.contains(&negative) as i32 as f64
+        };
+        let (forward, right, up) = (
+            axis(VirtualKeyCode::W, VirtualKeyCode::S),
+            axis(VirtualKeyCode::D, VirtualKeyCode::A),
+            axis(VirtualKeyCode::E, VirtualKeyCode::Q));
+        if forward != 0.0 || right != 0.0 || up != 0.0 {
+            let speed = FLY_SPEED * dt * if keys.contains(&VirtualKeyCode::LShift) { 4.0 } else { 1.0 };
+            let mut view = camera();
+            view.fly(forward * speed, right * speed, up * speed);
+            update_view(&shared, view);
+        }
+
+        let progress_title = shared.progress.lock().unwrap().title();
+        if progress_title != title {
+            display.gl_window().window().set_title(&progress_title);
+            title = progress_title;
+        }
+
+        let image = shared.image.lock().unwrap().clone();
+        let dimensions = image.dimensions();
+        let gpu_image = RawImage2d::from_raw_rgba_reversed(&image.into_raw(), dimensions);
+        let texture = glium::texture::SrgbTexture2d::new(&display, gpu_image).unwrap();
+
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 1.0, 1.0);
+        target.draw(&vertex_buffer, &index_buffer, &program,
+                    &uniform! { tex: &texture },
+                    &Default::default()).unwrap();
+        target.finish().unwrap();
+
+        let next_frame_time = Instant::now() + Duration::from_millis(16);
+        *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
+    });
+}
+
+fn update_view(shared: &Shared, camera: ViewCamera) {
+    let mut view = shared.view.lock().unwrap();
+    view.camera = camera;
+    view.version += 1;
+    view.changed = Instant::now();
+}
+
+// Traces the view forever: low resolution previews while the camera moves,
+// then one sample per pixel at a time into a buffer that is thrown away when
+// the camera changes again
+fn render_loop(params: RTParams, mut scene: Scene, shared: &Shared) {
+    let film = params.film();
+    let preview_params = params.clone().with_film(film.downscaled(PREVIEW_DOWNSCALE));
+    let (width, height) = film.output_size();
+
+    let mut version = None;
+    let mut buffer: Option<FrameBuffer> = None;
+    let mut pass = 0;
+    let mut start = Instant::now();
+
+    loop {
+        let (camera, current, moving) = {
+            let view = shared.view.lock().unwrap();
+            (view.camera, view.version, view.changed.elapsed() < SETTLE_TIME)
+        };
+
+        if version != Some(current) {
+            version = Some(current);
+            scene.camera = Box::new(camera.camera(film.aspect_ratio()));
+            buffer = None;
+            pass = 0;
+            start = Instant::now();
+        }
+
+        if moving {
+            let image = render_samples(&preview_params, &scene, 0..1, None).to_image();
+            *shared.image.lock().unwrap() = image;
+            shared.progress.lock().unwrap().preview = true;
+            continue;
+        }
+
+        if pass >= params.max_samples() {
+            std::thread::sleep(Duration::from_millis(16));
+            continue;
+        }
+
+        // Pixels adaptive sampling has found converged take no more samples
+        let pass_buffer = render_samples(&params, &scene, pass..pass + 1, buffer.as_ref());
+        match &mut buffer {
+            Some(buffer) => buffer.merge(&pass_buffer),
+            None => buffer = Some(pass_buffer)
+        }
+        pass += 1;
+
+        let buffer = buffer.as_ref().unwrap();
+        *shared.image.lock().unwrap() = buffer.to_image();
+
+        let elapsed = start.elapsed();
+        *shared.progress.lock().unwrap() = Progress {
+            samples_per_pixel: pass,
+            elapsed,
+            rays_per_second: (width * height) as f64 * pass as f64 / elapsed.as_secs_f64().max(1e-3),
+            preview: false
+        };
+
+        if pass == params.max_samples() {
+            println!("Finished {} spp in {:.1}s", pass, elapsed.as_secs_f64());
+            let path = Path::new("output.png");
+            if let Err(e) = buffer.to_image().save(path) {
+                println!("Error saving image: {}", e);
+            }
+            save_extras(&params, buffer, path);
+        }
+    }
+}
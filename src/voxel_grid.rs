@@ -0,0 +1,501 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crate::{Point3, Ray, Vec3};
+use crate::aabb::AABB;
+
+#[derive(Debug)]
+pub enum VolumeError {
+    Io(std::io::Error),
+    Format(String)
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::Io(e) => write!(f, "I/O error reading volume: {}", e),
+            VolumeError::Format(msg) => write!(f, "Invalid volume data: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+impl From<std::io::Error> for VolumeError {
+    fn from(e: std::io::Error) -> Self {
+        VolumeError::Io(e)
+    }
+}
+
+// Largest number of leaf positions in a sparse grid, which bounds the size of
+// its index, e.g. a 2048^3 grid in leaves of 8
+const MAX_LEAF_POSITIONS: usize = 1 << 24;
+
+enum GridData {
+    Dense(Vec<f32>),
+    // Only blocks of `leaf_size`^3 voxels that contain data are stored. `index`
+    // maps every block position to its offset in `values`, or u32::MAX if empty.
+    Sparse {
+        leaf_size: usize,
+        leaf_dims: [usize; 3],
+        index: Vec<u32>,
+        values: Vec<f32>,
+        background: f32
+    }
+}
+
+// Density samples on a regular grid spanning `bounds`. Sample (i, j, k) sits at
+// the centre of its voxel and values are stored with x varying fastest.
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    bounds: AABB,
+    data: GridData
+}
+
+impl VoxelGrid {
+    pub fn new_dense(dims: [usize; 3], bounds: AABB, data: Vec<f32>) -> Result<VoxelGrid, VolumeError> {
+        if dims.contains(&0) {
+            return Err(VolumeError::Format(String::from("grid dimensions must be non-zero")));
+        }
+
+        let expected = dims[0] * dims[1] * dims[2];
+        if data.len() != expected {
+            return Err(VolumeError::Format(format!("expected {} voxels for a {}x{}x{} grid but got {}",
+                                                   expected, dims[0], dims[1], dims[2], data.len())));
+        }
+
+        Ok(VoxelGrid { dims, bounds, data: GridData::Dense(data) })
+    }
+
+    // Headerless file of little endian f32 densities, x varying fastest
+    pub fn load_raw<P: AsRef<Path>>(path: P, dims: [usize; 3], bounds: AABB) -> Result<VoxelGrid, VolumeError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        VoxelGrid::from_raw(&bytes, dims, bounds)
+    }
+
+    fn from_raw(bytes: &[u8], dims: [usize; 3], bounds: AABB) -> Result<VoxelGrid, VolumeError> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(VolumeError::Format(String::from("raw grid size is not a multiple of 4 bytes")));
+        }
+
+        let data = bytes.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        VoxelGrid::new_dense(dims, bounds, data)
+    }
+
+    // Sparse grid file, all values little endian:
+    //   "SVOX", version: u32 = 1, dims: 3 x u32, leaf size: u32,
+    //   bounds min: 3 x f32, bounds max: 3 x f32, background: f32, leaf count: u32,
+    //   then per leaf its origin in voxels (3 x u32, multiples of the leaf size)
+    //   followed by leaf size^3 f32 values, x varying fastest
+    pub fn load_sparse<P: AsRef<Path>>(path: P) -> Result<VoxelGrid, VolumeError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        VoxelGrid::from_sparse(&bytes)
+    }
+
+    fn from_sparse(bytes: &[u8]) -> Result<VoxelGrid, VolumeError> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+
+        if reader.take(4)? != b"SVOX" {
+            return Err(VolumeError::Format(String::from("missing SVOX magic number")));
+        }
+
+        let version = reader.u32()?;
+        if version != 1 {
+            return Err(VolumeError::Format(format!("unsupported sparse grid version {}", version)));
+        }
+
+        let dims = [reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize];
+        let leaf_size = reader.u32()? as usize;
+        if dims.contains(&0) || leaf_size == 0 {
+            return Err(VolumeError::Format(String::from("grid dimensions and leaf size must be non-zero")));
+        }
+
+        let min = Point3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+        let max = Point3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+        let background = reader.f32()?;
+        let leaf_count = reader.u32()? as usize;
+
+        let leaf_dims = [
+            dims[0].div_ceil(leaf_size),
+            dims[1].div_ceil(leaf_size),
+            dims[2].div_ceil(leaf_size)
+        ];
+
+        // Sizes come straight from the header, so check them before allocating
+        let leaf_positions = leaf_dims[0].checked_mul(leaf_dims[1])
+            .and_then(|n| n.checked_mul(leaf_dims[2]))
+            .filter(|&n| n <= MAX_LEAF_POSITIONS)
+            .ok_or_else(|| VolumeError::Format(format!("a {}x{}x{} grid in leaves of {} has too many leaves",
+                                                       dims[0], dims[1], dims[2], leaf_size)))?;
+        let leaf_voxels = leaf_size.checked_pow(3)
+            .ok_or_else(|| VolumeError::Format(format!("leaf size {} is too large", leaf_size)))?;
+        let leaf_bytes = leaf_voxels.checked_mul(4).and_then(|n| n.checked_add(12));
+        if leaf_bytes.and_then(|n| n.checked_mul(leaf_count)).is_none_or(|n| n > reader.remaining()) {
+            return Err(VolumeError::Format(format!("file is too short for {} leaves of size {}", leaf_count, leaf_size)));
+        }
+
+        let mut index = vec![u32::MAX; leaf_positions];
+        let mut values = Vec::with_capacity(leaf_count * leaf_voxels);
+
+        for _ in 0..leaf_count {
+            let origin = [reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize];
+            if origin.iter().any(|&o| o % leaf_size != 0) {
+                return Err(VolumeError::Format(format!("leaf origin {:?} is not aligned to the leaf size", origin)));
+            }
+
+            let leaf = [origin[0] / leaf_size, origin[1] / leaf_size, origin[2] / leaf_size];
+            if (0..3).any(|a| leaf[a] >= leaf_dims[a]) {
+                return Err(VolumeError::Format(format!("leaf origin {:?} is outside the grid", origin)));
+            }
+
+            index[(leaf[2] * leaf_dims[1] + leaf[1]) * leaf_dims[0] + leaf[0]] = (values.len() / leaf_voxels) as u32;
+            for _ in 0..leaf_voxels {
+                values.push(reader.f32()?);
+            }
+        }
+
+        Ok(VoxelGrid {
+            dims,
+            bounds: AABB::new(&min, &max),
+            data: GridData::Sparse { leaf_size, leaf_dims, index, values, background }
+        })
+    }
+
+    #[inline(always)]
+    pub fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    fn voxel(&self, i: usize, j: usize, k: usize) -> f32 {
+        match &self.data {
+            GridData::Dense(data) => data[(k * self.dims[1] + j) * self.dims[0] + i],
+            GridData::Sparse { leaf_size, leaf_dims, index, values, background } => {
+                let leaf = index[((k / leaf_size) * leaf_dims[1] + j / leaf_size) * leaf_dims[0] + i / leaf_size];
+                if leaf == u32::MAX {
+                    return *background;
+                }
+
+                let (li, lj, lk) = (i % leaf_size, j % leaf_size, k % leaf_size);
+                values[leaf as usize * leaf_size * leaf_size * leaf_size + (lk * leaf_size + lj) * leaf_size + li]
+            }
+        }
+    }
+
+    // Trilinearly interpolated density, zero outside the grid
+    pub fn density(&self, p: &Point3) -> f64 {
+        let min = self.bounds.min();
+        let size = self.bounds.max() - min;
+
+        let mut base = [0usize; 3];
+        let mut frac = [0.0f64; 3];
+        let mut next = [0usize; 3];
+
+        for a in 0..3 {
+            let g = (p.e[a] - min.e[a]) / size.e[a];
+            if !(0.0..=1.0).contains(&g) {
+                return 0.0;
+            }
+
+            // Continuous voxel coordinate, with sample centres at integer + 0.5
+            let x = (g * self.dims[a] as f64 - 0.5).max(0.0);
+            let i = (x as usize).min(self.dims[a] - 1);
+            base[a] = i;
+            next[a] = (i + 1).min(self.dims[a] - 1);
+            frac[a] = (x - i as f64).min(1.0);
+        }
+
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let (i, wx) = if corner & 1 == 0 { (base[0], 1.0 - frac[0]) } else { (next[0], frac[0]) };
+            let (j, wy) = if corner & 2 == 0 { (base[1], 1.0 - frac[1]) } else { (next[1], frac[1]) };
+            let (k, wz) = if corner & 4 == 0 { (base[2], 1.0 - frac[2]) } else { (next[2], frac[2]) };
+            result += wx * wy * wz * self.voxel(i, j, k) as f64;
+        }
+
+        result
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VolumeError> {
+        if self.pos + n > self.bytes.len() {
+            return Err(VolumeError::Format(String::from("unexpected end of file")));
+        }
+
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, VolumeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, VolumeError> {
+        let b = self.take(4)?;
+        Ok(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+// Coarse grid holding the largest density found in each block of voxels. Tracking
+// uses it as a local bound, so thin regions do not pay for the densest part.
+pub struct MajorantGrid {
+    res: [usize; 3],
+    bounds: AABB,
+    max: Vec<f64>
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &VoxelGrid, block_size: usize) -> MajorantGrid {
+        let res = [
+            grid.dims[0].div_ceil(block_size),
+            grid.dims[1].div_ceil(block_size),
+            grid.dims[2].div_ceil(block_size)
+        ];
+
+        // Cells split the bounds evenly, so when a dimension is not a multiple
+        // of `block_size` a cell spans a little more than `block_size` voxels.
+        // Its majorant covers every voxel that interpolation inside it reads.
+        let covered = |c: usize, a: usize| {
+            let voxels_per_cell = grid.dims[a] as f64 / res[a] as f64;
+            let lo = (c as f64 * voxels_per_cell - 0.5).floor().max(0.0) as usize;
+            let hi = ((c + 1) as f64 * voxels_per_cell - 0.5).floor().max(0.0) as usize + 2;
+            lo..hi.min(grid.dims[a])
+        };
+
+        let mut max = vec![0.0; res[0] * res[1] * res[2]];
+        for z in 0..res[2] {
+            for y in 0..res[1] {
+                for x in 0..res[0] {
+                    let mut block_max = 0.0f32;
+                    for k in covered(z, 2) {
+                        for j in covered(y, 1) {
+                            for i in covered(x, 0) {
+                                block_max = block_max.max(grid.voxel(i, j, k));
+                            }
+                        }
+                    }
+
+                    max[(z * res[1] + y) * res[0] + x] = block_max as f64;
+                }
+            }
+        }
+
+        MajorantGrid { res, bounds: grid.bounds, max }
+    }
+
+    // Walks the cells pierced by `ray` between `t_min` and `t_max`, calling
+    // `segment(t0, t1, majorant)` for each one until it returns false
+    pub fn traverse<F: FnMut(f64, f64, f64) -> bool>(&self, ray: &Ray, t_min: f64, t_max: f64, mut segment: F) {
+        let min = self.bounds.min();
+        let max = self.bounds.max();
+
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for a in 0..3 {
+            let mut ta = (min.e[a] - ray.origin().e[a]) * ray.inv_dir().e[a];
+            let mut tb = (max.e[a] - ray.origin().e[a]) * ray.inv_dir().e[a];
+            if ta > tb {
+                std::mem::swap(&mut ta, &mut tb);
+            }
+
+            t0 = t0.max(ta);
+            t1 = t1.min(tb);
+        }
+
+        // Also bails out when either end is NaN
+        if t0.partial_cmp(&t1) != Some(std::cmp::Ordering::Less) {
+            return;
+        }
+
+        let cell_size = (max - min) / Vec3::new(self.res[0] as f64, self.res[1] as f64, self.res[2] as f64);
+        let start = ray.at(t0);
+
+        let mut cell = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+
+        for a in 0..3 {
+            let c = ((start.e[a] - min.e[a]) / cell_size.e[a]).floor() as i64;
+            cell[a] = c.clamp(0, self.res[a] as i64 - 1);
+
+            let dir = ray.dir().e[a];
+            if dir > 0.0 {
+                step[a] = 1;
+                t_next[a] = t0 + (min.e[a] + (cell[a] + 1) as f64 * cell_size.e[a] - start.e[a]) / dir;
+                t_delta[a] = cell_size.e[a] / dir;
+            } else if dir < 0.0 {
+                step[a] = -1;
+                t_next[a] = t0 + (min.e[a] + cell[a] as f64 * cell_size.e[a] - start.e[a]) / dir;
+                t_delta[a] = -cell_size.e[a] / dir;
+            }
+        }
+
+        let mut t = t0;
+        loop {
+            let axis = if t_next[0] < t_next[1] {
+                if t_next[0] < t_next[2] { 0 } else { 2 }
+            } else if t_next[1] < t_next[2] { 1 } else { 2 };
+
+            let t_exit = t_next[axis].min(t1);
+            let index = (cell[2] as usize * self.res[1] + cell[1] as usize) * self.res[0] + cell[0] as usize;
+            if !segment(t, t_exit, self.max[index]) || t_exit >= t1 {
+                return;
+            }
+
+            t = t_exit;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.res[axis] as i64 {
+                return;
+            }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_bounds() -> AABB {
+        AABB::new(&Point3::new_empty(), &Point3::new(1, 1, 1))
+    }
+
+    fn le_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    // SVOX file of a grid with `dims` and a single leaf at `origin`
+    fn sparse_bytes(dims: [u32; 3], leaf_size: u32, origin: [u32; 3], leaf: &[f32]) -> Vec<u8> {
+        let mut bytes = b"SVOX".to_vec();
+        for v in [1, dims[0], dims[1], dims[2], leaf_size] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend(le_bytes(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.25]));
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for v in origin {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend(le_bytes(leaf));
+        bytes
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let values: Vec<f32> = (0..24).map(|i| i as f32 * 0.5).collect();
+        let grid = VoxelGrid::from_raw(&le_bytes(&values), [2, 3, 4], unit_bounds()).unwrap();
+
+        for (n, value) in values.iter().enumerate() {
+            assert_eq!(grid.voxel(n % 2, (n / 2) % 3, n / 6), *value);
+        }
+    }
+
+    #[test]
+    fn raw_rejects_size_mismatch() {
+        let bytes = le_bytes(&[1.0; 8]);
+        assert!(matches!(VoxelGrid::from_raw(&bytes, [2, 2, 3], unit_bounds()), Err(VolumeError::Format(_))));
+        assert!(matches!(VoxelGrid::from_raw(&bytes[..30], [2, 2, 2], unit_bounds()), Err(VolumeError::Format(_))));
+        assert!(matches!(VoxelGrid::from_raw(&bytes, [0, 2, 2], unit_bounds()), Err(VolumeError::Format(_))));
+    }
+
+    #[test]
+    fn sparse_round_trip() {
+        let leaf: Vec<f32> = (0..8).map(|i| i as f32 + 1.0).collect();
+        let grid = VoxelGrid::from_sparse(&sparse_bytes([4, 4, 4], 2, [2, 0, 2], &leaf)).unwrap();
+
+        for (n, value) in leaf.iter().enumerate() {
+            assert_eq!(grid.voxel(2 + n % 2, (n / 2) % 2, 2 + n / 4), *value);
+        }
+        // Voxels of leaves the file leaves out read as the background
+        assert_eq!(grid.voxel(0, 0, 0), 0.25);
+        assert_eq!(grid.voxel(3, 3, 3), 0.25);
+    }
+
+    #[test]
+    fn majorant_bounds_density_when_blocks_do_not_divide_grid() {
+        // 10 voxels in blocks of 8, with density falling along x so the second
+        // cell's densest point is at its left edge, in the first block's voxels
+        let dims = [10, 3, 3];
+        let data = (0..90).map(|n| 10.0 - (n % 10) as f32).collect();
+        let bounds = AABB::new(&Point3::new_empty(), &Point3::new(10, 3, 3));
+        let grid = VoxelGrid::new_dense(dims, bounds, data).unwrap();
+        let majorants = MajorantGrid::new(&grid, 8);
+
+        for ray in [
+            Ray::new(Point3::new(-1, 1.5, 1.5), Vec3::new(1, 0, 0)),
+            Ray::new(Point3::new(11, 0.2, 2.9), Vec3::new(-1, 0.05, -0.1)),
+            Ray::new(Point3::new(-1, -1, -1), Vec3::new(1, 0.4, 0.4))
+        ] {
+            let mut segments = 0;
+            majorants.traverse(&ray, 0.0, f64::INFINITY, |t0, t1, majorant| {
+                segments += 1;
+                for n in 0..=100 {
+                    let t = t0 + (t1 - t0) * n as f64 / 100.0;
+                    let density = grid.density(&ray.at(t));
+                    assert!(density <= majorant + 1e-9, "density {} above majorant {} at t = {}", density, majorant, t);
+                }
+                true
+            });
+            assert!(segments > 0);
+        }
+    }
+
+    #[test]
+    fn sparse_rejects_oversized_headers() {
+        let leaf = [1.0; 8];
+
+        // Far more leaf positions than any real grid
+        let huge = sparse_bytes([u32::MAX, u32::MAX, u32::MAX], 1, [0, 0, 0], &leaf);
+        assert!(matches!(VoxelGrid::from_sparse(&huge), Err(VolumeError::Format(_))));
+
+        // A leaf size whose cube overflows
+        let mut big_leaf = sparse_bytes([4, 4, 4], 2, [0, 0, 0], &leaf);
+        big_leaf[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(VoxelGrid::from_sparse(&big_leaf), Err(VolumeError::Format(_))));
+
+        // More leaves than the file holds
+        let mut many = sparse_bytes([4, 4, 4], 2, [0, 0, 0], &leaf);
+        let count = many.len() - 12 - 32 - 4;
+        many[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(VoxelGrid::from_sparse(&many), Err(VolumeError::Format(_))));
+    }
+
+    #[test]
+    fn sparse_rejects_bad_files() {
+        let leaf = [1.0; 8];
+        let good = sparse_bytes([4, 4, 4], 2, [0, 0, 0], &leaf);
+
+        let mut bad_magic = good.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(VoxelGrid::from_sparse(&bad_magic), Err(VolumeError::Format(_))));
+
+        // Truncated leaf data
+        assert!(matches!(VoxelGrid::from_sparse(&good[..good.len() - 4]), Err(VolumeError::Format(_))));
+
+        let misaligned = sparse_bytes([4, 4, 4], 2, [1, 0, 0], &leaf);
+        assert!(matches!(VoxelGrid::from_sparse(&misaligned), Err(VolumeError::Format(_))));
+
+        let outside = sparse_bytes([4, 4, 4], 2, [4, 0, 0], &leaf);
+        assert!(matches!(VoxelGrid::from_sparse(&outside), Err(VolumeError::Format(_))));
+
+        let empty = sparse_bytes([4, 0, 4], 2, [0, 0, 0], &leaf);
+        assert!(matches!(VoxelGrid::from_sparse(&empty), Err(VolumeError::Format(_))));
+    }
+}